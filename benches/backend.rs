@@ -0,0 +1,46 @@
+use std::time::{Duration, Instant};
+
+use pamada::channel_v2::channel;
+
+// Run once per backend and compare:
+//     cargo bench --bench backend
+//     cargo bench --bench backend --features parking_lot
+const PRODUCERS: usize = 4;
+const PER_PRODUCER: usize = 100000;
+
+fn stress() -> Duration {
+    let (tx, mut rx) = channel::<usize>();
+    let start = Instant::now();
+    let producers: Vec<_> = (0..PRODUCERS)
+        .map(|_| {
+            let tx = tx.clone();
+            std::thread::spawn(move || {
+                for i in 0..PER_PRODUCER {
+                    tx.send(i).unwrap();
+                }
+            })
+        })
+        .collect();
+    drop(tx);
+    let mut received = 0;
+    while rx.recv().is_some() {
+        received += 1;
+    }
+    let elapsed = start.elapsed();
+    producers.into_iter().for_each(|jh| jh.join().unwrap());
+    assert_eq!(received, PRODUCERS * PER_PRODUCER);
+    elapsed
+}
+
+fn main() {
+    let backend = if cfg!(feature = "parking_lot") {
+        "parking_lot"
+    } else {
+        "std"
+    };
+    let elapsed = stress();
+    println!(
+        "{backend}: {} messages from {PRODUCERS} producers in {elapsed:?}",
+        PRODUCERS * PER_PRODUCER
+    );
+}
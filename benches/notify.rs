@@ -0,0 +1,120 @@
+use std::time::{Duration, Instant};
+
+use pamada::channel_v2::{channel, Receiver};
+
+// A send only notifies when the receiver is parked, so sends to a receiver
+// that keeps up (here: one that never runs out of queued messages) skip the
+// condvar entirely, while sends to a parked receiver pay for the wakeup.
+// Within a burst only the first send pays: the receiver still counts as
+// parked until it runs, but the rest land on a non-empty queue.
+//
+// With `--features lock-metrics` each run also reports the condvar notifies
+// per message; with std's condvar on Linux each one is a `futex` syscall.
+const MESSAGES: u32 = 200000;
+
+struct Run {
+    per_message: Duration,
+    notifies: Option<u64>,
+    messages: u32,
+}
+
+impl Run {
+    fn print(&self, label: &str) {
+        print!("{label:<20} {:?} per message", self.per_message);
+        if let Some(notifies) = self.notifies {
+            print!(
+                ", {:.3} notifies per message",
+                notifies as f64 / f64::from(self.messages)
+            );
+        }
+        println!();
+    }
+}
+
+#[cfg(feature = "lock-metrics")]
+fn notifies<T>(rx: &Receiver<T>) -> Option<u64> {
+    Some(rx.condvar_notifies())
+}
+
+#[cfg(not(feature = "lock-metrics"))]
+fn notifies<T>(_: &Receiver<T>) -> Option<u64> {
+    None
+}
+
+fn keeping_up() -> Run {
+    let (tx, mut rx) = channel::<u32>();
+    let start = Instant::now();
+    for i in 0..MESSAGES {
+        tx.send(i).unwrap();
+    }
+    let elapsed = start.elapsed();
+    let notifies = notifies(&rx);
+    drop(tx);
+    while rx.recv().is_some() {}
+    Run {
+        per_message: elapsed / MESSAGES,
+        notifies,
+        messages: MESSAGES,
+    }
+}
+
+fn parked() -> Run {
+    const ROUND_TRIPS: u32 = MESSAGES / 10;
+    let (tx, mut rx) = channel::<u32>();
+    let (ack_tx, mut ack_rx) = channel::<()>();
+    let jh = std::thread::spawn(move || {
+        while rx.recv().is_some() {
+            ack_tx.send(()).unwrap();
+        }
+        notifies(&rx)
+    });
+    let start = Instant::now();
+    for i in 0..ROUND_TRIPS {
+        tx.send(i).unwrap();
+        ack_rx.recv().unwrap();
+    }
+    let elapsed = start.elapsed();
+    drop(tx);
+    // Less the one notify that dropping the sender adds.
+    let notifies = jh.join().unwrap().map(|n| n - 1);
+    Run {
+        per_message: elapsed / ROUND_TRIPS,
+        notifies,
+        messages: ROUND_TRIPS,
+    }
+}
+
+fn burst_to_parked() -> Run {
+    const BURST: u32 = 100;
+    let (tx, mut rx) = channel::<u32>();
+    let (ack_tx, mut ack_rx) = channel::<()>();
+    let jh = std::thread::spawn(move || {
+        while let Some(i) = rx.recv() {
+            if i % BURST == BURST - 1 {
+                ack_tx.send(()).unwrap();
+            }
+        }
+        notifies(&rx)
+    });
+    let start = Instant::now();
+    for i in 0..MESSAGES {
+        tx.send(i).unwrap();
+        if i % BURST == BURST - 1 {
+            ack_rx.recv().unwrap();
+        }
+    }
+    let elapsed = start.elapsed();
+    drop(tx);
+    let notifies = jh.join().unwrap().map(|n| n - 1);
+    Run {
+        per_message: elapsed / MESSAGES,
+        notifies,
+        messages: MESSAGES,
+    }
+}
+
+fn main() {
+    keeping_up().print("receiver keeping up:");
+    parked().print("receiver parked:");
+    burst_to_parked().print("burst to parked:");
+}
@@ -0,0 +1,32 @@
+use std::time::{Duration, Instant};
+
+use pamada::channel_v2::ChannelBuilder;
+
+// Spinning only pays off when the producer runs on another core; on a
+// single-core machine every spin just delays the producer.
+const ROUND_TRIPS: u32 = 20000;
+
+fn round_trip(spin: usize) -> Duration {
+    let (ping_tx, mut ping_rx) = ChannelBuilder::new().spin(spin).build::<u32>();
+    let (pong_tx, mut pong_rx) = ChannelBuilder::new().spin(spin).build::<u32>();
+    let jh = std::thread::spawn(move || {
+        while let Some(v) = ping_rx.recv() {
+            pong_tx.send(v).unwrap();
+        }
+    });
+    let start = Instant::now();
+    for i in 0..ROUND_TRIPS {
+        ping_tx.send(i).unwrap();
+        assert_eq!(pong_rx.recv(), Some(i));
+    }
+    let elapsed = start.elapsed();
+    drop(ping_tx);
+    jh.join().unwrap();
+    elapsed / ROUND_TRIPS
+}
+
+fn main() {
+    for spin in [0, 100, 1000, 10000] {
+        println!("spin {:>5}: {:?} per round trip", spin, round_trip(spin));
+    }
+}
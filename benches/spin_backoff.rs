@@ -0,0 +1,48 @@
+use std::time::{Duration, Instant};
+
+use pamada::channel_v2::{ChannelBuilder, Origin};
+
+// Bursts of back-to-back messages separated by idle gaps: a flat spin long
+// enough to catch the next message of a burst burns its whole count on
+// every gap, where a backoff gives up after a few doubling rounds.
+// As in the `spin` bench, only meaningful with the producer on another core.
+const BURSTS: u32 = 200;
+const BURST_LEN: u32 = 50;
+const GAP: Duration = Duration::from_micros(500);
+
+// Mean latency from send to receive, and how many receives parked.
+fn bursty(builder: ChannelBuilder) -> (Duration, u32) {
+    let (tx, mut rx) = builder.build::<Instant>();
+    let jh = std::thread::spawn(move || {
+        let (mut latency, mut parks, mut n) = (Duration::ZERO, 0, 0);
+        while let Some((sent, origin)) = rx.recv_origin() {
+            latency += sent.elapsed();
+            parks += u32::from(origin == Origin::AfterWait);
+            n += 1;
+        }
+        (latency / n, parks)
+    });
+    for _ in 0..BURSTS {
+        for _ in 0..BURST_LEN {
+            tx.send(Instant::now()).unwrap();
+        }
+        std::thread::sleep(GAP);
+    }
+    drop(tx);
+    jh.join().unwrap()
+}
+
+fn main() {
+    let configs = [
+        ("no spin", ChannelBuilder::new()),
+        ("flat spin 10000", ChannelBuilder::new().spin(10000)),
+        (
+            "backoff 1..4096",
+            ChannelBuilder::new().spin_backoff(1, 4096),
+        ),
+    ];
+    for (name, builder) in configs {
+        let (latency, parks) = bursty(builder);
+        println!("{:<16}: {:?} mean latency, {} parks", name, latency, parks);
+    }
+}
@@ -0,0 +1,192 @@
+//! Drives a channel through arbitrary sends, clones, drops and receives on
+//! one thread, checking it against a plain queue: every message arrives
+//! once, in order, and disconnects are reported exactly when they happen.
+//! Receives only block when the model says they'd return at once.
+
+#![no_main]
+
+use std::collections::VecDeque;
+
+use libfuzzer_sys::{
+    arbitrary::{self, Arbitrary},
+    fuzz_target,
+};
+use pamada::{channel_v1, channel_v2};
+
+#[derive(Arbitrary, Debug)]
+enum Flavor {
+    V1,
+    Unbounded,
+    Bounded(u8),
+}
+
+#[derive(Arbitrary, Debug)]
+enum Op {
+    Send(u8),
+    CloneSender(u8),
+    DropSender(u8),
+    Recv,
+    TryRecv,
+    DropReceiver,
+}
+
+#[derive(Arbitrary, Debug)]
+struct Input {
+    flavor: Flavor,
+    ops: Vec<Op>,
+}
+
+fuzz_target!(|input: Input| match input.flavor {
+    Flavor::V1 => run_v1(input.ops),
+    Flavor::Unbounded => run_v2(input.ops, channel_v2::channel(), None),
+    Flavor::Bounded(capacity) => {
+        // At least one slot, or nothing could ever be sent.
+        let capacity = usize::from(capacity % 8) + 1;
+        run_v2(input.ops, channel_v2::bounded(capacity), Some(capacity));
+    }
+});
+
+// Messages sent and not yet received, oldest first, each one distinct.
+#[derive(Default)]
+struct Model {
+    queue: VecDeque<u64>,
+    next: u64,
+}
+
+impl Model {
+    fn next_value(&mut self) -> u64 {
+        self.next += 1;
+        self.next
+    }
+}
+
+fn pick<T>(items: &[T], index: u8) -> Option<usize> {
+    (!items.is_empty()).then(|| usize::from(index) % items.len())
+}
+
+fn run_v2(
+    ops: Vec<Op>,
+    (tx, rx): (channel_v2::Sender<u64>, channel_v2::Receiver<u64>),
+    capacity: Option<usize>,
+) {
+    let mut senders = vec![tx];
+    let mut rx = Some(rx);
+    let mut model = Model::default();
+    for op in ops {
+        match op {
+            Op::Send(index) => {
+                let Some(index) = pick(&senders, index) else {
+                    continue;
+                };
+                let value = model.next_value();
+                // Never `send`: a full bounded channel would block forever.
+                let sent = senders[index].send_if_space(value);
+                match (&rx, sent) {
+                    (None, Err(channel_v2::SendError::Disconnected(v))) => assert_eq!(v, value),
+                    (Some(_), Ok(Some(v))) => {
+                        assert_eq!(v, value);
+                        assert_eq!(Some(model.queue.len()), capacity, "refused with room");
+                    }
+                    (Some(_), Ok(None)) => {
+                        assert!(capacity.is_none_or(|c| model.queue.len() < c));
+                        model.queue.push_back(value);
+                    }
+                    (rx, sent) => panic!("send with receiver {}: {sent:?}", rx.is_some()),
+                }
+            }
+            Op::CloneSender(index) => {
+                if let Some(index) = pick(&senders, index) {
+                    senders.push(senders[index].clone());
+                }
+            }
+            Op::DropSender(index) => {
+                if let Some(index) = pick(&senders, index) {
+                    senders.swap_remove(index);
+                }
+            }
+            Op::Recv => {
+                let Some(rx) = &mut rx else {
+                    continue;
+                };
+                if model.queue.is_empty() && !senders.is_empty() {
+                    // Would block with nobody to wake it.
+                    continue;
+                }
+                assert_eq!(rx.recv(), model.queue.pop_front());
+            }
+            Op::TryRecv => {
+                let Some(rx) = &mut rx else {
+                    continue;
+                };
+                let mut out = Vec::new();
+                rx.try_recv_many(1, &mut out);
+                assert_eq!(out.pop(), model.queue.pop_front());
+            }
+            Op::DropReceiver => {
+                rx = None;
+                model.queue.clear();
+            }
+        }
+        if let Some(rx) = &rx {
+            assert_eq!(
+                rx.is_disconnected(),
+                senders.is_empty() && model.queue.is_empty()
+            );
+        }
+    }
+    drop(senders);
+    if let Some(mut rx) = rx {
+        let rest: Vec<_> = std::iter::from_fn(|| rx.recv()).collect();
+        assert_eq!(rest, Vec::from(model.queue));
+        assert!(rx.is_disconnected());
+    }
+}
+
+// `channel_v1` has no non-blocking receive, and its `send` panics once the
+// receiver is gone, so neither is attempted.
+fn run_v1(ops: Vec<Op>) {
+    let (tx, rx) = channel_v1::channel();
+    let mut senders = vec![tx];
+    let mut rx = Some(rx);
+    let mut model = Model::default();
+    for op in ops {
+        match op {
+            Op::Send(index) => {
+                let (Some(index), Some(_)) = (pick(&senders, index), &rx) else {
+                    continue;
+                };
+                let value = model.next_value();
+                senders[index].send(value).unwrap();
+                model.queue.push_back(value);
+            }
+            Op::CloneSender(index) => {
+                if let Some(index) = pick(&senders, index) {
+                    senders.push(senders[index].clone());
+                }
+            }
+            Op::DropSender(index) => {
+                if let Some(index) = pick(&senders, index) {
+                    senders.swap_remove(index);
+                }
+            }
+            Op::Recv | Op::TryRecv => {
+                let Some(rx) = &mut rx else {
+                    continue;
+                };
+                if model.queue.is_empty() && !senders.is_empty() {
+                    continue;
+                }
+                assert_eq!(rx.recv(), model.queue.pop_front());
+            }
+            Op::DropReceiver => {
+                rx = None;
+                model.queue.clear();
+            }
+        }
+    }
+    drop(senders);
+    if let Some(mut rx) = rx {
+        let rest: Vec<_> = std::iter::from_fn(|| rx.recv()).collect();
+        assert_eq!(rest, Vec::from(model.queue));
+    }
+}
@@ -0,0 +1,91 @@
+#ifndef PAMADA_H
+#define PAMADA_H
+
+/* Generated by cbindgen from src/ffi.rs; do not edit. */
+
+#include <stdarg.h>
+#include <stdbool.h>
+#include <stdint.h>
+#include <stdlib.h>
+
+typedef enum ChannelStatus {
+  ChannelStatus_Ok,
+  /**
+   * Nothing was ready to receive yet.
+   */
+  ChannelStatus_Empty,
+  /**
+   * The other side is gone or the channel was closed.
+   */
+  ChannelStatus_Disconnected,
+} ChannelStatus;
+
+/**
+ * Opaque receiving half; there is only ever one per channel.
+ */
+typedef struct ChannelReceiver ChannelReceiver;
+
+/**
+ * Opaque sending half; clone with `channel_sender_clone`.
+ */
+typedef struct ChannelSender ChannelSender;
+
+/**
+ * Create a channel, writing its two handles to `sender` and `receiver`.
+ *
+ * # Safety
+ *
+ * Both pointers must be valid for writes.
+ */
+void channel_create(struct ChannelSender **sender, struct ChannelReceiver **receiver);
+
+/**
+ * # Safety
+ *
+ * `sender` must be null or a live handle.
+ */
+enum ChannelStatus channel_send(const struct ChannelSender *sender, uint64_t value);
+
+/**
+ * Receive without blocking, writing the value to `out` on `Ok`.
+ *
+ * # Safety
+ *
+ * `receiver` must be null or a live handle, not used from another thread
+ * at the same time, and `out` must be valid for writes.
+ */
+enum ChannelStatus channel_try_recv(struct ChannelReceiver *receiver, uint64_t *out);
+
+/**
+ * Make further sends fail; what's queued can still be received.
+ *
+ * # Safety
+ *
+ * `receiver` must be null or a live handle.
+ */
+void channel_close(const struct ChannelReceiver *receiver);
+
+/**
+ * A new sender handle for the same channel, or null for a null `sender`.
+ *
+ * # Safety
+ *
+ * `sender` must be null or a live handle.
+ */
+struct ChannelSender *channel_sender_clone(const struct ChannelSender *sender);
+
+/**
+ * # Safety
+ *
+ * `sender` must be null or a live handle, which is dead afterwards.
+ */
+void channel_sender_free(struct ChannelSender *sender);
+
+/**
+ * # Safety
+ *
+ * `receiver` must be null or a live handle, which is dead afterwards.
+ */
+void channel_receiver_free(struct ChannelReceiver *receiver);
+
+#endif  /* PAMADA_H */
@@ -0,0 +1,310 @@
+//! A channel whose storage is left to a `QueueBackend`, so the order
+//! messages come out in is the backend's choice: FIFO with a `VecDeque`,
+//! greatest first with a `BinaryHeap`, or anything custom. A backend that
+//! can fill up makes the channel bounded. The `priority` and `ring`
+//! channels are this one with backends of their own.
+//!
+//! With the nightly-only `allocator-api` feature, `channel_in` keeps the
+//! queue in a custom allocator, e.g. an arena.
+
+#[cfg(feature = "allocator-api")]
+use std::alloc::Allocator;
+use std::{
+    collections::{BinaryHeap, VecDeque},
+    marker::PhantomData,
+    sync::{Arc, Condvar, Mutex},
+};
+
+use crate::channel_v2::SendError;
+
+/// Storage for a `backend` channel. Only ever used under the channel's
+/// lock, so implementations need no synchronization of their own.
+pub trait QueueBackend<T> {
+    fn push(&mut self, value: T);
+
+    /// The next message to receive, if any.
+    fn pop(&mut self) -> Option<T>;
+
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    /// Whether `push` has no room right now; `send` waits until a receive
+    /// makes some. Unbounded backends keep the default.
+    fn is_full(&self) -> bool {
+        false
+    }
+}
+
+#[cfg(not(feature = "allocator-api"))]
+impl<T> QueueBackend<T> for VecDeque<T> {
+    fn push(&mut self, value: T) {
+        self.push_back(value);
+    }
+
+    fn pop(&mut self) -> Option<T> {
+        self.pop_front()
+    }
+
+    fn len(&self) -> usize {
+        VecDeque::len(self)
+    }
+}
+
+#[cfg(feature = "allocator-api")]
+impl<T, A: Allocator> QueueBackend<T> for VecDeque<T, A> {
+    fn push(&mut self, value: T) {
+        self.push_back(value);
+    }
+
+    fn pop(&mut self) -> Option<T> {
+        self.pop_front()
+    }
+
+    fn len(&self) -> usize {
+        VecDeque::len(self)
+    }
+}
+
+impl<T: Ord> QueueBackend<T> for BinaryHeap<T> {
+    fn push(&mut self, value: T) {
+        BinaryHeap::push(self, value);
+    }
+
+    fn pop(&mut self) -> Option<T> {
+        BinaryHeap::pop(self)
+    }
+
+    fn len(&self) -> usize {
+        BinaryHeap::len(self)
+    }
+}
+
+pub struct Sender<T, B: QueueBackend<T> = VecDeque<T>> {
+    shared: Arc<Shared<B>>,
+    _marker: PhantomData<T>,
+}
+
+impl<T, B: QueueBackend<T>> Sender<T, B> {
+    /// Block while the backend is full.
+    pub fn send(&self, value: T) -> Result<(), SendError<T>> {
+        let mut inner = self.shared.inner.lock().unwrap();
+        loop {
+            if !inner.rx_alive {
+                return Err(SendError::Disconnected(value));
+            }
+            if !inner.queue.is_full() {
+                break;
+            }
+            inner = self.shared.full.wait(inner).unwrap();
+        }
+        inner.queue.push(value);
+        drop(inner);
+        self.shared.avaliable.notify_one();
+        Ok(())
+    }
+}
+
+impl<T, B: QueueBackend<T>> Clone for Sender<T, B> {
+    fn clone(&self) -> Self {
+        let mut inner = self.shared.inner.lock().unwrap();
+        inner.tx_count += 1;
+        drop(inner);
+        Sender {
+            shared: Arc::clone(&self.shared),
+            _marker: PhantomData,
+        }
+    }
+}
+
+impl<T, B: QueueBackend<T>> Drop for Sender<T, B> {
+    fn drop(&mut self) {
+        let mut inner = self.shared.inner.lock().unwrap();
+        inner.tx_count -= 1;
+        if inner.tx_count == 0 {
+            self.shared.avaliable.notify_one();
+        }
+    }
+}
+
+pub struct Receiver<T, B: QueueBackend<T> = VecDeque<T>> {
+    shared: Arc<Shared<B>>,
+    _marker: PhantomData<T>,
+}
+
+impl<T, B: QueueBackend<T>> Receiver<T, B> {
+    pub fn recv(&mut self) -> Option<T> {
+        let mut inner = self.shared.inner.lock().unwrap();
+        loop {
+            match inner.queue.pop() {
+                v @ Some(_) => {
+                    drop(inner);
+                    self.shared.full.notify_one();
+                    return v;
+                }
+                None if inner.tx_count == 0 => return None,
+                None => {
+                    inner = self.shared.avaliable.wait(inner).unwrap();
+                }
+            }
+        }
+    }
+
+    /// Messages waiting to be received.
+    pub fn len(&self) -> usize {
+        self.shared.inner.lock().unwrap().queue.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.shared.inner.lock().unwrap().queue.is_empty()
+    }
+}
+
+impl<T, B: QueueBackend<T>> Drop for Receiver<T, B> {
+    fn drop(&mut self) {
+        let mut inner = self.shared.inner.lock().unwrap();
+        inner.rx_alive = false;
+        // Nobody can receive these any more; dispose of them once unlocked.
+        let pending: Vec<T> = std::iter::from_fn(|| inner.queue.pop()).collect();
+        drop(inner);
+        self.shared.full.notify_all();
+        drop(pending);
+    }
+}
+
+struct Shared<B> {
+    inner: Mutex<Inner<B>>,
+    avaliable: Condvar,
+    full: Condvar,
+}
+
+struct Inner<B> {
+    queue: B,
+    tx_count: usize,
+    rx_alive: bool,
+}
+
+/// A channel storing its messages in `backend`, which may already hold
+/// some.
+pub fn channel_with<T, B: QueueBackend<T>>(backend: B) -> (Sender<T, B>, Receiver<T, B>) {
+    let shared = Arc::new(Shared {
+        inner: Mutex::new(Inner {
+            queue: backend,
+            tx_count: 1,
+            rx_alive: true,
+        }),
+        avaliable: Condvar::default(),
+        full: Condvar::default(),
+    });
+    (
+        Sender {
+            shared: Arc::clone(&shared),
+            _marker: PhantomData,
+        },
+        Receiver {
+            shared,
+            _marker: PhantomData,
+        },
+    )
+}
+
+/// The ends of a `channel_in` channel, queueing in `A`.
+#[cfg(feature = "allocator-api")]
+pub type SenderIn<T, A> = Sender<T, VecDeque<T, A>>;
+#[cfg(feature = "allocator-api")]
+pub type ReceiverIn<T, A> = Receiver<T, VecDeque<T, A>>;
+
+/// A FIFO channel whose queue allocates from `alloc` instead of the global
+/// allocator.
+#[cfg(feature = "allocator-api")]
+pub fn channel_in<T, A: Allocator>(alloc: A) -> (SenderIn<T, A>, ReceiverIn<T, A>) {
+    channel_with(VecDeque::new_in(alloc))
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    struct Stack<T>(Vec<T>);
+
+    impl<T> QueueBackend<T> for Stack<T> {
+        fn push(&mut self, value: T) {
+            self.0.push(value);
+        }
+
+        fn pop(&mut self) -> Option<T> {
+            self.0.pop()
+        }
+
+        fn len(&self) -> usize {
+            self.0.len()
+        }
+    }
+
+    #[test]
+    fn test_custom_backend() {
+        let (tx, mut rx) = channel_with(Stack(Vec::new()));
+        for i in 1..=3 {
+            tx.send(i).unwrap();
+        }
+        let jh = std::thread::spawn(move || tx.send(4).unwrap());
+        jh.join().unwrap();
+        assert_eq!(rx.len(), 4);
+        assert_eq!(
+            std::iter::from_fn(|| rx.recv()).collect::<Vec<_>>(),
+            [4, 3, 2, 1]
+        );
+    }
+
+    #[cfg(feature = "allocator-api")]
+    #[test]
+    fn test_channel_in_bump() {
+        use std::{
+            alloc::{AllocError, Layout},
+            cell::{Cell, UnsafeCell},
+            ptr::NonNull,
+        };
+
+        // Hands out consecutive chunks of `arena` and never frees any.
+        struct Bump {
+            arena: UnsafeCell<[u8; 4096]>,
+            used: Cell<usize>,
+        }
+
+        unsafe impl Allocator for &Bump {
+            fn allocate(&self, layout: Layout) -> Result<NonNull<[u8]>, AllocError> {
+                let base = self.arena.get().cast::<u8>();
+                let start = (base as usize + self.used.get()).next_multiple_of(layout.align())
+                    - base as usize;
+                let end = start + layout.size();
+                if end > 4096 {
+                    return Err(AllocError);
+                }
+                self.used.set(end);
+                // SAFETY: `start..end` is within the arena.
+                let ptr = unsafe { base.add(start) };
+                let slice = std::ptr::slice_from_raw_parts_mut(ptr, layout.size());
+                NonNull::new(slice).ok_or(AllocError)
+            }
+
+            unsafe fn deallocate(&self, _: NonNull<u8>, _: Layout) {}
+        }
+
+        let bump = Bump {
+            arena: UnsafeCell::new([0; 4096]),
+            used: Cell::new(0),
+        };
+        let (tx, mut rx) = channel_in(&bump);
+        for i in 0..10u64 {
+            tx.send(i).unwrap();
+        }
+        assert!(bump.used.get() >= 10 * size_of::<u64>());
+        drop(tx);
+        assert_eq!(
+            std::iter::from_fn(|| rx.recv()).collect::<Vec<_>>(),
+            (0..10).collect::<Vec<_>>()
+        );
+    }
+}
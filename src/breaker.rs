@@ -0,0 +1,131 @@
+//! A sender that sheds load instead of piling it onto a consumer that has
+//! fallen behind, tripping on the channel's consumer lag.
+
+use std::{
+    error::Error,
+    fmt::{Debug, Display},
+    sync::Mutex,
+};
+
+use crate::channel_v2::{SendError, Sender};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum BreakerState {
+    /// Sends go through.
+    Closed,
+    /// Sends are refused until the lag falls below the low watermark.
+    Open,
+    /// One probe went through after the lag fell; the breaker closes once
+    /// the lag, probe included, is still below the low watermark, and
+    /// refuses sends until then.
+    HalfOpen,
+}
+
+/// Wraps a sender so its sends fail fast with `BreakerError::Open` once
+/// more than `threshold` messages are waiting to be received.
+pub struct CircuitBreakerSender<T> {
+    tx: Sender<T>,
+    threshold: usize,
+    low_watermark: usize,
+    state: Mutex<BreakerState>,
+}
+
+impl<T> CircuitBreakerSender<T> {
+    /// Trip once the lag exceeds `threshold`, and start recovering once it
+    /// drops below `low_watermark`.
+    pub fn new(tx: Sender<T>, threshold: usize, low_watermark: usize) -> Self {
+        assert!(
+            low_watermark <= threshold,
+            "the low watermark must not exceed the threshold"
+        );
+        CircuitBreakerSender {
+            tx,
+            threshold,
+            low_watermark,
+            state: Mutex::new(BreakerState::Closed),
+        }
+    }
+
+    pub fn send(&self, value: T) -> Result<(), BreakerError<T>> {
+        let mut state = self.state.lock().unwrap();
+        let lag = self.tx.consumer_lag();
+        *state = match *state {
+            BreakerState::Closed if lag > self.threshold => BreakerState::Open,
+            BreakerState::Open if lag < self.low_watermark => {
+                self.tx.send(value).map_err(BreakerError::Send)?;
+                *state = BreakerState::HalfOpen;
+                return Ok(());
+            }
+            BreakerState::HalfOpen if lag < self.low_watermark => BreakerState::Closed,
+            BreakerState::HalfOpen if lag > self.threshold => BreakerState::Open,
+            unchanged => unchanged,
+        };
+        if *state != BreakerState::Closed {
+            return Err(BreakerError::Open(value));
+        }
+        self.tx.send(value).map_err(BreakerError::Send)
+    }
+
+    /// The state as of the last send.
+    pub fn state(&self) -> BreakerState {
+        *self.state.lock().unwrap()
+    }
+
+    pub fn into_inner(self) -> Sender<T> {
+        self.tx
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum BreakerError<T> {
+    /// The breaker is open or half-open; the value was not sent.
+    Open(T),
+    Send(SendError<T>),
+}
+
+impl<T> Display for BreakerError<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BreakerError::Open(_) => f.write_str("sending through an open circuit breaker"),
+            BreakerError::Send(err) => Display::fmt(err, f),
+        }
+    }
+}
+
+impl<T: Debug> Error for BreakerError<T> {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::channel_v2;
+
+    #[test]
+    fn test_trips_and_recovers() {
+        let (tx, mut rx) = channel_v2::channel();
+        let breaker = CircuitBreakerSender::new(tx, 3, 2);
+        for i in 0..4 {
+            breaker.send(i).unwrap();
+        }
+        assert_eq!(breaker.state(), BreakerState::Closed);
+        assert_eq!(breaker.send(4), Err(BreakerError::Open(4)));
+        assert_eq!(breaker.state(), BreakerState::Open);
+
+        // Down to a lag of 2, not yet below the low watermark.
+        rx.recv();
+        rx.recv();
+        assert_eq!(breaker.send(4), Err(BreakerError::Open(4)));
+        assert_eq!(breaker.state(), BreakerState::Open);
+
+        rx.recv();
+        breaker.send(4).unwrap();
+        assert_eq!(breaker.state(), BreakerState::HalfOpen);
+        // The probe keeps the lag at 2 until it's received.
+        assert_eq!(breaker.send(5), Err(BreakerError::Open(5)));
+        assert_eq!(rx.recv(), Some(3));
+        breaker.send(5).unwrap();
+        assert_eq!(breaker.state(), BreakerState::Closed);
+
+        drop(breaker);
+        assert_eq!(std::iter::from_fn(|| rx.recv()).collect::<Vec<_>>(), [4, 5]);
+    }
+}
@@ -0,0 +1,203 @@
+//! Async receiving for code that can't use `Receiver::poll_recv` directly:
+//! a receive that would block hands the receiver to a worker thread, one per
+//! bridge, which waits with the plain blocking machinery until a message is
+//! ready and wakes the awaiting task. The worker only waits; the message is
+//! still taken on the task's side, so none is ever stranded on the worker.
+
+use std::{
+    future::Future,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    task::{Context, Poll, Waker},
+};
+
+use crate::{
+    channel_v2::Receiver,
+    sync::{Condvar, Mutex},
+};
+
+/// A receiver for async code, made with `Receiver::into_async`. Receives
+/// that can finish at once do; the rest are waited out on a worker thread
+/// spawned the first time one is needed and reused after.
+///
+/// Dropping the bridge drops the receiver too, so senders see the channel
+/// disconnect: at once if no receive is pending, otherwise as soon as the
+/// worker notices, within `INTERRUPT_POLL`. Messages still queued then go
+/// to the channel's `DropPolicy` as with any receiver.
+pub struct AsyncReceiver<T> {
+    // Here between receives; with `worker` while one is pending.
+    receiver: Option<Receiver<T>>,
+    worker: Option<Arc<Worker<T>>>,
+}
+
+struct Worker<T> {
+    state: Mutex<Handoff<T>>,
+    // Wakes the worker when a receiver is handed to it or the bridge drops.
+    wake: Condvar,
+    // Set when the bridge drops, interrupting the worker's wait.
+    stop: AtomicBool,
+}
+
+struct Handoff<T> {
+    // From the bridge, for the worker to wait on.
+    waiting: Option<Receiver<T>>,
+    // Back from the worker, with a message ready or disconnected.
+    ready: Option<Receiver<T>>,
+    waker: Option<Waker>,
+}
+
+impl<T: Send + 'static> AsyncReceiver<T> {
+    pub(crate) fn new(receiver: Receiver<T>) -> Self {
+        AsyncReceiver {
+            receiver: Some(receiver),
+            worker: None,
+        }
+    }
+
+    /// Resolves as `Receiver::recv` returns. Dropping the future while it
+    /// waits doesn't lose the message: the next `recv` picks it up.
+    pub fn recv(&mut self) -> Recv<'_, T> {
+        Recv { bridge: self }
+    }
+
+    fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        loop {
+            if let Some(receiver) = &mut self.receiver {
+                if let Some(value) = receiver.recv_ready() {
+                    return Poll::Ready(value);
+                }
+                let worker = self.worker.get_or_insert_with(Worker::spawn);
+                let mut state = worker.state.lock();
+                state.waiting = self.receiver.take();
+                state.waker = Some(cx.waker().clone());
+                drop(state);
+                worker.wake.notify_one();
+                return Poll::Pending;
+            }
+            let worker = self.worker.as_ref().unwrap();
+            let mut state = worker.state.lock();
+            let Some(receiver) = state.ready.take() else {
+                state.waker = Some(cx.waker().clone());
+                return Poll::Pending;
+            };
+            drop(state);
+            // The worker left the ready message first in line.
+            self.receiver = Some(receiver);
+        }
+    }
+}
+
+impl<T> Drop for AsyncReceiver<T> {
+    fn drop(&mut self) {
+        let Some(worker) = &self.worker else {
+            return;
+        };
+        worker.stop.store(true, Ordering::Release);
+        let mut state = worker.state.lock();
+        let parked = (state.waiting.take(), state.ready.take());
+        drop(state);
+        worker.wake.notify_one();
+        drop(parked);
+    }
+}
+
+impl<T: Send + 'static> Worker<T> {
+    fn spawn() -> Arc<Self> {
+        let worker = Arc::new(Worker {
+            state: Mutex::new(Handoff {
+                waiting: None,
+                ready: None,
+                waker: None,
+            }),
+            wake: Condvar::default(),
+            stop: AtomicBool::new(false),
+        });
+        let run = Arc::clone(&worker);
+        std::thread::spawn(move || run.run());
+        worker
+    }
+
+    fn run(&self) {
+        loop {
+            let mut state = self.state.lock();
+            let mut receiver = loop {
+                // Checked under the lock the bridge's drop takes to notify.
+                if self.stop.load(Ordering::Acquire) {
+                    return;
+                }
+                if let Some(receiver) = state.waiting.take() {
+                    break receiver;
+                }
+                state = self.wake.wait(state);
+            };
+            drop(state);
+            // Interrupted: the bridge is gone, and the receiver with it.
+            if receiver.wait_ready_interruptible(&self.stop).is_err() {
+                return;
+            }
+            let mut state = self.state.lock();
+            state.ready = Some(receiver);
+            let waker = state.waker.take();
+            drop(state);
+            waker.into_iter().for_each(Waker::wake);
+        }
+    }
+}
+
+/// The future returned by `AsyncReceiver::recv`.
+pub struct Recv<'a, T> {
+    bridge: &'a mut AsyncReceiver<T>,
+}
+
+impl<T: Send + 'static> Future for Recv<'_, T> {
+    type Output = Option<T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        self.get_mut().bridge.poll_recv(cx)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use crate::channel_v2::{channel, SendError, INTERRUPT_POLL};
+
+    #[test]
+    fn test_await_bridged() {
+        let (tx, rx) = channel();
+        let mut rx = rx.into_async();
+        let jh = std::thread::spawn(move || {
+            for i in 0..3 {
+                std::thread::sleep(Duration::from_millis(10));
+                tx.send(i).unwrap();
+            }
+        });
+        smol::block_on(async {
+            for i in 0..3 {
+                assert_eq!(rx.recv().await, Some(i));
+            }
+            assert_eq!(rx.recv().await, None);
+        });
+        jh.join().unwrap();
+    }
+
+    #[test]
+    fn test_drop_pending_bridge() {
+        let (tx, rx) = channel::<i32>();
+        let mut rx = rx.into_async();
+        assert!(smol::block_on(smol::future::poll_once(rx.recv())).is_none());
+        drop(rx);
+        // The worker lets go of the receiver instead of keeping the channel
+        // open and swallowing the next message.
+        let start = Instant::now();
+        while tx.ping().is_ok() {
+            assert!(start.elapsed() < INTERRUPT_POLL * 100);
+            std::thread::sleep(INTERRUPT_POLL);
+        }
+        assert_eq!(tx.send(1), Err(SendError::Disconnected(1)));
+    }
+}
@@ -0,0 +1,209 @@
+use std::{
+    collections::{HashSet, VecDeque},
+    sync::{Arc, Condvar, Mutex},
+};
+
+use crate::channel_v2::SendError;
+
+pub struct Sender<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Sender<T> {
+    /// Deliver `value` to every live receiver. Receivers share one
+    /// allocation, so `T` is never cloned no matter how many subscribe.
+    pub fn send(&self, value: T) -> Result<(), SendError<T>> {
+        self.fan_out(value, |_| true)
+    }
+
+    /// Deliver `value` only to the receivers subscribed to `topic` with
+    /// `Receiver::subscribe_topic`.
+    pub fn publish(&self, topic: &str, value: T) -> Result<(), SendError<T>> {
+        self.fan_out(value, |slot| slot.topics.contains(topic))
+    }
+
+    fn fan_out(&self, value: T, wants: impl Fn(&Slot<T>) -> bool) -> Result<(), SendError<T>> {
+        let mut inner = self.shared.inner.lock().unwrap();
+        if inner.slots.iter().all(Option::is_none) {
+            return Err(SendError::Disconnected(value));
+        }
+        let value = Arc::new(value);
+        for slot in inner.slots.iter_mut().flatten() {
+            if wants(slot) {
+                slot.queue.push_back(Arc::clone(&value));
+            }
+        }
+        drop(inner);
+        self.shared.avaliable.notify_all();
+        Ok(())
+    }
+
+    /// Add a receiver that sees every message sent from now on.
+    pub fn subscribe(&self) -> Receiver<T> {
+        let mut inner = self.shared.inner.lock().unwrap();
+        let slot = inner.slots.len();
+        inner.slots.push(Some(Slot::default()));
+        Receiver {
+            shared: Arc::clone(&self.shared),
+            slot,
+        }
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        let mut inner = self.shared.inner.lock().unwrap();
+        inner.tx_count += 1;
+        drop(inner);
+        Sender {
+            shared: Arc::clone(&self.shared),
+        }
+    }
+}
+
+impl<T> Drop for Sender<T> {
+    fn drop(&mut self) {
+        let mut inner = self.shared.inner.lock().unwrap();
+        inner.tx_count -= 1;
+        if inner.tx_count == 0 {
+            self.shared.avaliable.notify_all();
+        }
+    }
+}
+
+pub struct Receiver<T> {
+    shared: Arc<Shared<T>>,
+    slot: usize,
+}
+
+impl<T> Receiver<T> {
+    pub fn recv(&mut self) -> Option<Arc<T>> {
+        let mut inner = self.shared.inner.lock().unwrap();
+        loop {
+            match inner.slot(self.slot).queue.pop_front() {
+                v @ Some(_) => return v,
+                None if inner.tx_count == 0 => return None,
+                None => {
+                    inner = self.shared.avaliable.wait(inner).unwrap();
+                }
+            }
+        }
+    }
+
+    /// Also receive what's published to `topic`, from now on.
+    pub fn subscribe_topic(&mut self, topic: &str) {
+        let mut inner = self.shared.inner.lock().unwrap();
+        inner.slot(self.slot).topics.insert(topic.to_owned());
+    }
+}
+
+impl<T> Drop for Receiver<T> {
+    fn drop(&mut self) {
+        self.shared.inner.lock().unwrap().slots[self.slot] = None;
+    }
+}
+
+struct Shared<T> {
+    inner: Mutex<Inner<T>>,
+    avaliable: Condvar,
+}
+
+struct Inner<T> {
+    // Indexed by `Receiver::slot`; `None` once that receiver has dropped.
+    slots: Vec<Option<Slot<T>>>,
+    tx_count: usize,
+}
+
+struct Slot<T> {
+    queue: VecDeque<Arc<T>>,
+    topics: HashSet<String>,
+}
+
+impl<T> Default for Slot<T> {
+    fn default() -> Self {
+        Slot {
+            queue: VecDeque::default(),
+            topics: HashSet::default(),
+        }
+    }
+}
+
+impl<T> Inner<T> {
+    fn slot(&mut self, slot: usize) -> &mut Slot<T> {
+        self.slots[slot]
+            .as_mut()
+            .expect("a live receiver always has a slot")
+    }
+}
+
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    let shared = Arc::new(Shared {
+        inner: Mutex::new(Inner {
+            slots: vec![Some(Slot::default())],
+            tx_count: 1,
+        }),
+        avaliable: Condvar::default(),
+    });
+    (
+        Sender {
+            shared: Arc::clone(&shared),
+        },
+        Receiver { shared, slot: 0 },
+    )
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn test_broadcast() {
+        let (tx, mut rx1) = channel();
+        let mut rx2 = tx.subscribe();
+        tx.send(1).unwrap();
+        tx.send(2).unwrap();
+        drop(tx);
+        assert_eq!(rx1.recv().as_deref(), Some(&1));
+        assert_eq!(rx1.recv().as_deref(), Some(&2));
+        assert_eq!(rx1.recv(), None);
+        assert_eq!(rx2.recv().as_deref(), Some(&1));
+        assert_eq!(rx2.recv().as_deref(), Some(&2));
+        assert_eq!(rx2.recv(), None);
+    }
+
+    #[test]
+    fn test_broadcast_shares_payload() {
+        let (tx, mut rx1) = channel();
+        let mut rx2 = tx.subscribe();
+        tx.send(vec![0u8; 1 << 20]).unwrap();
+        let a = rx1.recv().unwrap();
+        let b = rx2.recv().unwrap();
+        assert!(Arc::ptr_eq(&a, &b));
+        assert_eq!(a.as_ptr(), b.as_ptr());
+    }
+
+    #[test]
+    fn test_broadcast_no_receivers() {
+        let (tx, rx) = channel();
+        drop(rx);
+        assert_eq!(tx.send(1), Err(SendError::Disconnected(1)));
+    }
+
+    #[test]
+    fn test_publish_topics() {
+        let (tx, mut news) = channel();
+        let mut sports = tx.subscribe();
+        news.subscribe_topic("news");
+        sports.subscribe_topic("sports");
+        tx.publish("news", 1).unwrap();
+        tx.publish("sports", 2).unwrap();
+        tx.publish("weather", 3).unwrap();
+        tx.send(4).unwrap();
+        drop(tx);
+        let news: Vec<_> = std::iter::from_fn(|| news.recv()).map(|v| *v).collect();
+        let sports: Vec<_> = std::iter::from_fn(|| sports.recv()).map(|v| *v).collect();
+        assert_eq!(news, [1, 4]);
+        assert_eq!(sports, [2, 4]);
+    }
+}
@@ -0,0 +1,266 @@
+use crate::{channel_v1, channel_v2};
+
+pub trait Channel<T> {
+    type Sender: SenderExt<T> + Clone;
+    type Receiver: ReceiverExt<T>;
+
+    fn channel() -> (Self::Sender, Self::Receiver);
+}
+
+pub trait SenderExt<T> {
+    type Error;
+
+    /// Messages from one sender are received in the order they were sent;
+    /// how messages from different senders interleave is unspecified.
+    ///
+    /// A send happens-before the `recv` that returns its message: whatever
+    /// the sending thread wrote before the call, even through relaxed
+    /// atomics or other interior mutability, is visible to the receiving
+    /// thread once `recv` returns.
+    fn send(&self, value: T) -> Result<(), Self::Error>;
+}
+
+pub trait ReceiverExt<T> {
+    fn recv(&mut self) -> Option<T>;
+}
+
+pub struct ChannelV1;
+
+impl<T> Channel<T> for ChannelV1 {
+    type Sender = channel_v1::Sender<T>;
+    type Receiver = channel_v1::Receiver<T>;
+
+    fn channel() -> (Self::Sender, Self::Receiver) {
+        channel_v1::channel()
+    }
+}
+
+impl<T> SenderExt<T> for channel_v1::Sender<T> {
+    type Error = channel_v1::SendError;
+
+    fn send(&self, value: T) -> Result<(), Self::Error> {
+        channel_v1::Sender::send(self, value)
+    }
+}
+
+impl<T> ReceiverExt<T> for channel_v1::Receiver<T> {
+    fn recv(&mut self) -> Option<T> {
+        channel_v1::Receiver::recv(self)
+    }
+}
+
+pub struct ChannelV2;
+
+impl<T> Channel<T> for ChannelV2 {
+    type Sender = channel_v2::Sender<T>;
+    type Receiver = channel_v2::Receiver<T>;
+
+    fn channel() -> (Self::Sender, Self::Receiver) {
+        channel_v2::channel()
+    }
+}
+
+impl<T> SenderExt<T> for channel_v2::Sender<T> {
+    type Error = channel_v2::SendError<T>;
+
+    fn send(&self, value: T) -> Result<(), Self::Error> {
+        channel_v2::Sender::send(self, value)
+    }
+}
+
+impl<T> ReceiverExt<T> for channel_v2::Receiver<T> {
+    fn recv(&mut self) -> Option<T> {
+        channel_v2::Receiver::recv(self)
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Barrier,
+    };
+
+    use super::*;
+
+    fn sum_from_producers<C>() -> usize
+    where
+        C: Channel<usize>,
+        C::Sender: Send + 'static,
+        C::Receiver: Send + 'static,
+    {
+        let (tx, mut rx) = C::channel();
+        const PRODUCERS: usize = 4;
+        const PER_PRODUCER: usize = 1000;
+        for _ in 0..PRODUCERS {
+            let tx = tx.clone();
+            std::thread::spawn(move || {
+                for _ in 0..PER_PRODUCER {
+                    let _ = tx.send(1);
+                }
+            });
+        }
+        drop(tx);
+        let jh = std::thread::spawn(move || {
+            let mut count = 0;
+            while let Some(v) = rx.recv() {
+                count += v;
+            }
+            count
+        });
+        let total = jh.join().unwrap();
+        assert_eq!(total, PRODUCERS * PER_PRODUCER);
+        total
+    }
+
+    #[test]
+    fn test_generic_over_implementations() {
+        assert_eq!(
+            sum_from_producers::<ChannelV1>(),
+            sum_from_producers::<ChannelV2>()
+        );
+    }
+
+    fn check_per_producer_order<C>()
+    where
+        C: Channel<(usize, usize)>,
+        C::Sender: Send + 'static,
+    {
+        let (tx, mut rx) = C::channel();
+        const PRODUCERS: usize = 4;
+        const PER_PRODUCER: usize = 10000;
+        for producer in 0..PRODUCERS {
+            let tx = tx.clone();
+            std::thread::spawn(move || {
+                for seq in 0..PER_PRODUCER {
+                    let _ = tx.send((producer, seq));
+                }
+            });
+        }
+        drop(tx);
+        let mut next = [0; PRODUCERS];
+        while let Some((producer, seq)) = rx.recv() {
+            assert_eq!(seq, next[producer], "producer {} out of order", producer);
+            next[producer] += 1;
+        }
+        assert_eq!(next, [PER_PRODUCER; PRODUCERS]);
+    }
+
+    #[test]
+    fn test_per_producer_order() {
+        check_per_producer_order::<ChannelV1>();
+        check_per_producer_order::<ChannelV2>();
+    }
+
+    fn check_send_happens_before_recv<C>()
+    where
+        C: Channel<usize>,
+        C::Sender: Send + 'static,
+    {
+        const ROUNDS: usize = if cfg!(miri) { 50 } else { 10000 };
+        let (tx, mut rx) = C::channel();
+        // Written with relaxed stores, so only the channel orders them
+        // against the receiver's relaxed loads. x86 never reorders stores,
+        // so natively there this only catches compiler reordering; Miri's
+        // weak memory emulation can hand a relaxed load any write it isn't
+        // ordered after, which makes it the real check:
+        //     cargo +nightly miri test --lib -- send_happens relaxed_handoff
+        // where `test_relaxed_handoff_caught` checks it does catch a
+        // handoff with no ordering.
+        let slots: Arc<[AtomicUsize; 8]> = Arc::default();
+        let written = Arc::clone(&slots);
+        let producer = std::thread::spawn(move || {
+            for round in 1..=ROUNDS {
+                for slot in written.iter() {
+                    slot.store(round, Ordering::Relaxed);
+                }
+                let _ = tx.send(round);
+            }
+        });
+        let mut received = 0;
+        while let Some(round) = rx.recv() {
+            for slot in slots.iter() {
+                let seen = slot.load(Ordering::Relaxed);
+                assert!(seen >= round, "round {} saw stale write {}", round, seen);
+            }
+            received += 1;
+        }
+        producer.join().unwrap();
+        assert_eq!(received, ROUNDS);
+    }
+
+    #[test]
+    fn test_send_happens_before_recv() {
+        check_send_happens_before_recv::<ChannelV1>();
+        check_send_happens_before_recv::<ChannelV2>();
+    }
+
+    // The same check against a "channel" that's only a relaxed counter,
+    // which orders nothing. Miri's default seed makes the stale read it
+    // serves up deterministic.
+    #[cfg(miri)]
+    #[test]
+    #[should_panic(expected = "saw stale write")]
+    fn test_relaxed_handoff_caught() {
+        let slots: Arc<[AtomicUsize; 8]> = Arc::default();
+        let sent = Arc::new(AtomicUsize::new(0));
+        let (written, published) = (Arc::clone(&slots), Arc::clone(&sent));
+        std::thread::spawn(move || {
+            for round in 1..=20 {
+                for slot in written.iter() {
+                    slot.store(round, Ordering::Relaxed);
+                }
+                published.store(round, Ordering::Relaxed);
+            }
+        });
+        let mut last = 0;
+        while last < 20 {
+            let round = sent.load(Ordering::Relaxed);
+            if round == last {
+                std::thread::yield_now();
+                continue;
+            }
+            last = round;
+            for slot in slots.iter() {
+                let seen = slot.load(Ordering::Relaxed);
+                assert!(seen >= round, "round {} saw stale write {}", round, seen);
+            }
+        }
+    }
+
+    fn check_concurrent_sender_drops<C>()
+    where
+        C: Channel<usize>,
+        C::Sender: Send + 'static,
+    {
+        const SENDERS: usize = 8;
+        const ROUNDS: usize = 200;
+        for _ in 0..ROUNDS {
+            let (tx, mut rx) = C::channel();
+            let barrier = Arc::new(Barrier::new(SENDERS));
+            let droppers: Vec<_> = (0..SENDERS)
+                .map(|_| {
+                    let tx = tx.clone();
+                    let barrier = Arc::clone(&barrier);
+                    std::thread::spawn(move || {
+                        barrier.wait();
+                        drop(tx);
+                    })
+                })
+                .collect();
+            drop(tx);
+            // Hangs if no dropper notices it was the last.
+            assert!(rx.recv().is_none());
+            for dropper in droppers {
+                dropper.join().unwrap();
+            }
+        }
+    }
+
+    #[test]
+    fn test_concurrent_sender_drops() {
+        check_concurrent_sender_drops::<ChannelV1>();
+        check_concurrent_sender_drops::<ChannelV2>();
+    }
+}
@@ -1,12 +1,12 @@
 use std::{
     collections::VecDeque,
     error::Error,
-    fmt::{Display, Write},
+    fmt::Display,
     sync::{Arc, Condvar, Mutex, Weak},
 };
 
 #[derive(Debug)]
-struct SendError;
+pub struct SendError;
 
 impl Display for SendError {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
@@ -16,12 +16,12 @@ impl Display for SendError {
 
 impl Error for SendError {}
 
-struct Sender<T> {
+pub struct Sender<T> {
     shared: Weak<Shared<T>>,
 }
 
 impl<T> Sender<T> {
-    fn send(&self, value: T) -> Result<(), SendError> {
+    pub fn send(&self, value: T) -> Result<(), SendError> {
         let share = match self.shared.upgrade() {
             Some(share) => share,
             None => panic!("Sender send value but the Receiver has closed."),
@@ -42,19 +42,31 @@ impl<T> Clone for Sender<T> {
 
 impl<T> Drop for Sender<T> {
     fn drop(&mut self) {
-        if Weak::weak_count(&self.shared) == 1 {
-            self.shared.upgrade().unwrap().avaliable.notify_one();
+        let weak = std::mem::replace(&mut self.shared, Weak::new());
+        let share = match weak.upgrade() {
+            Some(share) => share,
+            None => return,
+        };
+        // The weak count is the sender count. Every decrement happens here
+        // under the queue lock, and the receiver reads it under that lock
+        // before parking, so exactly the last sender sees zero and its
+        // notify can't land between the receiver's check and its wait.
+        // Clones only ever raise it from a live sender's nonzero count.
+        let _queue = share.queue.lock().unwrap();
+        drop(weak);
+        if Arc::weak_count(&share) == 0 {
+            share.avaliable.notify_one();
         }
     }
 }
 
-struct Receiver<T> {
+pub struct Receiver<T> {
     shared: Arc<Shared<T>>,
     buffer: VecDeque<T>,
 }
 
 impl<T> Receiver<T> {
-    fn recv(&mut self) -> Option<T> {
+    pub fn recv(&mut self) -> Option<T> {
         if let v @ Some(_) = self.buffer.pop_front() {
             return v;
         }
@@ -79,7 +91,7 @@ struct Shared<T> {
     avaliable: Condvar,
 }
 
-fn channel<T>() -> (Sender<T>, Receiver<T>) {
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
     let shared = Arc::new(Shared {
         queue: Mutex::default(),
         avaliable: Condvar::default(),
@@ -1,147 +1,6263 @@
 use std::{
+    any::Any,
     collections::VecDeque,
-    sync::{Arc, Condvar, Mutex},
+    convert::Infallible,
+    error::Error,
+    ffi::c_void,
+    fmt::{Debug, Display},
+    future::Future,
+    io::{self, Write},
+    marker::PhantomData,
+    mem::{ManuallyDrop, MaybeUninit},
+    ops::Deref,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering},
+        Arc, Weak,
+    },
+    task::{Context, Poll, Waker},
+    time::{Duration, Instant},
 };
 
-struct Sender<T> {
+use crate::{
+    metrics::{Metrics, MetricsReport, ARRIVAL_BUCKETS},
+    sync::{Condvar, Mutex, MutexGuard},
+    thread_priority,
+};
+
+/// How often a parked `recv_interruptible` re-checks its interrupt flag.
+pub const INTERRUPT_POLL: Duration = Duration::from_millis(10);
+
+/// How many recent receive timestamps `recv_rate` keeps.
+pub const RATE_SAMPLES: usize = 256;
+
+/// How far `ChannelBuilder::backlog_priority_boost` lowers the receiving
+/// thread's nice value.
+pub const PRIORITY_BOOST: i32 = 5;
+
+pub struct Sender<T, R = Shutdown> {
     shared: Arc<Shared<T>>,
+    id: SenderId,
+    reason: PhantomData<fn() -> R>,
+}
+
+/// Identifies a `Sender`: the one `channel` returns is `0`, and each clone
+/// gets the next number.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub struct SenderId(pub u64);
+
+impl<T, R: CloseReason> Sender<T, R> {
+    /// The id attached to every message this sender sends, as reported by
+    /// `Receiver::recv_tagged`.
+    pub fn id(&self) -> SenderId {
+        self.id
+    }
+
+    fn envelope(&self, value: T) -> Envelope<T> {
+        let mut envelope = Envelope::new(value);
+        envelope.sender = self.id;
+        envelope
+    }
+
+    pub fn send(&self, value: T) -> Result<(), SendError<T, R>> {
+        self.push(value, |_| {}).map(drop)
+    }
+
+    /// Send a clone of `value`, made only once the channel is known to
+    /// still accept messages, so a send that fails up front costs no clone.
+    /// The clone is made unlocked; should the receiver drop during it, the
+    /// send fails anyway and the clone is dropped.
+    pub fn send_cloned(&self, value: &T) -> Result<(), SendError<(), R>>
+    where
+        T: Clone,
+    {
+        if self.lock_open().is_none() {
+            return Err(self.shared.send_error(()));
+        }
+        self.send(value.clone()).map_err(|err| err.map(drop))
+    }
+
+    /// Like `send`, but also return the shared queue's length after the
+    /// value went in, as a cheap backpressure signal. Messages the receiver
+    /// has already bulk-buffered aren't counted.
+    pub fn send_len(&self, value: T) -> Result<usize, SendError<T, R>> {
+        self.push(value, |_| {})
+    }
+
+    /// Send a message that can be withdrawn with the returned token for as
+    /// long as the receiver hasn't taken it.
+    pub fn send_cancellable(&self, value: T) -> Result<CancelToken<T>, SendError<T, R>> {
+        let claimed = Arc::new(AtomicBool::new(false));
+        self.push(value, |envelope| {
+            envelope.claimed = Some(Arc::clone(&claimed))
+        })?;
+        Ok(CancelToken {
+            shared: Arc::downgrade(&self.shared),
+            claimed,
+        })
+    }
+
+    /// Send a message that is only worth delivering within `ttl`. Expiry is
+    /// checked lazily: an expired message stays queued until `recv` reaches
+    /// it and then is dropped instead of returned.
+    pub fn send_with_ttl(&self, value: T, ttl: Duration) -> Result<(), SendError<T, R>> {
+        let expires = Instant::now() + ttl;
+        self.push(value, |envelope| envelope.expires = Some(expires))
+            .map(drop)
+    }
+
+    /// Send `value` along with the current `tracing` span, for
+    /// `Receiver::recv_traced` to enter on the other side.
+    #[cfg(feature = "tracing")]
+    pub fn send_traced(&self, value: T) -> Result<(), SendError<T, R>> {
+        let span = tracing::Span::current();
+        self.push(value, |envelope| envelope.span = Some(span))
+            .map(drop)
+    }
+
+    // Send `value` ahead of everything already sent, including what the
+    // receiver has buffered. Ignores capacity and overflow limits.
+    pub(crate) fn send_ahead(&self, value: T) -> Result<(), SendError<T, R>> {
+        let Some(mut inner) = self.lock_open() else {
+            return Err(self.shared.send_error(value));
+        };
+        let mut envelope = self.envelope(value);
+        self.shared.stamp(&mut inner, &mut envelope);
+        self.shared.push_ahead(&mut inner, envelope);
+        drop(inner);
+        self.shared.emit(ChannelEvent::MessageSent);
+        self.shared.notify_receiver();
+        Ok(())
+    }
+
+    /// Send only if a bounded channel has room, handing the value back as
+    /// `Ok(Some(value))` when it's full. Unbounded channels have room until
+    /// they reach `max_queue`.
+    pub fn send_if_space(&self, value: T) -> Result<Option<T>, SendError<T, R>> {
+        let Some(inner) = self.lock_open() else {
+            return Err(self.shared.send_error(value));
+        };
+        if !self.shared.has_space(&inner, 1) || self.shared.at_max_queue(&inner, 1) {
+            return Ok(Some(value));
+        }
+        self.shared.enqueue(inner, self.envelope(value));
+        Ok(None)
+    }
+
+    // `send_if_space` for `select_op`: finding no room, leave `waker` to be
+    // woken once there may be some, then look again.
+    fn send_if_space_or_wake(&self, value: T, waker: &Waker) -> Result<Option<T>, SendError<T, R>> {
+        let Some(value) = self.send_if_space(value)? else {
+            return Ok(None);
+        };
+        let mut wakers = self.shared.select_wakers.lock();
+        if !wakers.iter().any(|w| w.will_wake(waker)) {
+            wakers.push(waker.clone());
+        }
+        self.shared.has_select_wakers.store(true, Ordering::SeqCst);
+        drop(wakers);
+        self.send_if_space(value)
+    }
+
+    /// Send on a channel built with `headroom`, which may take the slots
+    /// kept back from ordinary sends, blocking only once those are full too.
+    /// Ignores the overflow policy.
+    pub fn send_priority(&self, value: T) -> Result<(), SendError<T, R>> {
+        let Some(mut inner) = self.lock_open() else {
+            return Err(self.shared.send_error(value));
+        };
+        while self.shared.priority_slots(&inner) == 0 {
+            let Some(woken) = self.check_open(self.shared.full.wait(inner)) else {
+                return Err(self.shared.send_error(value));
+            };
+            inner = woken;
+        }
+        if self.shared.at_max_queue(&inner, 1) {
+            drop(inner);
+            return Err(SendError::Overflow(value));
+        }
+        self.shared.enqueue(inner, self.envelope(value));
+        Ok(())
+    }
+
+    // Returns the queue length after the push.
+    fn push(
+        &self,
+        value: T,
+        stamp: impl FnOnce(&mut Envelope<T>),
+    ) -> Result<usize, SendError<T, R>> {
+        let len = self.push_quiet(value, stamp)?;
+        self.shared.notify_pushed(len);
+        Ok(len)
+    }
+
+    // `push` without waking the receiver.
+    fn push_quiet(
+        &self,
+        value: T,
+        stamp: impl FnOnce(&mut Envelope<T>),
+    ) -> Result<usize, SendError<T, R>> {
+        self.push_quiet_sized(value, 0, stamp)
+    }
+
+    // `push_quiet` counting `size` bytes toward `max_total_bytes`.
+    fn push_quiet_sized(
+        &self,
+        value: T,
+        size: usize,
+        stamp: impl FnOnce(&mut Envelope<T>),
+    ) -> Result<usize, SendError<T, R>> {
+        let Some(inner) = (match self.shared.config.overflow {
+            OverflowPolicy::Block => self.lock_space(1),
+            OverflowPolicy::DropNewest | OverflowPolicy::DropOldest => self.lock_open(),
+        }) else {
+            return Err(self.shared.send_error(value));
+        };
+        self.push_locked(inner, value, size, stamp)
+    }
+
+    // The rest of `push_quiet_sized` once the lock is held, with space
+    // already waited for under `OverflowPolicy::Block`.
+    fn push_locked(
+        &self,
+        inner: MutexGuard<'_, Inner<T>>,
+        value: T,
+        size: usize,
+        stamp: impl FnOnce(&mut Envelope<T>),
+    ) -> Result<usize, SendError<T, R>> {
+        let policy = self.shared.config.overflow;
+        // Declared before the guard so an evicted message drops unlocked.
+        let mut evicted = None;
+        let mut inner = inner;
+        if self.shared.at_max_queue(&inner, 1) {
+            drop(inner);
+            return Err(SendError::Overflow(value));
+        }
+        let full = !self.shared.has_space(&inner, 1);
+        // Barrier markers aren't messages and are never evicted.
+        let oldest = match policy {
+            OverflowPolicy::DropOldest if full => {
+                inner.queue.iter().position(|e| e.value.is_some())
+            }
+            _ => None,
+        };
+        // Bytes an eviction frees count toward the new message.
+        let freed = oldest.map_or(0, |at| inner.queue[at].size);
+        if !self.shared.has_bytes(size.saturating_sub(freed)) {
+            drop(inner);
+            return Err(SendError::SizeLimit(value));
+        }
+        if full {
+            let Some(oldest) = oldest else {
+                // With nothing evictable the new value is what gets dropped.
+                self.shared.metrics.record_dropped();
+                return Ok(inner.queue.len());
+            };
+            evicted = self.shared.withdraw(&mut inner, oldest);
+            self.shared.metrics.record_dropped();
+        }
+        let mut envelope = self.envelope(value);
+        stamp(&mut envelope);
+        envelope.size = size;
+        self.shared.bytes.fetch_add(size, Ordering::SeqCst);
+        let len = self.shared.enqueue_quiet(inner, envelope);
+        // The slot freed is taken again at once, but a `send_until_buffered`
+        // waiting on the evicted message is done.
+        if evicted.is_some() && self.shared.buffer_waiters.load(Ordering::SeqCst) > 0 {
+            self.shared.notify_full();
+        }
+        Ok(len)
+    }
+
+    /// Like `send`, but count the message's `size_hint` toward
+    /// `ChannelBuilder::max_total_bytes` until it's received, failing with
+    /// `SendError::SizeLimit` if it would take the total past the limit.
+    /// Messages sent any other way count as zero bytes.
+    pub fn send_sized(&self, value: T) -> Result<(), SendError<T, R>>
+    where
+        T: MessageSize,
+    {
+        let size = value.size_hint();
+        let len = self.push_quiet_sized(value, size, |_| {})?;
+        self.shared.notify_pushed(len);
+        Ok(())
+    }
+
+    /// This channel's shared state; see `SharedPtr`.
+    #[cfg(feature = "unstable")]
+    pub fn shared_ptr(&self) -> SharedPtr<T, R> {
+        SharedPtr(Arc::clone(&self.shared), PhantomData)
+    }
+
+    /// Messages sent but not yet received, as `Receiver::consumer_lag`.
+    pub fn consumer_lag(&self) -> usize {
+        self.shared.metrics.lag() as usize
+    }
+
+    /// Check the channel would still accept a message, failing as `send`
+    /// would but without sending anything, nor blocking on a full channel.
+    /// Pair with `consumer_lag` to see whether the receiver keeps up.
+    pub fn ping(&self) -> Result<(), SendError<(), R>> {
+        match self.lock_open() {
+            Some(_) => Ok(()),
+            None => Err(self.shared.send_error(())),
+        }
+    }
+
+    /// Run `f` once the channel is gone for good: every sender and receiver
+    /// dropped, including ones created later. Callbacks run in the order
+    /// they were added, on whichever thread drops the last handle.
+    pub fn on_close(&self, f: impl FnOnce() + Send + 'static) {
+        self.shared.inner.lock().on_close.push(Box::new(f));
+    }
+
+    /// Like `send`, but also block until a receiver has moved the message
+    /// out of the shared queue, into its private buffer or straight out: a
+    /// cheaper confirmation than waiting for it to be handled. Returns once
+    /// the message is gone from the queue for any reason, including the
+    /// receiver dropping it. Always blocks when full, whatever the overflow
+    /// policy.
+    pub fn send_until_buffered(&self, value: T) -> Result<(), SendError<T, R>> {
+        let Some(mut inner) = self.lock_space(1) else {
+            return Err(self.shared.send_error(value));
+        };
+        if self.shared.at_max_queue(&inner, 1) {
+            drop(inner);
+            return Err(SendError::Overflow(value));
+        }
+        let mut envelope = self.envelope(value);
+        self.shared.stamp(&mut inner, &mut envelope);
+        let seq = envelope.seq;
+        inner.queue.push_back(envelope);
+        let len = inner.queue.len();
+        self.shared.buffer_waiters.fetch_add(1, Ordering::SeqCst);
+        drop(inner);
+        self.shared.emit(ChannelEvent::MessageSent);
+        self.shared.notify_pushed(len);
+        let mut inner = self.shared.inner.lock();
+        while inner
+            .queue
+            .iter()
+            .any(|envelope| envelope.seq == seq && envelope.value.is_some())
+        {
+            inner = self.shared.full.wait(inner);
+        }
+        self.shared.buffer_waiters.fetch_sub(1, Ordering::SeqCst);
+        Ok(())
+    }
+
+    /// Send `values` one by one as a group, returning a handle to wait for
+    /// the consumer to finish with all of them: a message counts once a
+    /// plain receive hands it out, or once its `Delivery` is acked. Values
+    /// that can't be sent are dropped, and `wait_all` reports the group as
+    /// incomplete.
+    pub fn send_gather(&self, values: Vec<T>) -> GatherHandle {
+        static NEXT: AtomicU64 = AtomicU64::new(0);
+        let gather = Arc::new(Gather {
+            id: NEXT.fetch_add(1, Ordering::Relaxed),
+            state: Mutex::new(GatherState {
+                pending: values.len(),
+                lost: false,
+            }),
+            settled: Condvar::default(),
+        });
+        for value in values {
+            let ack = GatherAck {
+                gather: Arc::clone(&gather),
+                acked: false,
+            };
+            // A failed send drops `ack` with the closure, unacked.
+            let _ = self.push(value, move |envelope| envelope.gather = Some(ack));
+        }
+        GatherHandle { gather }
+    }
+
+    /// Send `value` as the end of the stream: the receiver gets every
+    /// earlier message, then this one, then `None`, even while other senders
+    /// are still alive. Their further sends fail.
+    pub fn send_last(&self, value: T) -> Result<(), SendError<T, R>> {
+        let Some(mut inner) = self.lock_space(1) else {
+            return Err(self.shared.send_error(value));
+        };
+        if self.shared.at_max_queue(&inner, 1) {
+            drop(inner);
+            return Err(SendError::Overflow(value));
+        }
+        let mut envelope = self.envelope(value);
+        self.shared.stamp(&mut inner, &mut envelope);
+        inner.queue.push_back(envelope);
+        self.shared.close_senders_locked(inner);
+        self.shared.emit(ChannelEvent::MessageSent);
+        Ok(())
+    }
+
+    /// Claim `n` slots of a bounded channel up front, blocking until they
+    /// are free, so the returned reservation can send that many messages
+    /// without waiting. Returns `None` if the channel is disconnected or `n`
+    /// exceeds its capacity less any `headroom`. Unused slots are released
+    /// when it drops.
+    pub fn reserve(&self, n: usize) -> Option<Reservation<'_, T, R>> {
+        let mut inner = self.lock_open()?;
+        // Rechecked after every wait, as `Receiver::set_capacity` may have
+        // shrunk the channel meanwhile.
+        while !self.shared.has_space(&inner, n) {
+            if inner
+                .capacity
+                .is_some_and(|capacity| n > capacity.saturating_sub(self.shared.config.headroom))
+            {
+                return None;
+            }
+            inner = self.check_open(self.shared.full.wait(inner))?;
+        }
+        inner.reserved += n;
+        Some(Reservation {
+            sender: self,
+            remaining: n,
+        })
+    }
+
+    /// Like `reserve`, but return `None` straight away instead of blocking
+    /// when fewer than `n` slots are free.
+    pub fn try_reserve(&self, n: usize) -> Option<Reservation<'_, T, R>> {
+        let mut inner = self.lock_open()?;
+        if !self.shared.has_space(&inner, n) {
+            return None;
+        }
+        inner.reserved += n;
+        Some(Reservation {
+            sender: self,
+            remaining: n,
+        })
+    }
+
+    /// Lock the shared state if the channel still accepts messages.
+    fn lock_open(&self) -> Option<MutexGuard<'_, Inner<T>>> {
+        self.check_open(self.shared.inner.lock())
+    }
+
+    /// Like `lock_open`, but on a bounded channel also block until at least
+    /// `n` slots are free.
+    fn lock_space(&self, n: usize) -> Option<MutexGuard<'_, Inner<T>>> {
+        let mut inner = self.lock_open()?;
+        while !self.shared.has_space(&inner, n) {
+            inner = self.check_open(self.shared.full.wait(inner))?;
+        }
+        Some(inner)
+    }
+
+    fn check_open<'a>(
+        &'a self,
+        inner: MutexGuard<'a, Inner<T>>,
+    ) -> Option<MutexGuard<'a, Inner<T>>> {
+        if inner.rx_count == 0 {
+            drop(inner);
+            self.shared.receiver_gone();
+            return None;
+        }
+        if inner.tx_closed {
+            return None;
+        }
+        Some(inner)
+    }
+
+    /// Append every value under a single lock acquisition and wake the
+    /// receiver once. A bounded channel takes the batch in as many chunks as
+    /// its capacity requires. On disconnect, or once the queue reaches
+    /// `max_queue`, the values not yet sent are handed back.
+    pub fn send_all(&self, values: Vec<T>) -> Result<(), SendError<Vec<T>, R>> {
+        if values.is_empty() {
+            return match self.lock_open() {
+                Some(_) => Ok(()),
+                None => Err(self.shared.send_error(values)),
+            };
+        }
+        let mut values = values.into_iter();
+        while values.len() > 0 {
+            let Some(mut inner) = self.lock_space(1) else {
+                return Err(self.shared.send_error(values.collect()));
+            };
+            let room = self.shared.max_queue_room(&inner);
+            if room == 0 {
+                drop(inner);
+                return Err(SendError::Overflow(values.collect()));
+            }
+            let n = self.shared.free_slots(&inner).min(room).min(values.len());
+            for value in values.by_ref().take(n) {
+                let mut envelope = self.envelope(value);
+                self.shared.stamp(&mut inner, &mut envelope);
+                inner.queue.push_back(envelope);
+            }
+            drop(inner);
+            self.shared.emit_n(ChannelEvent::MessageSent, n);
+            self.shared.notify_receiver();
+        }
+        Ok(())
+    }
+
+    /// Send as many of `values` as fit right now, in order and under a
+    /// single lock, handing back the ones that didn't as `Ok`: empty when
+    /// all of them went in. Never blocks; like `send_if_space`, an unbounded
+    /// channel has room until it reaches `max_queue`.
+    pub fn send_batch_partial(&self, values: Vec<T>) -> Result<Vec<T>, SendError<Vec<T>, R>> {
+        let Some(mut inner) = self.lock_open() else {
+            return Err(self.shared.send_error(values));
+        };
+        let room = self
+            .shared
+            .free_slots(&inner)
+            .min(self.shared.max_queue_room(&inner));
+        let mut values = values.into_iter();
+        let n = room.min(values.len());
+        for value in values.by_ref().take(n) {
+            let mut envelope = self.envelope(value);
+            self.shared.stamp(&mut inner, &mut envelope);
+            inner.queue.push_back(envelope);
+        }
+        drop(inner);
+        self.shared.emit_n(ChannelEvent::MessageSent, n);
+        self.shared.notify_receiver();
+        Ok(values.collect())
+    }
+
+    /// Like `send_all`, sending `every` values per lock acquisition and
+    /// calling `progress` with the running total after each such chunk.
+    pub fn send_all_progress(
+        &self,
+        values: Vec<T>,
+        every: usize,
+        mut progress: impl FnMut(usize),
+    ) -> Result<(), SendError<Vec<T>, R>> {
+        let every = every.max(1);
+        let mut values = values.into_iter();
+        let mut sent = 0;
+        while values.len() > 0 {
+            let chunk: Vec<T> = values.by_ref().take(every).collect();
+            let n = chunk.len();
+            self.send_all(chunk).map_err(|err| {
+                err.map(|mut rest| {
+                    rest.extend(values.by_ref());
+                    rest
+                })
+            })?;
+            sent += n;
+            progress(sent);
+        }
+        Ok(())
+    }
+
+    /// First half of a clean shutdown: from now on every sender's sends fail
+    /// with `SendError::Closed` carrying `Shutdown` (as the channel's `R`),
+    /// while the messages already queued stay deliverable. The receiver
+    /// finishes with `Receiver::drain_remaining`.
+    pub fn begin_shutdown(&self)
+    where
+        R: From<Shutdown>,
+    {
+        self.shared.close_senders_with(R::from(Shutdown));
+    }
+
+    /// Block until the receiver has taken every message that was sent, by
+    /// any sender, before this call. Returns early if the receiver drops.
+    pub fn barrier(&self) -> Result<(), SendError<(), R>> {
+        let marker = Arc::new(BarrierMarker::default());
+        let Some(mut inner) = self.lock_open() else {
+            return Err(self.shared.send_error(()));
+        };
+        inner.queue.push_back(Envelope {
+            value: None,
+            seq: 0,
+            sender: self.id,
+            claimed: None,
+            expires: None,
+            barrier: Some(BarrierRelease(Arc::clone(&marker))),
+            gather: None,
+            size: 0,
+            #[cfg(feature = "tracing")]
+            span: None,
+        });
+        drop(inner);
+        self.shared.notify_receiver();
+        let mut released = marker.released.lock();
+        while !*released {
+            released = marker.cond.wait(released);
+        }
+        Ok(())
+    }
+}
+
+impl<Req, Resp, R: CloseReason> Sender<(Req, Responder<Resp>), R> {
+    /// Send `request` together with a fresh reply slot and block until the
+    /// consumer answers it through the `Responder`. `None` if the request
+    /// couldn't be sent or its responder was dropped unanswered.
+    pub fn call(&self, request: Req) -> Option<Resp> {
+        let (tx, mut rx) = channel();
+        self.send((request, Responder { tx })).ok()?;
+        rx.recv()
+    }
+}
+
+impl<K: PartialEq, V, R: CloseReason> Sender<(K, V), R> {
+    /// Conflate consecutive updates: if the newest message still in the
+    /// shared queue has the same `key`, replace its value in place, keeping
+    /// its position; otherwise send `(key, value)` as usual. Only that one
+    /// message is checked, so an older one with the same key further up the
+    /// queue, or already buffered by the receiver, is still delivered.
+    ///
+    /// The check and the send happen under one lock, so a concurrent update
+    /// with the same key can't slip in between. A replaced message takes on
+    /// this sender's id and a fresh sequence number, as a new send would.
+    pub fn send_conflate_key(&self, key: K, value: V) -> Result<(), SendError<(K, V), R>> {
+        let blocking = self.shared.config.overflow == OverflowPolicy::Block;
+        let mut locked = self.lock_open();
+        loop {
+            let Some(mut guard) = locked else {
+                return Err(self.shared.send_error((key, value)));
+            };
+            let inner = &mut *guard;
+            let tail = inner
+                .queue
+                .back_mut()
+                .filter(|envelope| envelope.is_live())
+                .filter(|envelope| envelope.value.as_ref().is_some_and(|(k, _)| *k == key));
+            if let Some(envelope) = tail {
+                let old = envelope.value.replace((key, value));
+                envelope.sender = self.id;
+                envelope.seq = inner.next_seq;
+                inner.next_seq += 1;
+                drop(guard);
+                drop(old);
+                return Ok(());
+            }
+            // Waiting for space lets other sends in, so the tail is checked
+            // again afterwards.
+            if !blocking || self.shared.has_space(&guard, 1) {
+                let len = self.push_locked(guard, (key, value), 0, |_| {})?;
+                self.shared.notify_pushed(len);
+                return Ok(());
+            }
+            locked = self.check_open(self.shared.full.wait(guard));
+        }
+    }
 }
 
-impl<T> Sender<T> {
-    fn send(&self, value: T) -> anyhow::Result<()> {
-        self.shared.inner.lock().unwrap().queue.push_back(value);
-        self.shared.avaliable.notify_one();
+/// The reply slot handed to the consumer alongside each `call` request.
+pub struct Responder<Resp> {
+    tx: Sender<Resp>,
+}
+
+impl<Resp> Responder<Resp> {
+    pub fn respond(self, response: Resp) {
+        // The caller stays blocked on the reply until this responder is used
+        // or dropped, so the send can't find its receiver gone.
+        let _ = self.tx.send(response);
+    }
+}
+
+impl<T: Send + 'static, R: CloseReason> Sender<T, R> {
+    /// A sender whose sends wake the receiver at most once per `interval`
+    /// rather than on every message, cutting wakeups under high send rates
+    /// at the cost of up to `interval` extra latency. A background timer
+    /// delivers the wakeup for a burst's tail.
+    pub fn with_auto_flush(&self, interval: Duration) -> AutoFlush<T, R> {
+        let state = Arc::new(FlushState {
+            pending: AtomicBool::new(false),
+            last: Mutex::new(Instant::now()),
+            stopped: Mutex::new(false),
+            stop: Condvar::default(),
+            flushes: AtomicUsize::new(0),
+        });
+        let timer = {
+            let state = Arc::clone(&state);
+            let shared = Arc::downgrade(&self.shared);
+            std::thread::spawn(move || loop {
+                let mut stopped = state.stopped.lock();
+                // Checked before waiting too, or a drop that got in before
+                // this thread first locked would only be noticed after a
+                // whole `interval`.
+                if !*stopped {
+                    (stopped, _) = state.stop.wait_timeout(stopped, interval);
+                }
+                if *stopped {
+                    return;
+                }
+                drop(stopped);
+                match shared.upgrade() {
+                    Some(shared) => state.flush(&shared),
+                    None => return,
+                }
+            })
+        };
+        AutoFlush {
+            tx: self.clone(),
+            interval,
+            state,
+            timer: Some(timer),
+        }
+    }
+}
+
+pub struct AutoFlush<T, R = Shutdown> {
+    tx: Sender<T, R>,
+    interval: Duration,
+    state: Arc<FlushState>,
+    timer: Option<std::thread::JoinHandle<()>>,
+}
+
+struct FlushState {
+    // Messages were queued since the last wakeup.
+    pending: AtomicBool,
+    last: Mutex<Instant>,
+    stopped: Mutex<bool>,
+    stop: Condvar,
+    flushes: AtomicUsize,
+}
+
+impl FlushState {
+    fn flush<T>(&self, shared: &Shared<T>) {
+        if self.pending.swap(false, Ordering::AcqRel) {
+            *self.last.lock() = Instant::now();
+            self.flushes.fetch_add(1, Ordering::Relaxed);
+            shared.notify_receiver();
+        }
+    }
+}
+
+impl<T, R: CloseReason> AutoFlush<T, R> {
+    pub fn send(&self, value: T) -> Result<(), SendError<T, R>> {
+        self.tx.push_quiet(value, |_| {})?;
+        self.state.pending.store(true, Ordering::Release);
+        if self.state.last.lock().elapsed() >= self.interval {
+            self.state.flush(&self.tx.shared);
+        }
+        Ok(())
+    }
+}
+
+impl<T, R> Drop for AutoFlush<T, R> {
+    fn drop(&mut self) {
+        *self.state.stopped.lock() = true;
+        self.state.stop.notify_all();
+        if let Some(timer) = self.timer.take() {
+            let _ = timer.join();
+        }
+        // Staged messages no wakeup has announced yet would otherwise sit
+        // unseen until another sender happened to wake the receiver.
+        self.state.flush(&self.tx.shared);
+    }
+}
+
+pub struct Reservation<'a, T, R = Shutdown> {
+    sender: &'a Sender<T, R>,
+    remaining: usize,
+}
+
+impl<T, R: CloseReason> Reservation<'_, T, R> {
+    /// Send into one of the reserved slots. Fails once all of them are used.
+    pub fn send(&mut self, value: T) -> Result<(), SendError<T, R>> {
+        if self.remaining == 0 {
+            return Err(SendError::Overflow(value));
+        }
+        let Some(mut inner) = self.sender.lock_open() else {
+            return Err(self.sender.shared.send_error(value));
+        };
+        inner.reserved -= 1;
+        self.remaining -= 1;
+        self.sender
+            .shared
+            .enqueue(inner, self.sender.envelope(value));
         Ok(())
     }
+
+    pub fn remaining(&self) -> usize {
+        self.remaining
+    }
+}
+
+impl<T, R> Drop for Reservation<'_, T, R> {
+    fn drop(&mut self) {
+        if self.remaining == 0 {
+            return;
+        }
+        let mut inner = self.sender.shared.inner.lock();
+        inner.reserved -= self.remaining;
+        drop(inner);
+        self.sender.shared.notify_full();
+    }
+}
+
+impl<T, R> Sender<T, R> {
+    /// Detach the sender from scope tracking: it keeps counting as a live
+    /// sender, even past the `LeakedSender` being dropped, until reclaimed.
+    pub fn leak(self) -> LeakedSender<T, R> {
+        LeakedSender(ManuallyDrop::new(self))
+    }
+}
+
+/// A sender that no longer disconnects on drop; see `Sender::leak`.
+pub struct LeakedSender<T, R = Shutdown>(ManuallyDrop<Sender<T, R>>);
+
+impl<T, R> LeakedSender<T, R> {
+    pub fn reclaim(self) -> Sender<T, R> {
+        ManuallyDrop::into_inner(self.0)
+    }
+
+    /// An opaque pointer to hand across FFI, good for one `from_raw`, which
+    /// gives the sender a fresh id.
+    pub fn into_raw(self) -> *const c_void {
+        let sender = ManuallyDrop::into_inner(self.0);
+        let sender = ManuallyDrop::new(sender);
+        // SAFETY: `sender` is never dropped, so its `Arc` is moved out once.
+        let shared = unsafe { std::ptr::read(&sender.shared) };
+        Arc::into_raw(shared).cast()
+    }
+
+    /// # Safety
+    ///
+    /// `ptr` must come from `into_raw` on a `LeakedSender<T>` of the same
+    /// `T`, and not have been passed to `from_raw` already.
+    pub unsafe fn from_raw(ptr: *const c_void) -> Self {
+        let shared = Arc::from_raw(ptr.cast::<Shared<T>>());
+        let id = shared.inner.lock().next_sender_id();
+        Sender {
+            shared,
+            id,
+            reason: PhantomData,
+        }
+        .leak()
+    }
 }
 
-impl<T> Clone for Sender<T> {
+impl<T, R> Clone for Sender<T, R> {
     fn clone(&self) -> Self {
-        let mut inner = self.shared.inner.lock().unwrap();
+        let mut inner = self.shared.inner.lock();
         inner.tx_count += 1;
+        let id = inner.next_sender_id();
         drop(inner);
+        self.shared.emit(ChannelEvent::SenderCloned);
         Sender {
             shared: Arc::clone(&self.shared),
+            id,
+            reason: PhantomData,
         }
     }
 }
 
-impl<T> Drop for Sender<T> {
+impl<T, R> Debug for Sender<T, R> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Sender")
+            .field("name", &self.shared.config.name)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T, R> Drop for Sender<T, R> {
     fn drop(&mut self) {
-        eprintln!("drop Sender");
-        let mut inner = self.shared.inner.lock().unwrap();
-        eprintln!("drop Sender2");
+        let mut inner = self.shared.inner.lock();
         inner.tx_count -= 1;
-        eprintln!("decrease tx_count");
+        let disconnected = inner.tx_count == 0 && inner.rx_count > 0;
         if inner.tx_count == 0 {
-            self.shared.avaliable.notify_one();
+            self.shared.avaliable.notify_all();
+            Shared::disconnect(inner);
+        } else {
+            drop(inner);
+        }
+        self.shared.emit(ChannelEvent::SenderDropped);
+        if disconnected {
+            self.shared.emit(ChannelEvent::Disconnected);
         }
     }
 }
 
-struct Receiver<T> {
+pub struct Receiver<T, R = Shutdown> {
     shared: Arc<Shared<T>>,
-    buffer: VecDeque<T>,
+    reason: PhantomData<fn() -> R>,
+    buffer: VecDeque<Envelope<T>>,
+    // Buffered messages handed out since the last `yield_every` yield.
+    since_yield: usize,
+    #[cfg(test)]
+    yields: usize,
+    // Where `wait_envelope` found the last envelope it returned.
+    origin: Origin,
+    // The shared state under the lock that took the last envelope, or
+    // `None` if it came off the buffer without locking.
+    at_take: Option<AtTake>,
+    // When the last `RATE_SAMPLES` envelopes were taken, oldest first.
+    recent: VecDeque<Instant>,
+    // Set while this receiver is parked in a wait.
+    parked: Arc<AtomicBool>,
+    boost: Boost,
+    // Channels swapped in by `replace_channel` so far.
+    generation: u64,
 }
 
-impl<T> Receiver<T> {
-    fn recv(&mut self) -> Option<T> {
-        if let v @ Some(_) = self.buffer.pop_front() {
-            return v;
-        }
-        let mut inner = self.shared.inner.lock().unwrap();
-        loop {
-            match inner.queue.pop_front() {
-                v @ Some(_) => {
-                    std::mem::swap(&mut self.buffer, &mut inner.queue);
-                    return v;
-                }
-                None if inner.tx_count == 0 => return None,
-                None => {
-                    inner = self.shared.avaliable.wait(inner).unwrap();
-                }
-            }
-        }
-    }
+// What `recv_with_depth` and `recv_with_senders` report.
+#[derive(Clone, Copy)]
+struct AtTake {
+    queued: usize,
+    senders: usize,
 }
 
-struct Shared<T> {
-    inner: Mutex<Inner<T>>,
-    avaliable: Condvar,
+impl AtTake {
+    fn of<T>(inner: &Inner<T>) -> Self {
+        AtTake {
+            queued: inner.queue.len(),
+            senders: inner.tx_count,
+        }
+    }
 }
 
-#[derive(Default)]
-struct Inner<T> {
-    queue: VecDeque<T>,
-    tx_count: usize,
+// Where `backlog_priority_boost` stands for a receiver.
+enum Boost {
+    Off,
+    // `thread`'s nice value was lowered from `nice`.
+    On {
+        thread: std::thread::ThreadId,
+        nice: i32,
+    },
+    // A boost failed, most likely for lack of privileges; not retried.
+    Unavailable,
 }
 
-fn channel<T: Default>() -> (Sender<T>, Receiver<T>) {
-    let shared = Arc::new(Shared {
-        inner: Mutex::new(Inner {
-            queue: VecDeque::default(),
-            tx_count: 1,
-        }),
-        avaliable: Condvar::default(),
-    });
-    (
-        Sender {
-            shared: Arc::clone(&shared),
-        },
+impl<T, R> Receiver<T, R> {
+    fn new(shared: Arc<Shared<T>>) -> Self {
         Receiver {
             shared,
+            reason: PhantomData,
             buffer: VecDeque::default(),
-        },
-    )
-}
+            since_yield: 0,
+            #[cfg(test)]
+            yields: 0,
+            origin: Origin::Buffer,
+            at_take: None,
+            recent: VecDeque::default(),
+            parked: Arc::default(),
+            boost: Boost::Off,
+            generation: 0,
+        }
+    }
 
-#[cfg(test)]
-mod test {
+    pub fn recv(&mut self) -> Option<T> {
+        self.recv_sequenced().map(|(_, value)| value)
+    }
 
-    use super::*;
+    /// Like `recv`, but give up with `RecvError::Timeout` once blocked for
+    /// `ChannelBuilder::max_block`. `recv` itself never times out.
+    pub fn recv_checked(&mut self) -> Result<T, RecvError> {
+        loop {
+            let envelope = self.next_envelope_checked()?;
+            if let Some(value) = envelope.open(&self.shared) {
+                return Ok(value);
+            }
+        }
+    }
 
-    #[test]
-    fn test_rx_tx() -> anyhow::Result<()> {
-        let (tx, mut rx) = channel();
-        let _ = tx.send(1);
-        assert_eq!(rx.recv(), Some(1));
-        Ok(())
+    fn recv_sequenced(&mut self) -> Option<(u64, T)> {
+        let (seq, _, value) = self.recv_stamped()?;
+        Some((seq, value))
     }
 
-    #[test]
-    fn test_rx_tx_multi_threads() -> anyhow::Result<()> {
-        let (tx, mut rx) = channel();
-        const CYCLE: usize = 1000000;
-        for _ in 0..CYCLE {
-            let tx = tx.clone();
-            rayon::spawn(move || {
-                let _ = tx.send(1);
-            });
+    fn recv_stamped(&mut self) -> Option<(u64, SenderId, T)> {
+        loop {
+            let envelope = self.next_envelope()?;
+            let (seq, sender) = (envelope.seq, envelope.sender);
+            if let Some(value) = envelope.open(&self.shared) {
+                return Some((seq, sender, value));
+            }
         }
-        let jh = std::thread::spawn(move || {
-            let mut count = 0;
-            while let Some(v) = rx.recv() {
-                count += v;
+    }
+
+    /// Block until a message is ready, returning `true` without taking it,
+    /// or `false` once disconnected. The message stays first in line for
+    /// whichever receive comes next.
+    pub fn wait_ready(&mut self) -> bool {
+        let parked: Result<_, Infallible> =
+            self.wait_ready_with(|shared, inner| Ok(shared.avaliable.wait(inner)));
+        match parked {
+            Ok(ready) => ready,
+        }
+    }
+
+    // Like `wait_ready`, but give up with `Interrupted` once `interrupt` is
+    // set, polled as in `recv_interruptible`.
+    #[cfg(feature = "async-bridge")]
+    pub(crate) fn wait_ready_interruptible(
+        &mut self,
+        interrupt: &AtomicBool,
+    ) -> Result<bool, Interrupted> {
+        self.wait_ready_with(|shared, inner| {
+            if interrupt.load(Ordering::Acquire) {
+                return Err(Interrupted);
             }
-            assert_eq!(count, CYCLE);
-        });
-        drop(tx);
-        let _ = jh.join();
-        Ok(())
+            let (inner, _) = shared.avaliable.wait_timeout(inner, INTERRUPT_POLL);
+            Ok(inner)
+        })
     }
 
-    #[test]
-    fn test_drop_rx() {
-        let (tx, mut rx) = channel::<i32>();
-        drop(tx);
-        let _ = rx.recv();
+    fn wait_ready_with<E>(
+        &mut self,
+        mut wait: impl for<'a> FnMut(
+            &'a Shared<T>,
+            MutexGuard<'a, Inner<T>>,
+        ) -> Result<MutexGuard<'a, Inner<T>>, E>,
+    ) -> Result<bool, E> {
+        loop {
+            // Not `next_envelope`: peeking isn't a receive for `recv_rate`.
+            let Some(envelope) = self.take_envelope(&mut wait)? else {
+                return Ok(false);
+            };
+            if envelope.is_live() {
+                self.buffer.push_front(envelope);
+                return Ok(true);
+            }
+            // Cancelled, expired or a barrier marker: nothing to wait for.
+            drop(envelope.open(&self.shared));
+        }
     }
 
-    #[test]
-    #[should_panic]
-    fn test_drop_tx() {
-        let (tx, rx) = channel::<i32>();
-        drop(rx);
+    /// Like `recv`, but `default` once disconnected instead of `None`.
+    pub fn recv_or(&mut self, default: T) -> T {
+        self.recv().unwrap_or(default)
+    }
+
+    /// Like `recv_or`, computing the default only when it's needed.
+    pub fn recv_or_else(&mut self, f: impl FnOnce() -> T) -> T {
+        self.recv().unwrap_or_else(f)
+    }
+
+    /// Block for a message like `recv`, then hand it to `f` along with
+    /// `ctx`. `ctx` is dropped unused once the channel is closed and empty.
+    pub fn recv_map_ctx<C, U>(&mut self, ctx: C, f: impl FnOnce(C, T) -> U) -> Option<U> {
+        self.recv().map(|value| f(ctx, value))
+    }
+
+    /// Receive `expected.len()` messages, checking each against `expected`
+    /// in turn. Stops at the first that differs, having consumed it.
+    pub fn recv_expect(&mut self, expected: &[T]) -> Result<(), SequenceError<T>>
+    where
+        T: PartialEq + Clone,
+    {
+        for (index, wanted) in expected.iter().enumerate() {
+            match self.recv() {
+                Some(got) if got == *wanted => {}
+                Some(got) => {
+                    return Err(SequenceError::Mismatch {
+                        index,
+                        got,
+                        wanted: wanted.clone(),
+                    })
+                }
+                None => return Err(SequenceError::Disconnected { index }),
+            }
+        }
+        Ok(())
+    }
+
+    /// Like `recv`, but also report which sender sent the message.
+    pub fn recv_tagged(&mut self) -> Option<(SenderId, T)> {
+        let (_, sender, value) = self.recv_stamped()?;
+        Some((sender, value))
+    }
+
+    /// Like `recv`, but also enter the span the message was sent under, for
+    /// as long as the returned guard lives. Messages not sent with
+    /// `send_traced` come with a disabled span.
+    #[cfg(feature = "tracing")]
+    pub fn recv_traced(&mut self) -> Option<(T, tracing::span::EnteredSpan)> {
+        loop {
+            let mut envelope = self.next_envelope()?;
+            let span = envelope.span.take().unwrap_or_else(tracing::Span::none);
+            if let Some(value) = envelope.open(&self.shared) {
+                return Some((value, span.entered()));
+            }
+        }
+    }
+
+    /// Receive until `f` maps a message to `Some`, discarding the ones it
+    /// maps to `None`. `None` once disconnected.
+    pub fn recv_filter_map<U>(&mut self, mut f: impl FnMut(T) -> Option<U>) -> Option<U> {
+        loop {
+            if let v @ Some(_) = f(self.recv()?) {
+                return v;
+            }
+        }
+    }
+
+    /// Receive until disconnected, running `f` on every message across the
+    /// global rayon pool. The calling thread does the receiving and only
+    /// hands messages out, so it never ties up a pool thread. Returns once
+    /// every message has been processed.
+    pub fn par_for_each(mut self, f: impl Fn(T) + Sync + Send)
+    where
+        T: Send,
+    {
+        let f = &f;
+        rayon::in_place_scope(|scope| {
+            while let Some(value) = self.recv() {
+                scope.spawn(move |_| f(value));
+            }
+        });
+    }
+
+    /// Receive until disconnected, combining every message into `init`
+    /// with `f`.
+    pub fn fold<B>(mut self, init: B, mut f: impl FnMut(B, T) -> B) -> B {
+        let mut acc = init;
+        while let Some(value) = self.recv() {
+            acc = f(acc, value);
+        }
+        acc
+    }
+
+    /// Like `recv`, but also report which path the message came through, for
+    /// profiling how often the buffer fast path hits.
+    pub fn recv_origin(&mut self) -> Option<(T, Origin)> {
+        let (_, value) = self.recv_sequenced()?;
+        Some((value, self.origin))
+    }
+
+    /// Iterate over received messages paired with the sequence id the
+    /// channel assigned at send time. Ids count every message sent, so ones
+    /// that were cancelled or expired show up as gaps.
+    pub fn enumerate_recv(&mut self) -> EnumerateRecv<'_, T, R> {
+        EnumerateRecv { receiver: self }
+    }
+
+    /// Iterate without ever blocking, telling a message apart from an empty
+    /// channel with senders alive (`Idle`) and from a disconnected one
+    /// (`Closed`). Ends after yielding `Closed`.
+    pub fn recv_events(&mut self) -> RecvEvents<'_, T, R> {
+        RecvEvents {
+            receiver: self,
+            closed: false,
+        }
+    }
+
+    fn next_envelope(&mut self) -> Option<Envelope<T>> {
+        let parked: Result<_, Infallible> =
+            self.wait_envelope(|shared, inner| Ok(shared.avaliable.wait(inner)));
+        match parked {
+            Ok(envelope) => envelope,
+        }
+    }
+
+    fn next_envelope_checked(&mut self) -> Result<Envelope<T>, RecvError> {
+        let deadline = self.shared.block_deadline();
+        self.wait_envelope(|shared, inner| shared.wait_until(inner, deadline))?
+            .ok_or(RecvError::Disconnected)
+    }
+
+    // Pop the next envelope, calling `wait` whenever the queue is empty but
+    // senders remain. `wait` parks on the condvar and may give up with `Err`.
+    fn wait_envelope<E>(
+        &mut self,
+        wait: impl for<'a> FnMut(
+            &'a Shared<T>,
+            MutexGuard<'a, Inner<T>>,
+        ) -> Result<MutexGuard<'a, Inner<T>>, E>,
+    ) -> Result<Option<Envelope<T>>, E> {
+        let envelope = self.take_envelope(wait)?;
+        if envelope.is_some() {
+            if self.recent.len() == RATE_SAMPLES {
+                self.recent.pop_front();
+            }
+            self.recent.push_back(Instant::now());
+            self.adjust_priority();
+        }
+        Ok(envelope)
+    }
+
+    // Boost the receiving thread once `backlog_priority_boost` messages are
+    // waiting behind the one just taken, and restore it once none are.
+    fn adjust_priority(&mut self) {
+        let Some(threshold) = self.shared.config.boost_threshold else {
+            return;
+        };
+        // The envelope just taken isn't opened yet, so it's still counted.
+        let waiting = self.shared.metrics.lag().saturating_sub(1) as usize;
+        match self.boost {
+            Boost::Off if waiting >= threshold => {
+                self.boost = match thread_priority::current() {
+                    Some(nice) if thread_priority::set((nice - PRIORITY_BOOST).max(-20)) => {
+                        Boost::On {
+                            thread: std::thread::current().id(),
+                            nice,
+                        }
+                    }
+                    _ => Boost::Unavailable,
+                };
+            }
+            Boost::On { .. } if waiting == 0 => self.restore_priority(),
+            _ => {}
+        }
+    }
+
+    fn restore_priority(&mut self) {
+        if let Boost::On { thread, nice } = self.boost {
+            // Moved to another thread meanwhile, the boosted one is out of
+            // reach and stays boosted.
+            if thread == std::thread::current().id() {
+                thread_priority::set(nice);
+            }
+            self.boost = Boost::Off;
+        }
+    }
+
+    /// Whether `backlog_priority_boost` currently has this receiver's
+    /// thread running at a raised priority.
+    pub fn priority_boosted(&self) -> bool {
+        matches!(self.boost, Boost::On { .. })
+    }
+
+    fn take_envelope<E>(
+        &mut self,
+        mut wait: impl for<'a> FnMut(
+            &'a Shared<T>,
+            MutexGuard<'a, Inner<T>>,
+        ) -> Result<MutexGuard<'a, Inner<T>>, E>,
+    ) -> Result<Option<Envelope<T>>, E> {
+        if !self.shared.redelivered.load(Ordering::Acquire)
+            && self.shared.paused.load(Ordering::SeqCst) == 0
+        {
+            if let v @ Some(_) = self.buffer.pop_front() {
+                self.origin = Origin::Buffer;
+                self.at_take = None;
+                self.yield_if_due();
+                return Ok(v);
+            }
+        }
+        let mut inner = self.shared.inner.lock();
+        self.origin = Origin::Queue;
+        let mut spins = 0;
+        // Hints to spin before the next `spin_backoff` re-check, and the most.
+        let mut backoff = self.shared.config.spin_backoff;
+        loop {
+            // Every pass, as the lock may have been released meanwhile: a
+            // `push_ahead` made while parked must still go first, and be
+            // moved out with its count rather than popped from under it.
+            self.shared.take_redelivered(&mut inner, &mut self.buffer);
+            // A paused receiver parks even with messages waiting; resuming
+            // wakes it like a send would.
+            let paused = self.shared.paused.load(Ordering::SeqCst) > 0;
+            if paused && !(self.buffer.is_empty() && inner.queue.is_empty()) {
+                let parked = self.shared.park(&self.parked, inner, &mut wait);
+                self.origin = Origin::AfterWait;
+                inner = parked?;
+                continue;
+            }
+            if let v @ Some(_) = self.buffer.pop_front() {
+                self.at_take = Some(AtTake::of(&inner));
+                return Ok(v);
+            }
+            match inner.queue.pop_front() {
+                v @ Some(_) => {
+                    if self.shared.bulk_buffer(&inner) {
+                        // Appending into capacity the buffer already has
+                        // keeps both allocations where they are; only a batch
+                        // too big for it trades the allocations instead.
+                        if let Some(max) = self.shared.config.max_buffer {
+                            let n = max.min(inner.queue.len());
+                            self.buffer.extend(inner.queue.drain(..n));
+                        } else if inner.queue.len() <= self.buffer.capacity() {
+                            self.buffer.append(&mut inner.queue);
+                        } else {
+                            std::mem::swap(&mut self.buffer, &mut inner.queue);
+                        }
+                    }
+                    if self.shared.buffer_waiters.load(Ordering::SeqCst) > 0 {
+                        self.shared.notify_full();
+                    } else if inner.capacity.is_some() {
+                        self.shared.notify_space(&mut inner);
+                    }
+                    self.at_take = Some(AtTake::of(&inner));
+                    return Ok(v);
+                }
+                None if inner.tx_count == 0 || inner.tx_closed => {
+                    inner.disconnect_seen = true;
+                    return Ok(None);
+                }
+                None if spins < self.shared.config.spin => {
+                    spins += 1;
+                    drop(inner);
+                    std::hint::spin_loop();
+                    inner = self.shared.inner.lock();
+                }
+                None if backoff.is_some() => {
+                    let (hints, max) = backoff.unwrap();
+                    drop(inner);
+                    for _ in 0..hints {
+                        std::hint::spin_loop();
+                    }
+                    backoff = (hints < max).then(|| (hints.saturating_mul(2).min(max), max));
+                    inner = self.shared.inner.lock();
+                }
+                None => {
+                    let parked = self.shared.park(&self.parked, inner, &mut wait);
+                    self.origin = Origin::AfterWait;
+                    inner = parked?;
+                }
+            }
+        }
+    }
+
+    fn yield_if_due(&mut self) {
+        let every = self.shared.config.yield_every;
+        if every == 0 {
+            return;
+        }
+        self.since_yield += 1;
+        if self.since_yield >= every {
+            self.since_yield = 0;
+            #[cfg(test)]
+            {
+                self.yields += 1;
+            }
+            std::thread::yield_now();
+        }
+    }
+
+    /// Block for at least one message, then fill as much of `out` as is
+    /// ready without blocking again. Returns `n`: `out[..n]` is initialized
+    /// and owned by the caller from then on. `0` only once disconnected, or
+    /// for an empty `out`.
+    pub fn recv_into_slice(&mut self, out: &mut [MaybeUninit<T>]) -> usize {
+        let Some(first) = out.first_mut() else {
+            return 0;
+        };
+        let Some(value) = self.recv() else {
+            return 0;
+        };
+        first.write(value);
+        let mut n = 1;
+        for slot in &mut out[1..] {
+            let Some(Some(value)) = self.recv_ready() else {
+                break;
+            };
+            slot.write(value);
+            n += 1;
+        }
+        n
+    }
+
+    /// Block for at least one message, then take up to `max` of those ready
+    /// without blocking again as a single boxed slice. `None` once
+    /// disconnected; an empty slice straight away for a `max` of `0`.
+    pub fn recv_chunk(&mut self, max: usize) -> Option<Box<[T]>> {
+        if max == 0 {
+            return Some(Box::new([]));
+        }
+        let mut chunk = Vec::with_capacity(max);
+        chunk.push(self.recv()?);
+        while chunk.len() < max {
+            let Some(Some(value)) = self.recv_ready() else {
+                break;
+            };
+            chunk.push(value);
+        }
+        Some(chunk.into_boxed_slice())
+    }
+
+    /// Block for at least one message, then take every other one ready
+    /// right now too: `recv_chunk` without a limit. `None` once
+    /// disconnected.
+    pub fn recv_burst(&mut self) -> Option<Vec<T>> {
+        let mut burst = vec![self.recv()?];
+        self.try_recv_many(usize::MAX, &mut burst);
+        Some(burst)
+    }
+
+    /// Append up to `max` of the messages ready right now to `out` without
+    /// ever blocking, returning how many. `0` when nothing is ready, whether
+    /// or not the channel is disconnected.
+    pub fn try_recv_many(&mut self, max: usize, out: &mut Vec<T>) -> usize {
+        let mut n = 0;
+        while n < max {
+            let Some(Some(value)) = self.recv_ready() else {
+                break;
+            };
+            out.push(value);
+            n += 1;
+        }
+        n
+    }
+
+    /// Drop up to `n` of the messages ready right now, oldest first, to
+    /// catch up with fresher ones. Returns how many were dropped; never
+    /// blocks.
+    pub fn discard_oldest(&mut self, n: usize) -> usize {
+        let mut discarded = 0;
+        while discarded < n {
+            let Some(Some(_)) = self.recv_ready() else {
+                break;
+            };
+            discarded += 1;
+        }
+        discarded
+    }
+
+    // Receive without blocking: `None` if nothing is ready yet, `Some(None)`
+    // once the channel is disconnected.
+    pub(crate) fn recv_ready(&mut self) -> Option<Option<T>> {
+        loop {
+            let Ok(envelope) = self.wait_envelope(|_, _| Err(())) else {
+                return None;
+            };
+            let Some(envelope) = envelope else {
+                return Some(None);
+            };
+            if let v @ Some(_) = envelope.open(&self.shared) {
+                return Some(v);
+            }
+        }
+    }
+
+    /// Receive for a hand-rolled executor: `Ready` with what `recv` would
+    /// return if it wouldn't block, otherwise `Pending` with `cx`'s waker
+    /// registered to be woken by the next send or by disconnect.
+    pub fn poll_recv(&mut self, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        loop {
+            let envelope = self.wait_envelope(|shared, mut inner| {
+                let waker = cx.waker();
+                if !inner.wakers.iter().any(|w| w.will_wake(waker)) {
+                    inner.wakers.push(waker.clone());
+                }
+                shared.has_wakers.store(true, Ordering::SeqCst);
+                Err(())
+            });
+            let Ok(envelope) = envelope else {
+                return Poll::Pending;
+            };
+            let Some(envelope) = envelope else {
+                return Poll::Ready(None);
+            };
+            if let v @ Some(_) = envelope.open(&self.shared) {
+                return Poll::Ready(v);
+            }
+        }
+    }
+
+    /// Receive by awaiting instead of blocking, under any executor: the
+    /// task parks on its `Waker` as with `poll_recv`. The one way to receive
+    /// on single-threaded wasm, where a blocking `recv` has nobody to wake it
+    /// and panics.
+    pub fn recv_async(&mut self) -> RecvAsync<'_, T, R> {
+        RecvAsync { receiver: self }
+    }
+
+    /// Hold back delivery until the guard drops: senders keep enqueueing,
+    /// but receives block (or report nothing ready) even with messages
+    /// waiting. Guards nest; delivery resumes once the last one drops.
+    pub fn pause(&self) -> PauseGuard<T> {
+        self.shared.paused.fetch_add(1, Ordering::SeqCst);
+        PauseGuard {
+            shared: Arc::clone(&self.shared),
+        }
+    }
+
+    /// At-least-once receive: the message is handed out as a `Delivery`
+    /// that must be acked. Dropping it unacked, including by unwinding from
+    /// a panic, puts the message back at the front of the shared queue to be
+    /// received again. `None` once disconnected, even if deliveries are
+    /// still out.
+    pub fn recv_delivery(&mut self) -> Option<Delivery<T>> {
+        let (seq, sender, value, gather) = loop {
+            let envelope = self.next_envelope()?;
+            let (seq, sender) = (envelope.seq, envelope.sender);
+            if let Some((value, gather)) = envelope.open_gather(&self.shared) {
+                break (seq, sender, value, gather);
+            }
+        };
+        self.shared.inner.lock().in_flight += 1;
+        Some(Delivery {
+            shared: Arc::clone(&self.shared),
+            seq,
+            sender,
+            value: Some(value),
+            gather,
+        })
+    }
+
+    /// How many deliveries are out and not yet acked.
+    pub fn in_flight(&self) -> usize {
+        self.shared.inner.lock().in_flight
+    }
+
+    /// Like `recv`, but return `None` as soon as `token` is cancelled. The
+    /// token wakes this channel's receiver directly instead of being polled.
+    pub fn recv_with_token(&mut self, token: &CancellationToken) -> Option<T>
+    where
+        T: Send + 'static,
+    {
+        let shared = Arc::downgrade(&self.shared);
+        let id = token.register(Box::new(move || {
+            if let Some(shared) = shared.upgrade() {
+                // Taking the lock orders this after the receiver's check of
+                // the flag, so it's either seen there or parked and woken.
+                drop(shared.inner.lock());
+                shared.avaliable.notify_all();
+            }
+        }));
+        let received = loop {
+            let envelope = self.wait_envelope(|shared, inner| {
+                if token.is_cancelled() {
+                    return Err(());
+                }
+                Ok(shared.avaliable.wait(inner))
+            });
+            match envelope {
+                Ok(Some(envelope)) => {
+                    if let v @ Some(_) = envelope.open(&self.shared) {
+                        break v;
+                    }
+                }
+                Ok(None) | Err(()) => break None,
+            }
+        };
+        token.unregister(id);
+        received
+    }
+
+    /// Like `recv`, but give up with `Interrupted` once `interrupt` is set,
+    /// e.g. from a ctrl-c handler. The flag is polled while parked, so a
+    /// blocked call notices it within `INTERRUPT_POLL`.
+    pub fn recv_interruptible(&mut self, interrupt: &AtomicBool) -> Result<Option<T>, Interrupted> {
+        loop {
+            let envelope = self.wait_envelope(|shared, inner| {
+                if interrupt.load(Ordering::Acquire) {
+                    return Err(Interrupted);
+                }
+                let (inner, _) = shared.avaliable.wait_timeout(inner, INTERRUPT_POLL);
+                Ok(inner)
+            })?;
+            let Some(envelope) = envelope else {
+                return Ok(None);
+            };
+            if let v @ Some(_) = envelope.open(&self.shared) {
+                return Ok(v);
+            }
+        }
+    }
+
+    /// Like `recv`, but call `on_stall` each time `max_gap` goes by without a
+    /// message, counting from when the previous receive returned, and keep
+    /// waiting.
+    pub fn recv_watchdog(&mut self, max_gap: Duration, on_stall: impl Fn()) -> Option<T> {
+        let mut deadline = *self.recent.back().unwrap_or(&Instant::now()) + max_gap;
+        loop {
+            let envelope = self.wait_envelope(|shared, inner| {
+                let now = Instant::now();
+                if now >= deadline {
+                    on_stall();
+                    deadline = now + max_gap;
+                }
+                let (inner, _) = shared.avaliable.wait_timeout(inner, deadline - now);
+                Ok::<_, Infallible>(inner)
+            });
+            let Ok(envelope) = envelope;
+            if let v @ Some(_) = envelope?.open(&self.shared) {
+                return v;
+            }
+        }
+    }
+
+    /// Collect messages until none arrives for `quiet`, counting from the
+    /// call and again from each message, to debounce a burst into one batch.
+    /// Also returns once `max` are collected or the channel disconnects.
+    pub fn collect_until_quiet(&mut self, quiet: Duration, max: Option<usize>) -> Vec<T> {
+        let mut items = Vec::new();
+        let mut deadline = Instant::now() + quiet;
+        while max.is_none_or(|max| items.len() < max) {
+            let envelope = self.wait_envelope(|shared, inner| {
+                let now = Instant::now();
+                if now >= deadline {
+                    return Err(());
+                }
+                let (inner, _) = shared.avaliable.wait_timeout(inner, deadline - now);
+                Ok(inner)
+            });
+            let Ok(Some(envelope)) = envelope else {
+                break;
+            };
+            if let Some(value) = envelope.open(&self.shared) {
+                items.push(value);
+                deadline = Instant::now() + quiet;
+            }
+        }
+        items
+    }
+
+    /// Block for at least one message, then discard everything older than
+    /// the most recently queued one and return that.
+    pub fn recv_latest(&mut self) -> Option<T> {
+        let mut latest = self.recv()?;
+        let mut inner = self.shared.inner.lock();
+        self.shared.take_redelivered(&mut inner, &mut self.buffer);
+        self.buffer.append(&mut inner.queue);
+        self.shared.notify_full();
+        drop(inner);
+        for envelope in self.buffer.drain(..) {
+            if let Some(value) = envelope.open(&self.shared) {
+                latest = value;
+            }
+        }
+        Some(latest)
+    }
+
+    /// Without blocking, move every pending message matching `pred` into
+    /// `out` under one lock, returning how many. Both the taken and the
+    /// remaining messages keep their order, and the remaining ones stay
+    /// where they were, so a bounded channel's capacity still holds.
+    pub fn take_matching(&mut self, pred: impl Fn(&T) -> bool, out: &mut Vec<T>) -> usize {
+        let mut inner = self.shared.inner.lock();
+        self.shared.take_redelivered(&mut inner, &mut self.buffer);
+        let mut queued = VecDeque::new();
+        queued.append(&mut inner.queue);
+        let before = out.len();
+        let mut take = |envelopes: &mut VecDeque<Envelope<T>>| {
+            for _ in 0..envelopes.len() {
+                let Some(envelope) = envelopes.pop_front() else {
+                    break;
+                };
+                if envelope.is_live() && envelope.value.as_ref().is_some_and(&pred) {
+                    out.extend(envelope.open(&self.shared));
+                } else {
+                    envelopes.push_back(envelope);
+                }
+            }
+        };
+        take(&mut self.buffer);
+        take(&mut queued);
+        for envelope in queued {
+            inner.queue.push_back(envelope);
+        }
+        drop(inner);
+        self.shared.notify_full();
+        out.len() - before
+    }
+
+    /// Copy every pending message, in the order `recv` would return them,
+    /// along with how many were ever sent, so a test can restore this state
+    /// into a fresh channel with `ChannelBuilder::restore`. Cancelled and
+    /// expired messages are left out.
+    #[cfg(feature = "snapshot")]
+    pub fn snapshot(&self) -> Snapshot<T>
+    where
+        T: Clone + serde::Serialize,
+    {
+        let inner = self.shared.inner.lock();
+        let (redelivered, queued) = (
+            inner.queue.iter().take(inner.redelivered),
+            inner.queue.iter().skip(inner.redelivered),
+        );
+        let items = redelivered
+            .chain(&self.buffer)
+            .chain(queued)
+            .filter(|envelope| envelope.is_live())
+            .filter_map(|envelope| envelope.value.clone())
+            .collect();
+        Snapshot {
+            items,
+            sent: self.shared.metrics.sent_total(),
+        }
+    }
+
+    /// Take the newer half of the shared queue in one go, in order, for an
+    /// idle receiver to work through while the others keep the older half.
+    /// Doesn't touch any receiver's buffer or messages awaiting redelivery.
+    pub fn steal_half(&mut self) -> Vec<T> {
+        let mut inner = self.shared.inner.lock();
+        let len = inner.queue.len();
+        let at = (len - len / 2).max(inner.redelivered).min(len);
+        let stolen = inner.queue.split_off(at);
+        drop(inner);
+        self.shared.notify_full();
+        stolen
+            .into_iter()
+            .filter_map(|envelope| envelope.open(&self.shared))
+            .collect()
+    }
+
+    /// Carry on receiving from `other`'s channel instead, handing back a
+    /// receiver for the one this was on, which still holds whatever it
+    /// had buffered. Bumps `generation`.
+    pub fn replace_channel(&mut self, other: Receiver<T, R>) -> Receiver<T, R> {
+        // Any boost was for the old channel's backlog.
+        self.restore_priority();
+        let generation = self.generation + 1;
+        let old = std::mem::replace(self, other);
+        self.generation = generation;
+        old
+    }
+
+    /// How many times `replace_channel` has swapped the channel under this
+    /// receiver, so state kept per channel can be reset when it changes.
+    pub fn generation(&self) -> u64 {
+        self.generation
+    }
+
+    /// Move everything `other` has queued or buffered onto the back of this
+    /// channel's queue in one step, keeping its order, past `capacity` if
+    /// need be. Both locks are taken in address order, so two splices in
+    /// opposite directions can't deadlock.
+    pub fn splice_from(&mut self, other: &mut Receiver<T, R>) {
+        if Arc::ptr_eq(&self.shared, &other.shared) {
+            // Already one channel: just hand back what `other` buffered,
+            // behind any redeliveries so they still go first.
+            let mut inner = self.shared.inner.lock();
+            let at = inner.redelivered;
+            while let Some(envelope) = other.buffer.pop_back() {
+                inner.queue.insert(at, envelope);
+            }
+            drop(inner);
+            self.shared.notify_receiver();
+            return;
+        }
+        let (mut ours, mut theirs) = if Arc::as_ptr(&self.shared) < Arc::as_ptr(&other.shared) {
+            let ours = self.shared.inner.lock();
+            (ours, other.shared.inner.lock())
+        } else {
+            let theirs = other.shared.inner.lock();
+            (self.shared.inner.lock(), theirs)
+        };
+        other
+            .shared
+            .take_redelivered(&mut theirs, &mut other.buffer);
+        let mut moved = std::mem::take(&mut other.buffer);
+        moved.append(&mut theirs.queue);
+        let mut sent = 0;
+        for mut envelope in moved {
+            // Barrier markers move too, still waiting to be reached.
+            if envelope.value.is_some() {
+                other.shared.metrics.record_received();
+                self.shared.stamp(&mut ours, &mut envelope);
+                sent += 1;
+            }
+            other
+                .shared
+                .bytes
+                .fetch_sub(envelope.size, Ordering::SeqCst);
+            self.shared.bytes.fetch_add(envelope.size, Ordering::SeqCst);
+            ours.queue.push_back(envelope);
+        }
+        drop(theirs);
+        drop(ours);
+        self.shared.emit_n(ChannelEvent::MessageSent, sent);
+        other.shared.notify_full();
+        self.shared.notify_receiver();
+    }
+
+    /// Whether `recv` would return `None`: no sender can send any more and
+    /// nothing deliverable is left in the buffer or the shared queue.
+    pub fn is_disconnected(&self) -> bool {
+        let inner = self.shared.inner.lock();
+        (inner.tx_count == 0 || inner.tx_closed)
+            && !self
+                .buffer
+                .iter()
+                .chain(&inner.queue)
+                .any(Envelope::is_live)
+    }
+
+    /// Like `recv`, but also report how many messages are still waiting
+    /// in the buffer and the shared queue after this one was taken.
+    pub fn recv_with_depth(&mut self) -> Option<(T, usize)> {
+        let value = self.recv()?;
+        Some((value, self.buffer.len() + self.at_take().queued))
+    }
+
+    /// Like `recv`, but also report how many senders were alive once the
+    /// message was taken.
+    pub fn recv_with_senders(&mut self) -> Option<(T, usize)> {
+        let value = self.recv()?;
+        Some((value, self.at_take().senders))
+    }
+
+    fn at_take(&self) -> AtTake {
+        // Taken off the buffer, nothing shared was touched, so the state as
+        // it is now is as good as at the take.
+        self.at_take
+            .unwrap_or_else(|| AtTake::of(&self.shared.inner.lock()))
+    }
+
+    /// Close the channel so further sends fail with `SendError::Closed`
+    /// carrying `reason`. Messages already queued can still be received.
+    /// Only the first reason given sticks.
+    pub fn close_with_reason(&self, reason: R)
+    where
+        R: CloseReason,
+    {
+        self.shared.close_senders_with(reason);
+    }
+
+    /// Second half of a clean shutdown: receive everything still pending,
+    /// blocking until the channel is closed or every sender is gone and the
+    /// queue is empty.
+    pub fn drain_remaining(&mut self) -> Vec<T> {
+        std::iter::from_fn(|| self.recv()).collect()
+    }
+
+    /// Close the channel so further sends fail, returning how many messages
+    /// were still waiting. Those can still be received.
+    pub fn close(&self) -> usize {
+        let inner = self.shared.inner.lock();
+        let pending = self
+            .buffer
+            .iter()
+            .chain(&inner.queue)
+            .filter(|envelope| envelope.is_live())
+            .count();
+        if !inner.tx_closed {
+            self.shared.close_senders_locked(inner);
+        }
+        pending
+    }
+
+    /// Close the channel so further sends fail, then hand back everything
+    /// still queued. Pending messages are collected one at a time until
+    /// `deadline`; whatever is left after that is returned in bulk.
+    pub fn shutdown_with_deadline(&mut self, deadline: Instant) -> Vec<T> {
+        self.shared.close_senders();
+        let mut items = Vec::new();
+        while Instant::now() < deadline {
+            match self.recv() {
+                Some(value) => items.push(value),
+                None => return items,
+            }
+        }
+        let mut inner = self.shared.inner.lock();
+        self.shared.take_redelivered(&mut inner, &mut self.buffer);
+        self.buffer.append(&mut inner.queue);
+        drop(inner);
+        self.shared.notify_full();
+        items.extend(
+            self.buffer
+                .drain(..)
+                .filter_map(|envelope| envelope.open(&self.shared)),
+        );
+        items
+    }
+
+    /// Lock the shared queue and iterate over everything currently pending,
+    /// buffered messages first. Senders block until the guard is dropped, so
+    /// keep it short-lived and never send on, clone or drop a sender of this
+    /// channel from the holding thread, or it deadlocks.
+    pub fn lock_and_drain(&mut self) -> DrainGuard<'_, T> {
+        let mut inner = self.shared.inner.lock();
+        self.shared.take_redelivered(&mut inner, &mut self.buffer);
+        DrainGuard {
+            inner,
+            buffer: &mut self.buffer,
+            shared: &self.shared,
+        }
+    }
+
+    /// Counts of gaps between consecutive sends, bucketed on a log scale
+    /// as described by [`crate::metrics::arrival_bucket`].
+    pub fn arrival_histogram(&self) -> [u64; ARRIVAL_BUCKETS] {
+        self.shared.metrics.arrival_histogram()
+    }
+
+    /// Bound the channel to `cap` messages from now on, or lift the bound
+    /// with `None`. Messages already queued past a new bound stay queued;
+    /// senders wait until the receiver has taken enough of them. Senders
+    /// blocked on a full channel are woken to re-check. Panics on
+    /// `Some(0)`, as `ChannelBuilder::capacity` does.
+    pub fn set_capacity(&mut self, cap: Option<usize>) {
+        assert_ne!(
+            cap,
+            Some(0),
+            "a channel needs room for at least one message"
+        );
+        let mut inner = self.shared.inner.lock();
+        inner.capacity = cap;
+        drop(inner);
+        self.shared.notify_full();
+    }
+
+    /// Messages sent but not yet received, counting those queued, buffered
+    /// by this receiver, or waiting to be redelivered. Cancelled and expired
+    /// messages count until `recv` reaches and discards them.
+    pub fn consumer_lag(&self) -> usize {
+        self.shared.metrics.lag() as usize
+    }
+
+    /// Nanoseconds senders and receivers have spent in total acquiring the
+    /// channel's lock, uncontended acquisitions included.
+    #[cfg(feature = "lock-metrics")]
+    pub fn lock_wait_nanos(&self) -> u64 {
+        self.shared.inner.wait_nanos()
+    }
+
+    /// How many times the channel has notified its condvars to wake parked
+    /// receivers or senders. With std's condvar on Linux each one is a
+    /// `futex` syscall, whether anyone was parked or not.
+    #[cfg(feature = "lock-metrics")]
+    pub fn condvar_notifies(&self) -> u64 {
+        self.shared.avaliable.notifies() + self.shared.full.notifies()
+    }
+
+    /// Call `listener` with every `ChannelEvent` from now on, replacing any
+    /// earlier listener. It may run on any thread using the channel, always
+    /// after the change an event reports and with the channel unlocked, so
+    /// it may use the channel itself.
+    pub fn set_event_listener(&self, listener: Box<dyn Fn(ChannelEvent) + Send + Sync>) {
+        *self.shared.listener.lock() = Some(Arc::from(listener));
+        self.shared.has_listener.store(true, Ordering::Release);
+    }
+
+    /// Stop reporting events.
+    pub fn clear_event_listener(&self) {
+        self.shared.has_listener.store(false, Ordering::Release);
+        *self.shared.listener.lock() = None;
+    }
+
+    /// Messages lost to the overflow policy: evicted by `DropOldest` or
+    /// discarded by `DropNewest` while the channel was full.
+    pub fn dropped_total(&self) -> u64 {
+        self.shared.metrics.dropped_total()
+    }
+
+    /// The highest `consumer_lag` seen right after a send since the channel
+    /// was created or `reset_peak_lag` was last called.
+    pub fn peak_lag(&self) -> usize {
+        self.shared.metrics.peak_lag() as usize
+    }
+
+    /// Start tracking `peak_lag` afresh from `0`.
+    pub fn reset_peak_lag(&self) {
+        self.shared.metrics.reset_peak_lag();
+    }
+
+    /// The channel's counters in one go, labelled with its `name`.
+    pub fn metrics(&self) -> MetricsReport {
+        self.shared.metrics.report(self.name())
+    }
+
+    /// Messages per second taken by this receiver over the trailing
+    /// `window`. Only the last `RATE_SAMPLES` receives are remembered, so if
+    /// more than that fall inside `window` the rate is taken over the span
+    /// they cover instead.
+    pub fn recv_rate(&self, window: Duration) -> f64 {
+        let now = Instant::now();
+        let in_window = self
+            .recent
+            .iter()
+            .rev()
+            .take_while(|&&at| now - at <= window)
+            .count();
+        let span = match self.recent.front() {
+            Some(&oldest) if in_window == RATE_SAMPLES => now - oldest,
+            _ => window,
+        };
+        if span.is_zero() {
+            return 0.0;
+        }
+        in_window as f64 / span.as_secs_f64()
+    }
+
+    /// Whether this receiver is parked waiting for a message right now,
+    /// as opposed to busy between receives.
+    pub fn is_blocked_waiting(&self) -> bool {
+        self.parked.load(Ordering::SeqCst)
+    }
+
+    /// A handle answering `is_blocked_waiting` from another thread, for a
+    /// watchdog while the receiver itself is borrowed by `recv`.
+    pub fn blocked_probe(&self) -> BlockedProbe {
+        BlockedProbe(Arc::clone(&self.parked))
+    }
+
+    /// A handle producers can make senders from, even after every existing
+    /// sender is gone. It doesn't count as a sender itself.
+    pub fn connector(&self) -> Connector<T, R> {
+        Connector {
+            shared: Arc::clone(&self.shared),
+            reason: PhantomData,
+        }
+    }
+
+    /// This channel's shared state; see `SharedPtr`.
+    #[cfg(feature = "unstable")]
+    pub fn shared_ptr(&self) -> SharedPtr<T, R> {
+        SharedPtr(Arc::clone(&self.shared), PhantomData)
+    }
+
+    /// A handle that can get at the receiving side without keeping it
+    /// alive: senders still see the channel disconnect once every receiver
+    /// has dropped.
+    pub fn downgrade(&self) -> WeakReceiver<T, R> {
+        WeakReceiver {
+            shared: Arc::downgrade(&self.shared),
+            reason: PhantomData,
+        }
+    }
+
+    /// Block until at least one sender is alive, or one has connected since
+    /// the call even if it's gone again, for a receiver started before its
+    /// producers have connected through a `Connector`. Returns `false`
+    /// instead if the channel is closed first.
+    pub fn wait_for_sender(&self) -> bool {
+        let mut inner = self.shared.inner.lock();
+        let connected = inner.next_sender;
+        while inner.tx_count == 0 && inner.next_sender == connected {
+            if inner.tx_closed {
+                return false;
+            }
+            inner = self.shared.avaliable.wait(inner);
+        }
+        true
+    }
+
+    /// Spawn a monitor thread that calls `on_stall` with how long this
+    /// receiver has been parked once that exceeds `threshold` while senders
+    /// are alive and nothing is queued, which usually means a producer is
+    /// stuck. Fires once per stall, noticed within about a quarter of
+    /// `threshold`; the thread exits once either side disconnects.
+    #[cfg(feature = "diagnostics")]
+    pub fn detect_stalls(&self, threshold: Duration, on_stall: impl Fn(Duration) + Send + 'static)
+    where
+        T: Send + 'static,
+    {
+        let shared = Arc::downgrade(&self.shared);
+        let parked = Arc::clone(&self.parked);
+        let poll = (threshold / 4).max(Duration::from_millis(1));
+        std::thread::spawn(move || {
+            // When the current stall was first seen, and the received total
+            // then, so a receive in between starts a new one.
+            let mut stall: Option<(Instant, u64)> = None;
+            let mut reported = false;
+            loop {
+                std::thread::sleep(poll);
+                let Some(shared) = shared.upgrade() else {
+                    return;
+                };
+                let inner = shared.inner.lock();
+                if inner.rx_count == 0 || inner.tx_count == 0 || inner.tx_closed {
+                    return;
+                }
+                let stalled = parked.load(Ordering::SeqCst) && inner.queue.is_empty();
+                drop(inner);
+                let received = shared.metrics.received_total();
+                match stall {
+                    Some((since, at)) if stalled && at == received => {
+                        if !reported && since.elapsed() >= threshold {
+                            reported = true;
+                            on_stall(since.elapsed());
+                        }
+                    }
+                    _ if stalled => {
+                        stall = Some((Instant::now(), received));
+                        reported = false;
+                    }
+                    _ => stall = None,
+                }
+            }
+        });
+    }
+
+    /// The name given with [`ChannelBuilder::name`], for labelling metrics.
+    pub fn name(&self) -> Option<&str> {
+        self.shared.config.name.as_deref()
+    }
+
+    /// Create another receiver sharing this channel's queue. Only available
+    /// with the `mpmc` feature; single-consumer builds always return `Err`.
+    pub fn try_clone(&self) -> Result<Receiver<T, R>, CloneError> {
+        if !cfg!(feature = "mpmc") {
+            return Err(CloneError);
+        }
+        self.shared.inner.lock().rx_count += 1;
+        Ok(Receiver::new(Arc::clone(&self.shared)))
+    }
+
+    /// Rebalance by handing up to `n` of this receiver's buffered messages,
+    /// the most recently queued ones, to `other`, which will receive them
+    /// before anything else. Returns how many moved. Panics if `other`
+    /// belongs to a different channel.
+    pub fn transfer_buffered(&mut self, other: &mut Receiver<T, R>, n: usize) -> usize {
+        assert!(
+            Arc::ptr_eq(&self.shared, &other.shared),
+            "transfer_buffered between different channels"
+        );
+        let n = n.min(self.buffer.len());
+        let moved = self.buffer.split_off(self.buffer.len() - n);
+        for envelope in moved.into_iter().rev() {
+            other.buffer.push_front(envelope);
+        }
+        n
+    }
+
+    /// Close `other`'s sending side once this channel disconnects, either
+    /// because every sender dropped or because this receiver did.
+    pub fn link<U: Send + 'static, S>(&self, other: &Sender<U, S>) {
+        let downstream = Arc::downgrade(&other.shared);
+        let close: Link = Box::new(move || {
+            if let Some(shared) = downstream.upgrade() {
+                shared.close_senders();
+            }
+        });
+        let mut inner = self.shared.inner.lock();
+        if inner.tx_count == 0 || inner.tx_closed {
+            drop(inner);
+            close();
+        } else {
+            inner.links.push(close);
+        }
+    }
+}
+
+// The bridge only takes receivers with the default close reason.
+#[cfg(feature = "async-bridge")]
+impl<T: Send + 'static> Receiver<T> {
+    /// Hand the receiver over to async code; see `AsyncReceiver`.
+    pub fn into_async(self) -> crate::bridge::AsyncReceiver<T> {
+        crate::bridge::AsyncReceiver::new(self)
+    }
+}
+
+impl<T, E, R> Receiver<Result<T, E>, R> {
+    /// Receive from a stream of results, stopping at the first error. The
+    /// error closes the channel so producers stop too; anything they had
+    /// already queued behind it can still be received.
+    pub fn recv_until_err(&mut self) -> Result<Option<T>, E> {
+        match self.recv() {
+            Some(Ok(value)) => Ok(Some(value)),
+            Some(Err(err)) => {
+                self.shared.close_senders();
+                Err(err)
+            }
+            None => Ok(None),
+        }
+    }
+}
+
+impl<T, R> Debug for Receiver<T, R> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Receiver")
+            .field("name", &self.shared.config.name)
+            .field("buffered", &self.buffer.len())
+            .finish_non_exhaustive()
+    }
+}
+
+impl<T, R> Drop for Receiver<T, R> {
+    fn drop(&mut self) {
+        self.restore_priority();
+        let mut inner = self.shared.inner.lock();
+        inner.rx_count -= 1;
+        if inner.rx_count > 0 {
+            // Hand anything this receiver had buffered back to the others,
+            // behind any redeliveries so they still go first.
+            let at = inner.redelivered;
+            while let Some(envelope) = self.buffer.pop_back() {
+                inner.queue.insert(at, envelope);
+            }
+            drop(inner);
+            self.shared.avaliable.notify_all();
+            self.shared.wake_tasks();
+            self.shared.emit(ChannelEvent::ReceiverDropped);
+            return;
+        }
+        // Nobody can receive these any more; dispose of them once unlocked.
+        self.shared.take_redelivered(&mut inner, &mut self.buffer);
+        let mut pending = std::mem::take(&mut self.buffer);
+        pending.append(&mut inner.queue);
+        let policy = std::mem::take(&mut inner.drop_policy);
+        let disconnected = inner.tx_count > 0;
+        self.shared.notify_full();
+        Shared::disconnect(inner);
+        self.shared.emit(ChannelEvent::ReceiverDropped);
+        if disconnected {
+            self.shared.emit(ChannelEvent::Disconnected);
+        }
+        match policy {
+            DropPolicy::RunDestructors => drop(pending),
+            DropPolicy::Discard => {
+                for envelope in pending {
+                    std::mem::forget(envelope.value);
+                }
+            }
+            DropPolicy::Callback(mut callback) => {
+                for envelope in pending {
+                    if let Some(value) = envelope.open(&self.shared) {
+                        callback(value);
+                    }
+                }
+            }
+        }
+    }
+}
+
+// Nothing a receiver holds is ever pinned, whatever `T` is.
+impl<T, R> Unpin for Receiver<T, R> {}
+
+/// Wakeups go through the polling task's own `Waker`, as with `poll_recv`,
+/// so the stream works under any executor.
+#[cfg(feature = "stream")]
+impl<T, R> futures_core::Stream for Receiver<T, R> {
+    type Item = T;
+
+    fn poll_next(self: std::pin::Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        self.get_mut().poll_recv(cx)
+    }
+}
+
+/// The future returned by `Receiver::recv_async`.
+pub struct RecvAsync<'a, T, R = Shutdown> {
+    receiver: &'a mut Receiver<T, R>,
+}
+
+impl<T, R> Future for RecvAsync<'_, T, R> {
+    type Output = Option<T>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<T>> {
+        self.get_mut().receiver.poll_recv(cx)
+    }
+}
+
+/// A receiver several threads can pull from without the `mpmc` feature.
+/// Each `recv` takes the lock for as long as it waits, so one caller parks
+/// on the channel while the others queue up on the lock behind it; the
+/// holder always wakes on a send or disconnect and passes the lock on.
+pub struct SharedReceiver<T, R = Shutdown> {
+    receiver: Arc<Mutex<Receiver<T, R>>>,
+}
+
+impl<T, R> SharedReceiver<T, R> {
+    pub fn new(receiver: Receiver<T, R>) -> Self {
+        SharedReceiver {
+            receiver: Arc::new(Mutex::new(receiver)),
+        }
+    }
+
+    pub fn recv(&self) -> Option<T> {
+        self.receiver.lock().recv()
+    }
+}
+
+impl<T, R> Clone for SharedReceiver<T, R> {
+    fn clone(&self) -> Self {
+        SharedReceiver {
+            receiver: Arc::clone(&self.receiver),
+        }
+    }
+}
+
+pub struct Connector<T, R = Shutdown> {
+    shared: Arc<Shared<T>>,
+    reason: PhantomData<fn() -> R>,
+}
+
+impl<T, R> Connector<T, R> {
+    /// A new sender, or `None` once the receiver is gone, the channel was
+    /// closed, or a receive has already returned `None` for want of
+    /// senders: a disconnect the receiver has seen is final. Connecting
+    /// while no sender is alive but before that, as after
+    /// `Receiver::wait_for_sender`, is fine, though any `Receiver::link`s
+    /// will have fired when the last earlier sender dropped.
+    pub fn connect(&self) -> Option<Sender<T, R>> {
+        let mut inner = self.shared.inner.lock();
+        if inner.rx_count == 0 || inner.tx_closed || inner.disconnect_seen {
+            return None;
+        }
+        inner.tx_count += 1;
+        let id = inner.next_sender_id();
+        drop(inner);
+        self.shared.avaliable.notify_all();
+        Some(Sender {
+            shared: Arc::clone(&self.shared),
+            id,
+            reason: PhantomData,
+        })
+    }
+}
+
+impl<T, R> Clone for Connector<T, R> {
+    fn clone(&self) -> Self {
+        Connector {
+            shared: Arc::clone(&self.shared),
+            reason: PhantomData,
+        }
+    }
+}
+
+pub struct WeakReceiver<T, R = Shutdown> {
+    shared: Weak<Shared<T>>,
+    reason: PhantomData<fn() -> R>,
+}
+
+impl<T, R> WeakReceiver<T, R> {
+    /// Another receiver on the channel, or `None` once every receiver is
+    /// gone. Like `Receiver::try_clone`, only with the `mpmc` feature:
+    /// single-consumer builds always return `None`.
+    pub fn upgrade(&self) -> Option<Receiver<T, R>> {
+        if !cfg!(feature = "mpmc") {
+            return None;
+        }
+        let shared = self.shared.upgrade()?;
+        let mut inner = shared.inner.lock();
+        if inner.rx_count == 0 {
+            return None;
+        }
+        inner.rx_count += 1;
+        drop(inner);
+        Some(Receiver::new(shared))
+    }
+
+    /// Messages sent but not yet received, as `Receiver::consumer_lag`, or
+    /// 0 once both ends of the channel are gone.
+    pub fn consumer_lag(&self) -> usize {
+        self.shared
+            .upgrade()
+            .map_or(0, |shared| shared.metrics.lag() as usize)
+    }
+
+    /// Whether every receiver is gone, or every sender is and the queue
+    /// holds nothing left to receive. Messages a receiver has already
+    /// buffered for itself aren't visible here.
+    pub fn is_disconnected(&self) -> bool {
+        let Some(shared) = self.shared.upgrade() else {
+            return true;
+        };
+        let inner = shared.inner.lock();
+        inner.rx_count == 0
+            || (inner.tx_count == 0 || inner.tx_closed)
+                && !inner.queue.iter().any(Envelope::is_live)
+    }
+}
+
+impl<T, R> Clone for WeakReceiver<T, R> {
+    fn clone(&self) -> Self {
+        WeakReceiver {
+            shared: Weak::clone(&self.shared),
+            reason: PhantomData,
+        }
+    }
+}
+
+/// A handle on a channel's shared state, for wrappers that combine several
+/// channels or mint their own ends. Unstable: may change in any release.
+///
+/// Ends made from it are ordinary senders and receivers, but the channel
+/// is built around a single receiver unless the `mpmc` feature is on.
+/// Without it, with several receivers alive only one may be blocked
+/// receiving at a time, or a send can wake the wrong one and be missed.
+/// Either way, what one receiver has buffered stays invisible to the
+/// others until it drops.
+#[cfg(feature = "unstable")]
+pub struct SharedPtr<T, R = Shutdown>(Arc<Shared<T>>, PhantomData<fn() -> R>);
+
+#[cfg(feature = "unstable")]
+impl<T, R> SharedPtr<T, R> {
+    /// Whether both handles are on the same channel.
+    pub fn ptr_eq(&self, other: &SharedPtr<T, R>) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+
+    /// A new sender, as `Connector::connect`.
+    pub fn new_sender(&self) -> Option<Sender<T, R>> {
+        Connector {
+            shared: Arc::clone(&self.0),
+            reason: PhantomData,
+        }
+        .connect()
+    }
+
+    /// A new receiver, or `None` once every receiver has dropped and the
+    /// queue with them.
+    pub fn new_receiver(&self) -> Option<Receiver<T, R>> {
+        let mut inner = self.0.inner.lock();
+        if inner.rx_count == 0 {
+            return None;
+        }
+        inner.rx_count += 1;
+        drop(inner);
+        Some(Receiver::new(Arc::clone(&self.0)))
+    }
+}
+
+#[cfg(feature = "unstable")]
+impl<T, R> Clone for SharedPtr<T, R> {
+    fn clone(&self) -> Self {
+        SharedPtr(Arc::clone(&self.0), PhantomData)
+    }
+}
+
+#[derive(Clone)]
+pub struct BlockedProbe(Arc<AtomicBool>);
+
+impl BlockedProbe {
+    pub fn is_blocked_waiting(&self) -> bool {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+pub struct PauseGuard<T> {
+    shared: Arc<Shared<T>>,
+}
+
+impl<T> Drop for PauseGuard<T> {
+    fn drop(&mut self) {
+        // Resume under the lock so a receiver between its check and its
+        // wait can't miss the wakeup.
+        let inner = self.shared.inner.lock();
+        let resumed = self.shared.paused.fetch_sub(1, Ordering::SeqCst) == 1;
+        drop(inner);
+        if resumed {
+            self.shared.avaliable.notify_all();
+            self.shared.wake_tasks();
+        }
+    }
+}
+
+pub struct Delivery<T> {
+    shared: Arc<Shared<T>>,
+    seq: u64,
+    sender: SenderId,
+    // Only `None` once acked.
+    value: Option<T>,
+    gather: Option<GatherAck>,
+}
+
+impl<T> Delivery<T> {
+    /// Confirm the message was handled, so it won't be redelivered.
+    pub fn ack(mut self) -> T {
+        self.shared.inner.lock().in_flight -= 1;
+        if let Some(gather) = self.gather.take() {
+            gather.ack();
+        }
+        self.value.take().unwrap()
+    }
+
+    /// The `GatherHandle::id` of the group this message was sent in by
+    /// `Sender::send_gather`, if any.
+    pub fn group(&self) -> Option<u64> {
+        self.gather.as_ref().map(|ack| ack.gather.id)
+    }
+}
+
+impl<T> Deref for Delivery<T> {
+    type Target = T;
+
+    fn deref(&self) -> &T {
+        self.value.as_ref().unwrap()
+    }
+}
+
+impl<T> Drop for Delivery<T> {
+    fn drop(&mut self) {
+        let Some(value) = self.value.take() else {
+            return;
+        };
+        let mut inner = self.shared.inner.lock();
+        inner.in_flight -= 1;
+        if inner.rx_count == 0 {
+            drop(inner);
+            return;
+        }
+        // Back in the queue, so no longer received.
+        self.shared.metrics.unrecord_received();
+        let mut envelope = Envelope::new(value);
+        envelope.seq = self.seq;
+        envelope.sender = self.sender;
+        envelope.gather = self.gather.take();
+        self.shared.push_ahead(&mut inner, envelope);
+        drop(inner);
+        self.shared.notify_receiver();
+    }
+}
+
+/// Where `recv_origin` found its message.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Origin {
+    /// The receiver's private buffer, without locking.
+    Buffer,
+    /// The shared queue, under the lock but without parking.
+    Queue,
+    /// The shared queue, after parking until a sender woke the receiver.
+    AfterWait,
+}
+
+pub struct EnumerateRecv<'a, T, R = Shutdown> {
+    receiver: &'a mut Receiver<T, R>,
+}
+
+impl<T, R> Iterator for EnumerateRecv<'_, T, R> {
+    type Item = (u64, T);
+
+    fn next(&mut self) -> Option<(u64, T)> {
+        self.receiver.recv_sequenced()
+    }
+}
+
+/// A change in a channel's life, reported to `Receiver::set_event_listener`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ChannelEvent {
+    SenderCloned,
+    SenderDropped,
+    ReceiverDropped,
+    /// The last sender or the last receiver is gone, whichever was first.
+    Disconnected,
+    MessageSent,
+    MessageReceived,
+}
+
+/// What `recv_events` found on each step.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Event<T> {
+    Message(T),
+    /// Nothing ready right now, but senders remain.
+    Idle,
+    Closed,
+}
+
+pub struct RecvEvents<'a, T, R = Shutdown> {
+    receiver: &'a mut Receiver<T, R>,
+    closed: bool,
+}
+
+impl<T, R> Iterator for RecvEvents<'_, T, R> {
+    type Item = Event<T>;
+
+    fn next(&mut self) -> Option<Event<T>> {
+        if self.closed {
+            return None;
+        }
+        loop {
+            let Ok(envelope) = self.receiver.wait_envelope(|_, _| Err(())) else {
+                return Some(Event::Idle);
+            };
+            let Some(envelope) = envelope else {
+                self.closed = true;
+                return Some(Event::Closed);
+            };
+            if let Some(value) = envelope.open(&self.receiver.shared) {
+                return Some(Event::Message(value));
+            }
+        }
+    }
+}
+
+pub struct DrainGuard<'a, T> {
+    inner: MutexGuard<'a, Inner<T>>,
+    buffer: &'a mut VecDeque<Envelope<T>>,
+    shared: &'a Shared<T>,
+}
+
+impl<T> Drop for DrainGuard<'_, T> {
+    fn drop(&mut self) {
+        self.shared.notify_full();
+    }
+}
+
+impl<T> Iterator for DrainGuard<'_, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        loop {
+            let envelope = match self.buffer.pop_front() {
+                Some(envelope) => envelope,
+                None => self.inner.queue.pop_front()?,
+            };
+            if let v @ Some(_) = envelope.open(self.shared) {
+                return v;
+            }
+        }
+    }
+}
+
+/// Write every chunk received into `w` until the channel disconnects, then
+/// flush it and return the number of bytes written.
+pub fn forward_to_writer(mut rx: Receiver<Vec<u8>>, mut w: impl Write) -> io::Result<u64> {
+    let mut written = 0;
+    while let Some(chunk) = rx.recv() {
+        w.write_all(&chunk)?;
+        written += chunk.len() as u64;
+    }
+    w.flush()?;
+    Ok(written)
+}
+
+/// One operation for `select_op` to wait on.
+pub enum Op<'a, T, U, R = Shutdown> {
+    Recv(&'a mut Receiver<T, R>),
+    /// The value is taken out once sent, leaving `None`.
+    Send(&'a Sender<U, R>, Option<U>),
+}
+
+impl<'a, T, U, R> Op<'a, T, U, R> {
+    pub fn recv(receiver: &'a mut Receiver<T, R>) -> Self {
+        Op::Recv(receiver)
+    }
+
+    pub fn send(sender: &'a Sender<U, R>, value: U) -> Self {
+        Op::Send(sender, Some(value))
+    }
+}
+
+/// Which operation `select_op` completed, by index, and its outcome.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Selected<T, U, R = Shutdown> {
+    /// `None` if that channel is disconnected.
+    Recv(usize, Option<T>),
+    Send(usize, Result<(), SendError<U, R>>),
+}
+
+/// Block until one of `ops` can complete and perform it: a receive that has
+/// a message (or whose channel disconnected), or a send into a channel with
+/// room (or whose receiver is gone). Earlier ops win ties. In between, the
+/// thread parks on a waker left with every channel, as with `poll_recv`.
+/// Panics if no op is pending, since it would never return.
+pub fn select_op<T, U, R: CloseReason>(ops: &mut [Op<'_, T, U, R>]) -> Selected<T, U, R> {
+    assert!(
+        ops.iter().any(|op| !matches!(op, Op::Send(_, None))),
+        "select_op needs at least one pending operation"
+    );
+    let waker = Waker::from(Arc::new(ThreadWaker(std::thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    let selected = 'select: loop {
+        for (index, op) in ops.iter_mut().enumerate() {
+            match op {
+                Op::Recv(receiver) => {
+                    if let Poll::Ready(received) = receiver.poll_recv(&mut cx) {
+                        break 'select Selected::Recv(index, received);
+                    }
+                }
+                Op::Send(sender, slot) => {
+                    let Some(value) = slot.take() else {
+                        continue;
+                    };
+                    match sender.send_if_space_or_wake(value, &waker) {
+                        Ok(Some(value)) => *slot = Some(value),
+                        Ok(None) => break 'select Selected::Send(index, Ok(())),
+                        Err(err) => break 'select Selected::Send(index, Err(err)),
+                    }
+                }
+            }
+        }
+        // Anything that happened since a waker was left unparks at once.
+        std::thread::park();
+    };
+    // Take this call's waker back off the ops that didn't win, or every
+    // call would leave one more behind, each unparking this thread later.
+    for op in ops.iter() {
+        match op {
+            Op::Recv(receiver) => receiver.shared.forget_waker(&waker),
+            Op::Send(sender, _) => sender.shared.forget_select_waker(&waker),
+        }
+    }
+    selected
+}
+
+// Unparks the thread blocked in `select_op`.
+struct ThreadWaker(std::thread::Thread);
+
+impl std::task::Wake for ThreadWaker {
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+}
+
+/// Roughly how many bytes a message holds, for
+/// `ChannelBuilder::max_total_bytes`.
+pub trait MessageSize {
+    fn size_hint(&self) -> usize;
+}
+
+impl<T> MessageSize for Vec<T> {
+    fn size_hint(&self) -> usize {
+        self.len() * std::mem::size_of::<T>()
+    }
+}
+
+impl<T> MessageSize for Box<[T]> {
+    fn size_hint(&self) -> usize {
+        self.len() * std::mem::size_of::<T>()
+    }
+}
+
+impl MessageSize for String {
+    fn size_hint(&self) -> usize {
+        self.len()
+    }
+}
+
+/// Why a send failed. Either way the unsent value is handed back.
+#[derive(Debug, PartialEq, Eq)]
+pub enum SendError<T, R = Shutdown> {
+    Disconnected(T),
+    /// The channel refused the value without blocking: its queue reached
+    /// `ChannelBuilder::max_queue`, or a reservation was used up.
+    Overflow(T),
+    /// The channel was closed with `Receiver::close_with_reason` or
+    /// `Sender::begin_shutdown`.
+    Closed(T, R),
+    /// `Sender::send_sized` would have taken the bytes waiting past
+    /// `ChannelBuilder::max_total_bytes`.
+    SizeLimit(T),
+}
+
+impl<T, R> SendError<T, R> {
+    pub fn into_inner(self) -> T {
+        match self {
+            SendError::Disconnected(value)
+            | SendError::Overflow(value)
+            | SendError::Closed(value, _)
+            | SendError::SizeLimit(value) => value,
+        }
+    }
+
+    /// Transform the unsent value, keeping the reason the send failed.
+    pub fn map<U>(self, f: impl FnOnce(T) -> U) -> SendError<U, R> {
+        match self {
+            SendError::Disconnected(value) => SendError::Disconnected(f(value)),
+            SendError::Overflow(value) => SendError::Overflow(f(value)),
+            SendError::Closed(value, reason) => SendError::Closed(f(value), reason),
+            SendError::SizeLimit(value) => SendError::SizeLimit(f(value)),
+        }
+    }
+}
+
+impl<T, R> Display for SendError<T, R> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SendError::Disconnected(_) => f.write_str("sending on a disconnected channel"),
+            SendError::Overflow(_) => f.write_str("sending on a full channel"),
+            SendError::Closed(..) => f.write_str("sending on a closed channel"),
+            SendError::SizeLimit(_) => f.write_str("sending past a channel's byte limit"),
+        }
+    }
+}
+
+impl<T: Debug, R: Debug> Error for SendError<T, R> {}
+
+/// Where `Receiver::recv_expect` found the stream diverging.
+#[derive(Debug, PartialEq, Eq)]
+pub enum SequenceError<T> {
+    Mismatch {
+        index: usize,
+        got: T,
+        wanted: T,
+    },
+    /// The channel disconnected before the message at `index` arrived.
+    Disconnected {
+        index: usize,
+    },
+}
+
+impl<T: Debug> Display for SequenceError<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SequenceError::Mismatch { index, got, wanted } => {
+                write!(f, "message {index} was {got:?}, expected {wanted:?}")
+            }
+            SequenceError::Disconnected { index } => {
+                write!(f, "disconnected before message {index}")
+            }
+        }
+    }
+}
+
+impl<T: Debug> Error for SequenceError<T> {}
+
+/// What a channel's ends can be closed with: the `R` of `Sender<T, R>` and
+/// `Receiver<T, R>`, picked with `ChannelBuilder::build_with_reason` and
+/// handed back to senders as `SendError::Closed`. Any cloneable type that
+/// can cross threads will do.
+pub trait CloseReason: Clone + Send + Sync + 'static {}
+
+impl<R: Clone + Send + Sync + 'static> CloseReason for R {}
+
+/// The close reason of a channel that didn't pick one, left by
+/// `Sender::begin_shutdown`.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Shutdown;
+
+/// Why `Receiver::recv_checked` returned without a message.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum RecvError {
+    Disconnected,
+    /// Nothing arrived within `ChannelBuilder::max_block`.
+    Timeout,
+}
+
+impl Display for RecvError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RecvError::Disconnected => f.write_str("receiving on a disconnected channel"),
+            RecvError::Timeout => f.write_str("receive blocked past the channel's max_block"),
+        }
+    }
+}
+
+impl Error for RecvError {}
+
+#[derive(Debug, PartialEq, Eq)]
+pub struct Interrupted;
+
+impl Display for Interrupted {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("receive interrupted")
+    }
+}
+
+impl Error for Interrupted {}
+
+#[derive(Debug)]
+pub struct CloneError;
+
+impl Display for CloneError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("cloning a Receiver requires the `mpmc` feature")
+    }
+}
+
+impl Error for CloneError {}
+
+type Link = Box<dyn FnOnce() + Send>;
+
+type Listener = Arc<dyn Fn(ChannelEvent) + Send + Sync>;
+
+pub struct CancelToken<T> {
+    shared: Weak<Shared<T>>,
+    claimed: Arc<AtomicBool>,
+}
+
+impl<T> CancelToken<T> {
+    /// Withdraw the message, returning `false` if the receiver already got
+    /// it. Still in the shared queue, it's dropped from there at once, so it
+    /// stops taking up a slot; one the receiver has already buffered is
+    /// skipped when reached.
+    pub fn cancel(&self) -> bool {
+        if self.claimed.swap(true, Ordering::AcqRel) {
+            return false;
+        }
+        let Some(shared) = self.shared.upgrade() else {
+            return true;
+        };
+        let mut inner = shared.inner.lock();
+        let at = inner.queue.iter().position(|envelope| {
+            envelope
+                .claimed
+                .as_ref()
+                .is_some_and(|claimed| Arc::ptr_eq(claimed, &self.claimed))
+        });
+        let withdrawn = at.and_then(|at| shared.withdraw(&mut inner, at));
+        drop(inner);
+        if withdrawn.is_some() {
+            shared.notify_full();
+        }
+        true
+    }
+}
+
+type Wake = Box<dyn Fn() + Send + Sync>;
+
+/// Cancels blocked `recv_with_token` calls. Clones share one state, so any
+/// clone can cancel and every clone sees it.
+#[derive(Clone, Default)]
+pub struct CancellationToken {
+    inner: Arc<TokenInner>,
+}
+
+#[derive(Default)]
+struct TokenInner {
+    cancelled: AtomicBool,
+    // Wakes for the channels that receivers are currently parked on.
+    wakes: Mutex<(u64, Vec<(u64, Wake)>)>,
+}
+
+impl CancellationToken {
+    pub fn new() -> Self {
+        CancellationToken::default()
+    }
+
+    /// Cancel, waking any receiver blocked on this token right away.
+    pub fn cancel(&self) {
+        self.inner.cancelled.store(true, Ordering::SeqCst);
+        let wakes = std::mem::take(&mut self.inner.wakes.lock().1);
+        for (_, wake) in wakes {
+            wake();
+        }
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.inner.cancelled.load(Ordering::SeqCst)
+    }
+
+    fn register(&self, wake: Wake) -> u64 {
+        let mut wakes = self.inner.wakes.lock();
+        let id = wakes.0;
+        wakes.0 += 1;
+        wakes.1.push((id, wake));
+        id
+    }
+
+    fn unregister(&self, id: u64) {
+        self.inner.wakes.lock().1.retain(|(i, _)| *i != id);
+    }
+}
+
+#[derive(Default)]
+struct BarrierMarker {
+    released: Mutex<bool>,
+    cond: Condvar,
+}
+
+// Releases the waiting `Sender::barrier` when the marker leaves the queue,
+// whether the receiver reached it or dropped it unread.
+struct BarrierRelease(Arc<BarrierMarker>);
+
+impl Drop for BarrierRelease {
+    fn drop(&mut self) {
+        *self.0.released.lock() = true;
+        self.0.cond.notify_all();
+    }
+}
+
+/// Waits on a group sent by `Sender::send_gather`.
+pub struct GatherHandle {
+    gather: Arc<Gather>,
+}
+
+impl GatherHandle {
+    /// Tags every message of the group, as seen by `Delivery::group`.
+    pub fn id(&self) -> u64 {
+        self.gather.id
+    }
+
+    /// Block until every message in the group is done with: `true` if all
+    /// were acked, `false` if any was dropped unacked instead, say unsent
+    /// or left behind by a dropped receiver.
+    pub fn wait_all(&self) -> bool {
+        let mut state = self.gather.state.lock();
+        while state.pending > 0 {
+            state = self.gather.settled.wait(state);
+        }
+        !state.lost
+    }
+}
+
+struct Gather {
+    id: u64,
+    state: Mutex<GatherState>,
+    settled: Condvar,
+}
+
+struct GatherState {
+    // Messages neither acked nor dropped yet.
+    pending: usize,
+    lost: bool,
+}
+
+// One message's share of its `send_gather` group, riding in its envelope
+// and then its `Delivery`. Settles the message when dropped, as lost unless
+// acked first.
+struct GatherAck {
+    gather: Arc<Gather>,
+    acked: bool,
+}
+
+impl GatherAck {
+    fn ack(mut self) {
+        self.acked = true;
+    }
+}
+
+impl Drop for GatherAck {
+    fn drop(&mut self) {
+        let mut state = self.gather.state.lock();
+        state.pending -= 1;
+        state.lost |= !self.acked;
+        if state.pending == 0 {
+            self.gather.settled.notify_all();
+        }
+    }
+}
+
+// `value` is only `None` for barrier markers.
+struct Envelope<T> {
+    value: Option<T>,
+    seq: u64,
+    sender: SenderId,
+    claimed: Option<Arc<AtomicBool>>,
+    expires: Option<Instant>,
+    barrier: Option<BarrierRelease>,
+    gather: Option<GatherAck>,
+    // Bytes counted toward `max_total_bytes`, from `send_sized`.
+    size: usize,
+    // The sender's span, from `send_traced`.
+    #[cfg(feature = "tracing")]
+    span: Option<tracing::Span>,
+}
+
+impl<T> Envelope<T> {
+    fn new(value: T) -> Self {
+        Envelope {
+            value: Some(value),
+            seq: 0,
+            sender: SenderId(0),
+            claimed: None,
+            expires: None,
+            barrier: None,
+            gather: None,
+            size: 0,
+            #[cfg(feature = "tracing")]
+            span: None,
+        }
+    }
+
+    // Would `open` return a value right now? Doesn't claim anything.
+    fn is_live(&self) -> bool {
+        self.value.is_some()
+            && !self
+                .claimed
+                .as_ref()
+                .is_some_and(|claimed| claimed.load(Ordering::Acquire))
+            && self.expires.is_none_or(|expires| Instant::now() < expires)
+    }
+
+    // Counts as received for lag purposes even if it turns out cancelled or
+    // expired, since either way it has left the queue.
+    fn open(self, shared: &Shared<T>) -> Option<T> {
+        let (value, gather) = self.open_gather(shared)?;
+        if let Some(gather) = gather {
+            gather.ack();
+        }
+        Some(value)
+    }
+
+    // `open`, leaving a `send_gather` message unacked for a `Delivery`.
+    fn open_gather(self, shared: &Shared<T>) -> Option<(T, Option<GatherAck>)> {
+        if self.value.is_some() {
+            shared.metrics.record_received();
+        }
+        shared.bytes.fetch_sub(self.size, Ordering::SeqCst);
+        let deliverable = self.claim();
+        drop(self.barrier);
+        let value = self.value.filter(|_| deliverable)?;
+        shared.emit(ChannelEvent::MessageReceived);
+        Some((value, self.gather))
+    }
+
+    // Whoever flips `claimed` first, the receiver or a `CancelToken`, owns
+    // the message; a cancelled or expired envelope is simply dropped when
+    // reached.
+    fn claim(&self) -> bool {
+        if let Some(claimed) = &self.claimed {
+            if claimed.swap(true, Ordering::AcqRel) {
+                return false;
+            }
+        }
+        self.expires.is_none_or(|expires| Instant::now() < expires)
+    }
+}
+
+struct Shared<T> {
+    inner: Mutex<Inner<T>>,
+    avaliable: Condvar,
+    full: Condvar,
+    config: ChannelBuilder,
+    metrics: Metrics,
+    // Sum of the `size` of every envelope sent but not yet received.
+    bytes: AtomicUsize,
+    // Receivers parked on `avaliable`. A receiver counts itself while still
+    // holding the lock it saw the empty queue under, and a sender only reads
+    // this after pushing under that same lock, so it can't miss a parked
+    // receiver; when it reads zero nobody needs waking and the notify,
+    // possibly a futex syscall, is skipped.
+    waiters: AtomicUsize,
+    // Set alongside `Inner::redelivered` so the receiver's lock-free buffer
+    // fast path knows to take the lock and pick redeliveries up first.
+    redelivered: AtomicBool,
+    // Whether `Inner::wakers` may be non-empty.
+    has_wakers: AtomicBool,
+    // Live `PauseGuard`s.
+    paused: AtomicUsize,
+    // Senders in `send_until_buffered`, parked on `full` until their message
+    // leaves the queue. Counted under the lock like `waiters`.
+    buffer_waiters: AtomicUsize,
+    // Whether `listener` is set, so events cost one load when it isn't.
+    has_listener: AtomicBool,
+    listener: Mutex<Option<Listener>>,
+    // Whether `select_wakers` may be non-empty.
+    has_select_wakers: AtomicBool,
+    // `select_op` calls waiting for room to send, woken along with `full`.
+    select_wakers: Mutex<Vec<Waker>>,
+}
+
+impl<T> Shared<T> {
+    fn free_slots(&self, inner: &Inner<T>) -> usize {
+        self.priority_slots(inner)
+            .saturating_sub(self.config.headroom)
+    }
+
+    // Free slots including the headroom only `send_priority` may use.
+    fn priority_slots(&self, inner: &Inner<T>) -> usize {
+        match inner.capacity {
+            Some(capacity) => capacity.saturating_sub(inner.queue.len() + inner.reserved),
+            None => usize::MAX,
+        }
+    }
+
+    // Wake senders waiting for space after every `notify_space_every`
+    // takes, or at once if the queue is empty, since the receiver may then
+    // park and take nothing more until a sender gets in. A batch of free
+    // slots wakes every waiting sender.
+    fn notify_space(&self, inner: &mut Inner<T>) {
+        let every = self.config.notify_space_every;
+        inner.taken_since_notify += 1;
+        if inner.taken_since_notify < every && !inner.queue.is_empty() {
+            return;
+        }
+        inner.taken_since_notify = 0;
+        if every > 1 {
+            self.full.notify_all();
+        } else {
+            self.full.notify_one();
+        }
+        self.wake_selects();
+    }
+
+    // Wake everything waiting on the channel to have room.
+    fn notify_full(&self) {
+        self.full.notify_all();
+        self.wake_selects();
+    }
+
+    // Wake the `select_op` calls waiting to send, if any. Like `has_wakers`,
+    // the flag is set before the select re-checks for room under the lock,
+    // so whoever makes room under that lock afterwards sees it.
+    // Drop a waker `send_if_space_or_wake` left, once its `select_op` is done.
+    fn forget_select_waker(&self, waker: &Waker) {
+        self.select_wakers.lock().retain(|w| !w.will_wake(waker));
+    }
+
+    fn wake_selects(&self) {
+        if !self.has_select_wakers.load(Ordering::SeqCst) {
+            return;
+        }
+        let mut wakers = self.select_wakers.lock();
+        self.has_select_wakers.store(false, Ordering::SeqCst);
+        let wakers = std::mem::take(&mut *wakers);
+        wakers.into_iter().for_each(Waker::wake);
+    }
+
+    // Bounded channels take one message at a time, so nothing a receiver
+    // has buffered can slip past the bound.
+    fn bulk_buffer(&self, inner: &Inner<T>) -> bool {
+        !self.config.no_bulk_buffer && inner.capacity.is_none()
+    }
+
+    fn has_bytes(&self, size: usize) -> bool {
+        self.config
+            .max_total_bytes
+            .is_none_or(|max| self.bytes.load(Ordering::SeqCst).saturating_add(size) <= max)
+    }
+
+    fn has_space(&self, inner: &Inner<T>, n: usize) -> bool {
+        self.free_slots(inner) >= n
+    }
+
+    fn max_queue_room(&self, inner: &Inner<T>) -> usize {
+        match self.config.max_queue {
+            Some(max) => max.saturating_sub(inner.queue.len()),
+            None => usize::MAX,
+        }
+    }
+
+    fn at_max_queue(&self, inner: &Inner<T>, n: usize) -> bool {
+        self.max_queue_room(inner) < n
+    }
+
+    // What a send that found the channel not open should fail with.
+    fn send_error<U, R: CloseReason>(&self, value: U) -> SendError<U, R> {
+        // Every end of a channel shares its `R`, so the reason is always one.
+        let reason = self.inner.lock().close_reason.clone();
+        match reason.and_then(|reason| reason.downcast_ref::<R>().cloned()) {
+            Some(reason) => SendError::Closed(value, reason),
+            None => SendError::Disconnected(value),
+        }
+    }
+
+    fn receiver_gone(&self) {
+        if self.config.on_disconnect == Behavior::Panic {
+            panic!("Sender send value but the Receiver has closed.");
+        }
+    }
+
+    fn close_senders(&self) {
+        let inner = self.inner.lock();
+        if inner.tx_closed {
+            return;
+        }
+        self.close_senders_locked(inner);
+    }
+
+    fn close_senders_with<R: CloseReason>(&self, reason: R) {
+        let mut inner = self.inner.lock();
+        if inner.tx_closed {
+            return;
+        }
+        inner.close_reason = Some(Arc::new(reason));
+        self.close_senders_locked(inner);
+    }
+
+    fn close_senders_locked(&self, mut inner: MutexGuard<'_, Inner<T>>) {
+        inner.tx_closed = true;
+        self.avaliable.notify_all();
+        self.notify_full();
+        Shared::disconnect(inner);
+    }
+
+    // Returns the queue length after the push.
+    fn enqueue(&self, inner: MutexGuard<'_, Inner<T>>, envelope: Envelope<T>) -> usize {
+        let len = self.enqueue_quiet(inner, envelope);
+        self.notify_pushed(len);
+        len
+    }
+
+    // Push and unlock, leaving the wakeup to the caller.
+    fn enqueue_quiet(
+        &self,
+        mut inner: MutexGuard<'_, Inner<T>>,
+        mut envelope: Envelope<T>,
+    ) -> usize {
+        self.stamp(&mut inner, &mut envelope);
+        inner.queue.push_back(envelope);
+        let len = inner.queue.len();
+        drop(inner);
+        self.emit(ChannelEvent::MessageSent);
+        len
+    }
+
+    // Queue `envelope` behind the others that jump the receiver's buffer,
+    // or under `strict_fifo` among the rest by sequence number.
+    fn push_ahead(&self, inner: &mut Inner<T>, envelope: Envelope<T>) {
+        if self.config.strict_fifo {
+            let at = inner
+                .queue
+                .partition_point(|queued| queued.seq < envelope.seq);
+            inner.queue.insert(at, envelope);
+            return;
+        }
+        let at = inner.redelivered;
+        inner.queue.insert(at, envelope);
+        inner.redelivered += 1;
+        self.redelivered.store(true, Ordering::Release);
+    }
+
+    // Move the messages queued with `push_ahead` ahead of everything this
+    // receiver has buffered in `buffer`.
+    fn take_redelivered(&self, inner: &mut Inner<T>, buffer: &mut VecDeque<Envelope<T>>) {
+        let n = std::mem::take(&mut inner.redelivered);
+        if n == 0 {
+            return;
+        }
+        self.redelivered.store(false, Ordering::Release);
+        for envelope in inner.queue.drain(..n).rev() {
+            buffer.push_front(envelope);
+        }
+        if inner.capacity.is_some() || self.buffer_waiters.load(Ordering::SeqCst) > 0 {
+            self.notify_full();
+        }
+    }
+
+    // When a receive starting now gives up under `max_block`.
+    fn block_deadline(&self) -> Option<Instant> {
+        self.config.max_block.map(|max| Instant::now() + max)
+    }
+
+    // Park until notified, or fail once `deadline` has passed.
+    fn wait_until<'a>(
+        &self,
+        inner: MutexGuard<'a, Inner<T>>,
+        deadline: Option<Instant>,
+    ) -> Result<MutexGuard<'a, Inner<T>>, RecvError> {
+        let Some(deadline) = deadline else {
+            return Ok(self.avaliable.wait(inner));
+        };
+        let now = Instant::now();
+        if now >= deadline {
+            return Err(RecvError::Timeout);
+        }
+        let (inner, _) = self.avaliable.wait_timeout(inner, deadline - now);
+        Ok(inner)
+    }
+
+    // Run a receiver's `wait`, counted in `waiters` and flagged in its
+    // `parked` for as long as it's parked.
+    fn park<'a, E>(
+        &'a self,
+        parked: &AtomicBool,
+        inner: MutexGuard<'a, Inner<T>>,
+        wait: &mut impl for<'b> FnMut(
+            &'b Shared<T>,
+            MutexGuard<'b, Inner<T>>,
+        ) -> Result<MutexGuard<'b, Inner<T>>, E>,
+    ) -> Result<MutexGuard<'a, Inner<T>>, E> {
+        self.waiters.fetch_add(1, Ordering::SeqCst);
+        parked.store(true, Ordering::SeqCst);
+        let inner = wait(self, inner);
+        parked.store(false, Ordering::SeqCst);
+        self.waiters.fetch_sub(1, Ordering::SeqCst);
+        inner
+    }
+
+    // Wake the receiver after a push that left `len` queued. A lone
+    // receiver re-checks the queue under the lock before parking and nothing
+    // else takes from it, so it only parks on an empty queue; unless this
+    // push made the queue non-empty, the one that did already woke it.
+    // Several receivers may be parked at once, so with `mpmc` every push
+    // still wakes one.
+    fn notify_pushed(&self, len: usize) {
+        if len == 1 || cfg!(feature = "mpmc") {
+            self.notify_receiver();
+        }
+    }
+
+    fn notify_receiver(&self) {
+        if self.waiters.load(Ordering::SeqCst) > 0 {
+            self.avaliable.notify_one();
+        }
+        self.wake_tasks();
+    }
+
+    // Wake the tasks `poll_recv` registered, if any. Like `waiters`, the flag
+    // is set under the lock before a poll returns `Pending`, so a sender that
+    // pushed under that lock afterwards sees it.
+    fn wake_tasks(&self) {
+        if !self.has_wakers.load(Ordering::SeqCst) {
+            return;
+        }
+        let mut inner = self.inner.lock();
+        self.has_wakers.store(false, Ordering::SeqCst);
+        let wakers = std::mem::take(&mut inner.wakers);
+        drop(inner);
+        wakers.into_iter().for_each(Waker::wake);
+    }
+
+    // Drop a waker `poll_recv` registered that no longer wants waking.
+    fn forget_waker(&self, waker: &Waker) {
+        if self.has_wakers.load(Ordering::SeqCst) {
+            self.inner.lock().wakers.retain(|w| !w.will_wake(waker));
+        }
+    }
+
+    // Assign the next sequence id and record arrival metrics. The caller
+    // emits `MessageSent` once it has unlocked.
+    fn stamp(&self, inner: &mut Inner<T>, envelope: &mut Envelope<T>) {
+        envelope.seq = inner.next_seq;
+        inner.next_seq += 1;
+        self.metrics.record_sent();
+        let now = Instant::now();
+        if let Some(last) = inner.last_arrival.replace(now) {
+            self.metrics.record_arrival(now - last);
+        }
+    }
+
+    // Events are emitted once the change they report is made and the lock
+    // released, so a listener sees it done and may use the channel itself.
+    fn emit(&self, event: ChannelEvent) {
+        self.emit_n(event, 1);
+    }
+
+    fn emit_n(&self, event: ChannelEvent, n: usize) {
+        if n == 0 || !self.has_listener.load(Ordering::Acquire) {
+            return;
+        }
+        let listener = self.listener.lock().clone();
+        if let Some(listener) = listener {
+            (0..n).for_each(|_| listener(event));
+        }
+    }
+
+    // Take the envelope at `at` out of the queue before it's reached, as
+    // though received: its bytes are freed and it no longer counts as lag.
+    fn withdraw(&self, inner: &mut Inner<T>, at: usize) -> Option<Envelope<T>> {
+        let envelope = inner.queue.remove(at)?;
+        self.bytes.fetch_sub(envelope.size, Ordering::SeqCst);
+        self.metrics.record_received();
+        if at < inner.redelivered {
+            inner.redelivered -= 1;
+        }
+        Some(envelope)
+    }
+
+    // Links are run after the lock is released so a chain of linked
+    // channels never holds more than one lock at a time.
+    fn disconnect(mut inner: MutexGuard<'_, Inner<T>>) {
+        let links = std::mem::take(&mut inner.links);
+        let wakers = std::mem::take(&mut inner.wakers);
+        drop(inner);
+        wakers.into_iter().for_each(Waker::wake);
+        for link in links {
+            link();
+        }
+    }
+}
+
+impl<T> Inner<T> {
+    fn next_sender_id(&mut self) -> SenderId {
+        let id = SenderId(self.next_sender);
+        self.next_sender += 1;
+        id
+    }
+}
+
+impl<T> Drop for Shared<T> {
+    fn drop(&mut self) {
+        for f in std::mem::take(&mut self.inner.get_mut().on_close) {
+            f();
+        }
+    }
+}
+
+// The sender/receiver counts and close flags are plain fields under the
+// same mutex the receiver holds while it checks them and parks. The last
+// sender's decrement and notify therefore happen either before the
+// receiver's check (which then sees zero) or after it has started waiting
+// (which the notify wakes), so no atomics or memory orderings are involved.
+struct Inner<T> {
+    queue: VecDeque<Envelope<T>>,
+    // Starts as `ChannelBuilder::capacity`; `Receiver::set_capacity` changes it.
+    capacity: Option<usize>,
+    // Takes from a bounded queue since `full` was last notified.
+    taken_since_notify: usize,
+    tx_count: usize,
+    tx_closed: bool,
+    // A receive has returned `None` for want of senders; `Connector::connect`
+    // won't revive the channel after that.
+    disconnect_seen: bool,
+    rx_count: usize,
+    // Slots of a bounded channel promised to live `Reservation`s.
+    reserved: usize,
+    links: Vec<Link>,
+    // Run from `Shared`'s drop, added with `Sender::on_close`.
+    on_close: Vec<Link>,
+    last_arrival: Option<Instant>,
+    next_seq: u64,
+    // The id the next cloned sender gets.
+    next_sender: u64,
+    // The `R` of the channel's ends.
+    close_reason: Option<Arc<dyn Any + Send + Sync>>,
+    drop_policy: DropPolicy<T>,
+    // Tasks parked in `poll_recv`.
+    wakers: Vec<Waker>,
+    // Messages handed out as a `Delivery` that hasn't been acked yet.
+    in_flight: usize,
+    // How many messages at the front of `queue` are unacked deliveries put
+    // back or control messages, which the receiver takes ahead of its own
+    // buffer.
+    redelivered: usize,
+}
+
+/// What happens to messages still queued when the last receiver drops.
+#[derive(Default)]
+pub enum DropPolicy<T> {
+    /// Forget them without running their destructors: fastest, and only
+    /// sound to use when leaking `T` is harmless.
+    Discard,
+    /// Drop each one.
+    #[default]
+    RunDestructors,
+    /// Hand each deliverable one to the closure.
+    Callback(Box<dyn FnMut(T) + Send>),
+}
+
+/// What `send` does when a bounded channel is full.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Wait for the receiver to free a slot.
+    #[default]
+    Block,
+    /// Drop the value being sent, still returning `Ok`.
+    DropNewest,
+    /// Evict the oldest queued message to make room.
+    DropOldest,
+}
+
+/// What a send does once the receiver is gone.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum Behavior {
+    #[default]
+    Error,
+    Panic,
+}
+
+#[derive(Default)]
+pub struct ChannelBuilder {
+    spin: usize,
+    spin_backoff: Option<(usize, usize)>,
+    no_bulk_buffer: bool,
+    strict_fifo: bool,
+    on_disconnect: Behavior,
+    capacity: Option<usize>,
+    yield_every: usize,
+    name: Option<String>,
+    overflow: OverflowPolicy,
+    max_queue: Option<usize>,
+    fair: bool,
+    max_buffer: Option<usize>,
+    headroom: usize,
+    notify_space_every: usize,
+    max_total_bytes: Option<usize>,
+    boost_threshold: Option<usize>,
+    max_block: Option<Duration>,
+}
+
+impl ChannelBuilder {
+    pub fn new() -> Self {
+        ChannelBuilder::default()
+    }
+
+    /// Re-check the queue `n` times before parking an empty `recv`, trading
+    /// CPU for latency when messages are expected imminently.
+    pub fn spin(mut self, n: usize) -> Self {
+        self.spin = n;
+        self
+    }
+
+    /// Keep spinning before parking an empty `recv`, re-checking the queue
+    /// after `min` spin hints, then twice as many each time up to `max`,
+    /// and parking once a round of `max` found nothing. Cheap when messages
+    /// come in bursts, while a long gap costs only a bounded spin. Follows
+    /// any `spin` re-checks.
+    pub fn spin_backoff(mut self, min: usize, max: usize) -> Self {
+        let min = min.max(1);
+        self.spin_backoff = Some((min, max.max(min)));
+        self
+    }
+
+    /// Take exactly one message per `recv` instead of moving the whole
+    /// shared queue into the receiver's buffer, which is fairer for large `T`.
+    pub fn no_bulk_buffer(mut self) -> Self {
+        self.no_bulk_buffer = true;
+        self
+    }
+
+    /// Serve every `recv` straight from the shared queue in send order, so
+    /// with several receivers each one always gets the oldest message still
+    /// queued rather than one holding a batch while another takes newer
+    /// ones. Goes further than `no_bulk_buffer`: messages that would jump
+    /// the queue, a dropped `Delivery` coming back or a control message,
+    /// take their place by sequence number instead. Costs a lock per `recv`.
+    pub fn strict_fifo(mut self) -> Self {
+        self.strict_fifo = true;
+        self.no_bulk_buffer()
+    }
+
+    /// Bound the queue to `capacity` messages; `send` blocks while it's
+    /// full. Bounded channels hand out one message per `recv` instead of
+    /// bulk-buffering, so every receive frees a slot immediately.
+    ///
+    /// Panics if `capacity` is 0: there are no rendezvous channels, and with
+    /// no slot every send would block forever.
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        assert!(
+            capacity > 0,
+            "a channel needs room for at least one message"
+        );
+        self.capacity = Some(capacity);
+        self
+    }
+
+    /// What `send`, `send_cancellable` and `send_with_ttl` do when a bounded
+    /// channel is full. Batch sends, `send_last` and `reserve` always block.
+    pub fn overflow(mut self, policy: OverflowPolicy) -> Self {
+        self.overflow = policy;
+        self
+    }
+
+    /// Make `Receiver::recv_checked` give up with `RecvError::Timeout` once
+    /// it has blocked for `max` without a message, as a safety net against
+    /// hangs. `recv` and the receives built on it are unaffected, so `None`
+    /// still only ever means disconnected. Unlimited by default.
+    pub fn max_block(mut self, max: Duration) -> Self {
+        self.max_block = Some(max);
+        self
+    }
+
+    /// Raise the receiving thread's priority by `PRIORITY_BOOST` while
+    /// `threshold` or more messages wait behind the one being received,
+    /// restoring it once the backlog is drained, so a consumer that fell
+    /// behind catches up sooner. Only on Linux, and usually only with
+    /// `CAP_SYS_NICE`; where the boost isn't allowed this does nothing.
+    ///
+    /// The boost belongs to the thread, not the receiver: a receiver moved
+    /// to another thread while boosted can't restore the thread it left,
+    /// which stays boosted.
+    pub fn backlog_priority_boost(mut self, threshold: usize) -> Self {
+        self.boost_threshold = Some(threshold);
+        self
+    }
+
+    /// Refuse `Sender::send_sized` with `SendError::SizeLimit` once the
+    /// messages sent that way and not yet received would add up to more
+    /// than `n` bytes, capping memory whatever the message count.
+    pub fn max_total_bytes(mut self, n: usize) -> Self {
+        self.max_total_bytes = Some(n);
+        self
+    }
+
+    /// Keep the last `n` slots of a bounded channel free for
+    /// `Sender::send_priority`, so urgent messages still get in while
+    /// ordinary sends find the channel full.
+    pub fn headroom(mut self, n: usize) -> Self {
+        self.headroom = n;
+        self
+    }
+
+    /// Move at most `n` messages into the receiver's private buffer per
+    /// bulk take, leaving the rest in the shared queue for the next one.
+    /// Unlimited by default.
+    pub fn max_buffer(mut self, n: usize) -> Self {
+        self.max_buffer = Some(n);
+        self
+    }
+
+    /// Refuse sends with `SendError::Overflow` once `max` messages wait in
+    /// the shared queue (not counting what the receiver already buffered),
+    /// instead of growing without limit. Unlike `capacity` this never blocks;
+    /// it's a safety valve against a runaway producer.
+    pub fn max_queue(mut self, max: Option<usize>) -> Self {
+        self.max_queue = max;
+        self
+    }
+
+    /// Choose what happens to messages left queued when the last receiver
+    /// drops. The policy fixes the message type, so this comes last, right
+    /// before `build`.
+    pub fn drop_policy<T>(self, policy: DropPolicy<T>) -> WithDropPolicy<T> {
+        WithDropPolicy {
+            builder: self,
+            policy,
+        }
+    }
+
+    /// Yield the thread after every `n` messages served from the receiver's
+    /// private buffer, so a long bulk drain doesn't starve producers. `0`,
+    /// the default, never yields.
+    pub fn yield_every(mut self, n: usize) -> Self {
+        self.yield_every = n;
+        self
+    }
+
+    /// On a bounded channel, wake the senders waiting for space only once
+    /// every `n` messages taken, so blocked producers refill in bursts
+    /// instead of one slot at a time. Emptying the queue always wakes them.
+    /// By default every take wakes one sender.
+    pub fn notify_space_every(mut self, n: usize) -> Self {
+        self.notify_space_every = n;
+        self
+    }
+
+    /// Hand the channel's lock to senders and receivers in the order they
+    /// asked for it, so under contention no producer is starved by others
+    /// that keep winning it back. Costs throughput: each handoff waits for
+    /// the next thread in line to be scheduled.
+    pub fn fair(mut self) -> Self {
+        self.fair = true;
+        self
+    }
+
+    /// Label the channel in `Debug` output and `Receiver::metrics` so logs
+    /// can tell channels apart.
+    pub fn name(mut self, name: &str) -> Self {
+        self.name = Some(name.to_owned());
+        self
+    }
+
+    pub fn on_disconnect(mut self, behavior: Behavior) -> Self {
+        self.on_disconnect = behavior;
+        self
+    }
+
+    pub fn build<T>(self) -> (Sender<T>, Receiver<T>) {
+        self.build_with(DropPolicy::default())
+    }
+
+    /// Build a channel whose ends are closed with an `R`, as
+    /// `Receiver::close_with_reason`, rather than with `Shutdown`.
+    pub fn build_with_reason<T, R: CloseReason>(self) -> (Sender<T, R>, Receiver<T, R>) {
+        self.build_with(DropPolicy::default())
+    }
+
+    fn build_with<T, R>(self, drop_policy: DropPolicy<T>) -> (Sender<T, R>, Receiver<T, R>) {
+        let inner = Inner {
+            queue: VecDeque::default(),
+            capacity: self.capacity,
+            taken_since_notify: 0,
+            tx_count: 1,
+            tx_closed: false,
+            disconnect_seen: false,
+            rx_count: 1,
+            reserved: 0,
+            links: Vec::new(),
+            on_close: Vec::new(),
+            last_arrival: None,
+            next_seq: 0,
+            next_sender: 1,
+            close_reason: None,
+            drop_policy,
+            wakers: Vec::new(),
+            in_flight: 0,
+            redelivered: 0,
+        };
+        let inner = if self.fair {
+            Mutex::fair(inner)
+        } else {
+            Mutex::new(inner)
+        };
+        let shared = Arc::new(Shared {
+            inner,
+            avaliable: Condvar::default(),
+            full: Condvar::default(),
+            config: self,
+            metrics: Metrics::default(),
+            bytes: AtomicUsize::new(0),
+            waiters: AtomicUsize::new(0),
+            redelivered: AtomicBool::new(false),
+            has_wakers: AtomicBool::new(false),
+            paused: AtomicUsize::new(0),
+            buffer_waiters: AtomicUsize::new(0),
+            has_listener: AtomicBool::new(false),
+            listener: Mutex::new(None),
+            has_select_wakers: AtomicBool::new(false),
+            select_wakers: Mutex::new(Vec::new()),
+        });
+        (
+            Sender {
+                shared: Arc::clone(&shared),
+                id: SenderId(0),
+                reason: PhantomData,
+            },
+            Receiver::new(shared),
+        )
+    }
+}
+
+/// A channel's pending messages and send count, from `Receiver::snapshot`.
+#[cfg(feature = "snapshot")]
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Snapshot<T> {
+    pub items: Vec<T>,
+    pub sent: u64,
+}
+
+#[cfg(feature = "snapshot")]
+impl ChannelBuilder {
+    /// Build a channel already holding `snapshot`'s messages, with sequence
+    /// ids and counts carrying on from where it was taken. The messages go
+    /// in even past `capacity` or `max_queue`.
+    pub fn restore<T>(self, snapshot: Snapshot<T>) -> (Sender<T>, Receiver<T>) {
+        let (tx, rx) = self.build();
+        let shared = &rx.shared;
+        let pending = snapshot.items.len() as u64;
+        let sent = snapshot.sent.max(pending);
+        let mut inner = shared.inner.lock();
+        for (seq, value) in (sent - pending..).zip(snapshot.items) {
+            let mut envelope = Envelope::new(value);
+            envelope.seq = seq;
+            inner.queue.push_back(envelope);
+        }
+        inner.next_seq = sent;
+        shared.metrics.set_totals(sent, sent - pending);
+        drop(inner);
+        (tx, rx)
+    }
+}
+
+pub struct WithDropPolicy<T> {
+    builder: ChannelBuilder,
+    policy: DropPolicy<T>,
+}
+
+impl<T> WithDropPolicy<T> {
+    pub fn build(self) -> (Sender<T>, Receiver<T>) {
+        self.builder.build_with(self.policy)
+    }
+}
+
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    ChannelBuilder::new().build()
+}
+
+/// A channel of `capacity` slots, as `ChannelBuilder::capacity`; panics if
+/// `capacity` is 0.
+pub fn bounded<T>(capacity: usize) -> (Sender<T>, Receiver<T>) {
+    ChannelBuilder::new().capacity(capacity).build()
+}
+
+/// A bounded channel of `total` slots, the last `reserved` of which only
+/// `Sender::send_priority` may fill.
+pub fn bounded_with_reserve<T>(total: usize, reserved: usize) -> (Sender<T>, Receiver<T>) {
+    ChannelBuilder::new()
+        .capacity(total)
+        .headroom(reserved)
+        .build()
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn test_rx_tx() -> anyhow::Result<()> {
+        let (tx, mut rx) = channel();
+        let _ = tx.send(1);
+        assert_eq!(rx.recv(), Some(1));
+        Ok(())
+    }
+
+    #[test]
+    fn test_rx_tx_multi_threads() -> anyhow::Result<()> {
+        const PRODUCERS: usize = 8;
+        const CYCLE: usize = 500;
+        // The receiver drains on its own thread while the producers send
+        // from theirs, taking turns in the order the seed picks.
+        let run = |seed| {
+            let scheduler = Arc::new(Scheduler::new(seed, PRODUCERS));
+            let (tx, mut rx) = channel();
+            let jh = std::thread::spawn(move || std::iter::from_fn(|| rx.recv()).collect());
+            let producers: Vec<_> = (0..PRODUCERS)
+                .map(|producer| {
+                    let (tx, scheduler) = (tx.clone(), Arc::clone(&scheduler));
+                    std::thread::spawn(move || {
+                        for i in 0..CYCLE {
+                            scheduler.send(&tx, producer, (producer, i), i + 1 == CYCLE);
+                        }
+                    })
+                })
+                .collect();
+            drop(tx);
+            producers.into_iter().for_each(|jh| jh.join().unwrap());
+            let received: Vec<(usize, usize)> = jh.join().unwrap();
+            let sent = std::mem::take(&mut scheduler.turns.lock().sent);
+            (received, sent)
+        };
+        let (first, sent) = run(42);
+        assert_eq!(first.len(), PRODUCERS * CYCLE);
+        // Received in exactly the order the turns were taken.
+        assert!(first.iter().map(|&(producer, _)| producer).eq(sent));
+        for _ in 0..3 {
+            assert_eq!(run(42).0, first);
+        }
+        assert_ne!(run(7).0, first);
+        Ok(())
+    }
+
+    // Hands out send turns to producers in an order fixed by a seed, so a
+    // multi-producer run interleaves the same way every time.
+    struct Scheduler {
+        turns: Mutex<Turns>,
+        advanced: Condvar,
+    }
+
+    struct Turns {
+        rng: u64,
+        // Which producers still have messages to send.
+        live: Vec<bool>,
+        // The producer whose send is next, or `usize::MAX` once all are done.
+        turn: usize,
+        // Each send's producer, in order.
+        sent: Vec<usize>,
+    }
+
+    impl Turns {
+        fn advance(&mut self) {
+            self.rng ^= self.rng << 13;
+            self.rng ^= self.rng >> 7;
+            self.rng ^= self.rng << 17;
+            let live: Vec<_> = (0..self.live.len()).filter(|&i| self.live[i]).collect();
+            self.turn = live
+                .get(self.rng as usize % live.len().max(1))
+                .copied()
+                .unwrap_or(usize::MAX);
+        }
+    }
+
+    impl Scheduler {
+        fn new(seed: u64, producers: usize) -> Self {
+            let mut turns = Turns {
+                rng: seed | 1,
+                live: vec![true; producers],
+                turn: 0,
+                sent: Vec::new(),
+            };
+            turns.advance();
+            Scheduler {
+                turns: Mutex::new(turns),
+                advanced: Condvar::default(),
+            }
+        }
+
+        fn send<T>(&self, tx: &Sender<T>, producer: usize, value: T, last: bool) {
+            let mut turns = self.turns.lock();
+            while turns.turn != producer {
+                turns = self.advanced.wait(turns);
+            }
+            assert!(tx.send(value).is_ok());
+            turns.sent.push(producer);
+            turns.live[producer] = !last;
+            turns.advance();
+            self.advanced.notify_all();
+        }
+    }
+
+    #[test]
+    fn test_drop_rx() {
+        let (tx, mut rx) = channel::<i32>();
+        drop(tx);
+        let _ = rx.recv();
+    }
+
+    #[test]
+    #[should_panic]
+    fn test_drop_tx() {
+        let (tx, rx) = ChannelBuilder::new()
+            .on_disconnect(Behavior::Panic)
+            .build::<i32>();
+        drop(rx);
         let _ = tx.send(1);
     }
+
+    #[test]
+    fn test_drop_tx_error() {
+        let (tx, rx) = channel::<i32>();
+        drop(rx);
+        assert!(tx.send(1).is_err());
+        assert!(tx.send_all(vec![2]).is_err());
+    }
+
+    #[test]
+    fn test_link_propagates_disconnect() {
+        let (tx1, rx1) = channel::<i32>();
+        let (tx2, mut rx2) = channel::<i32>();
+        rx1.link(&tx2);
+        tx2.send(1).unwrap();
+        drop(rx1);
+        assert_eq!(rx2.recv(), Some(1));
+        assert_eq!(rx2.recv(), None);
+        assert!(tx2.send(2).is_err());
+        drop(tx1);
+    }
+
+    #[test]
+    fn test_link_chain() {
+        let (tx1, rx1) = channel::<i32>();
+        let (tx2, rx2) = channel::<i32>();
+        let (tx3, mut rx3) = channel::<i32>();
+        rx1.link(&tx2);
+        rx2.link(&tx3);
+        drop(tx1);
+        assert_eq!(rx3.recv(), None);
+    }
+
+    #[test]
+    fn test_recv_with_depth() {
+        let (tx, mut rx) = channel();
+        for i in 0..4 {
+            tx.send(i).unwrap();
+        }
+        assert_eq!(rx.recv_with_depth(), Some((0, 3)));
+        assert_eq!(rx.recv_with_depth(), Some((1, 2)));
+        tx.send(4).unwrap();
+        assert_eq!(rx.recv_with_depth(), Some((2, 2)));
+
+        // A send landing between the take and the depth report isn't counted.
+        let (tx, mut rx) = bounded(4);
+        tx.send(0).unwrap();
+        let racer = std::sync::Mutex::new(Some(tx.clone()));
+        rx.set_event_listener(Box::new(move |event| {
+            if event == ChannelEvent::MessageReceived {
+                if let Some(tx) = racer.lock().unwrap().take() {
+                    tx.send(1).unwrap();
+                }
+            }
+        }));
+        assert_eq!(rx.recv_with_depth(), Some((0, 0)));
+        assert_eq!(rx.recv_with_depth(), Some((1, 0)));
+    }
+
+    #[test]
+    fn test_send_cancellable() {
+        let (tx, mut rx) = channel();
+        tx.send(1).unwrap();
+        let token = tx.send_cancellable(2).unwrap();
+        tx.send(3).unwrap();
+        assert!(token.cancel());
+        assert!(!token.cancel());
+        assert_eq!(rx.recv(), Some(1));
+        assert_eq!(rx.recv(), Some(3));
+
+        // A cancelled message gives its slot back.
+        let (tx, _rx) = bounded(2);
+        tx.send(1).unwrap();
+        let token = tx.send_cancellable(2).unwrap();
+        assert!(token.cancel());
+        assert_eq!(tx.send_len(3), Ok(2));
+        assert_eq!(tx.consumer_lag(), 2);
+    }
+
+    #[test]
+    fn test_cancel_after_recv() {
+        let (tx, mut rx) = channel();
+        let token = tx.send_cancellable(1).unwrap();
+        assert_eq!(rx.recv(), Some(1));
+        assert!(!token.cancel());
+    }
+
+    #[test]
+    fn test_spin_still_parks() {
+        let (tx, mut rx) = ChannelBuilder::new().spin(1000).build();
+        let jh = std::thread::spawn(move || rx.recv());
+        std::thread::sleep(Duration::from_millis(50));
+        tx.send(1).unwrap();
+        assert_eq!(jh.join().unwrap(), Some(1));
+
+        let (tx, mut rx) = ChannelBuilder::new().spin(1000).build::<i32>();
+        let jh = std::thread::spawn(move || rx.recv());
+        std::thread::sleep(Duration::from_millis(50));
+        drop(tx);
+        assert_eq!(jh.join().unwrap(), None);
+    }
+
+    #[test]
+    fn test_spin_backoff_still_parks() {
+        let (tx, mut rx) = ChannelBuilder::new().spin_backoff(1, 1 << 12).build();
+        for i in 0..3 {
+            tx.send(i).unwrap();
+        }
+        let jh = std::thread::spawn(move || {
+            let got: Vec<_> = std::iter::from_fn(|| rx.recv()).collect();
+            (got, rx)
+        });
+        std::thread::sleep(Duration::from_millis(50));
+        // Spun out after 13 rounds, so parked by now.
+        assert_eq!(tx.shared.waiters.load(Ordering::SeqCst), 1);
+        tx.send(3).unwrap();
+        drop(tx);
+        let (got, rx) = jh.join().unwrap();
+        assert_eq!(got, [0, 1, 2, 3]);
+        assert!(rx.is_disconnected());
+    }
+
+    #[test]
+    fn test_no_bulk_buffer() {
+        let (tx, mut rx) = ChannelBuilder::new().no_bulk_buffer().build();
+        for i in 0..5 {
+            tx.send(i).unwrap();
+        }
+        for i in 0..5 {
+            assert_eq!(rx.recv(), Some(i));
+            assert_eq!(rx.shared.inner.lock().queue.len(), 4 - i);
+            assert!(rx.buffer.is_empty());
+        }
+    }
+
+    #[test]
+    #[cfg(not(feature = "mpmc"))]
+    fn test_try_clone_requires_mpmc() {
+        let (_tx, rx) = channel::<i32>();
+        assert!(rx.try_clone().is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "mpmc")]
+    fn test_try_clone_mpmc() {
+        let (tx, mut rx1) = channel();
+        let mut rx2 = rx1.try_clone().unwrap();
+        for i in 0..4 {
+            tx.send(i).unwrap();
+        }
+        assert_eq!(rx1.recv(), Some(0));
+        drop(rx1);
+        drop(tx);
+        assert_eq!(
+            std::iter::from_fn(|| rx2.recv()).collect::<Vec<_>>(),
+            vec![1, 2, 3]
+        );
+    }
+
+    #[test]
+    fn test_shutdown_with_deadline() {
+        let (tx, mut rx) = channel();
+        for i in 1..=3 {
+            tx.send(i).unwrap();
+        }
+        let deadline = Instant::now() + Duration::from_secs(1);
+        assert_eq!(rx.shutdown_with_deadline(deadline), vec![1, 2, 3]);
+        assert!(tx.send(4).is_err());
+        assert_eq!(rx.recv(), None);
+    }
+
+    #[test]
+    fn test_shutdown_past_deadline() {
+        let (tx, mut rx) = channel();
+        for i in 1..=3 {
+            tx.send(i).unwrap();
+        }
+        assert_eq!(rx.shutdown_with_deadline(Instant::now()), vec![1, 2, 3]);
+    }
+
+    #[test]
+    fn test_arrival_histogram() {
+        use crate::metrics::arrival_bucket;
+
+        // The slow gaps are long enough that a fast send preempted on a
+        // loaded machine can't plausibly take as long and land with them.
+        let (tx, rx) = channel();
+        for i in 0..3 {
+            tx.send(i).unwrap();
+            std::thread::sleep(Duration::from_millis(100));
+        }
+        for i in 0..100 {
+            tx.send(i).unwrap();
+        }
+        let histogram = rx.arrival_histogram();
+        assert_eq!(histogram.iter().sum::<u64>(), 102);
+        let slow = arrival_bucket(Duration::from_millis(100));
+        assert_eq!(histogram[slow..].iter().sum::<u64>(), 3);
+        assert!(histogram[..slow - 4].iter().sum::<u64>() >= 90);
+    }
+
+    #[test]
+    fn test_send_with_ttl() {
+        let (tx, mut rx) = channel();
+        tx.send_with_ttl(1, Duration::from_millis(10)).unwrap();
+        tx.send_with_ttl(2, Duration::from_secs(60)).unwrap();
+        std::thread::sleep(Duration::from_millis(20));
+        tx.send(3).unwrap();
+        assert_eq!(rx.recv(), Some(2));
+        assert_eq!(rx.recv(), Some(3));
+    }
+
+    #[test]
+    fn test_send_all() {
+        let (tx, mut rx) = channel();
+        tx.send_all((0..1000).collect()).unwrap();
+        drop(tx);
+        assert_eq!(
+            std::iter::from_fn(|| rx.recv()).collect::<Vec<_>>(),
+            (0..1000).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_send_all_disconnected() {
+        let (tx, rx) = channel();
+        drop(rx);
+        assert_eq!(
+            tx.send_all(vec![1, 2, 3]),
+            Err(SendError::Disconnected(vec![1, 2, 3]))
+        );
+    }
+
+    #[test]
+    fn test_lock_and_drain() {
+        use std::sync::atomic::AtomicUsize;
+
+        let (tx, mut rx) = channel();
+        for i in 0..5 {
+            tx.send(i).unwrap();
+        }
+        let sent = Arc::new(AtomicUsize::new(0));
+        let jh = {
+            let tx = tx.clone();
+            let guard = rx.lock_and_drain();
+            let producer_sent = Arc::clone(&sent);
+            let jh = std::thread::spawn(move || {
+                tx.send(5).unwrap();
+                producer_sent.fetch_add(1, Ordering::SeqCst);
+            });
+            std::thread::sleep(Duration::from_millis(50));
+            assert_eq!(sent.load(Ordering::SeqCst), 0);
+            assert_eq!(guard.collect::<Vec<_>>(), vec![0, 1, 2, 3, 4]);
+            jh
+        };
+        jh.join().unwrap();
+        assert_eq!(sent.load(Ordering::SeqCst), 1);
+        assert_eq!(rx.recv(), Some(5));
+    }
+
+    #[test]
+    fn test_send_after_rx_drop() {
+        let (tx, rx) = channel();
+        tx.send(1).unwrap();
+        drop(rx);
+        assert_eq!(tx.send(2), Err(SendError::Disconnected(2)));
+        assert!(tx.shared.inner.lock().queue.is_empty());
+    }
+
+    #[test]
+    fn test_barrier() {
+        use std::sync::atomic::AtomicUsize;
+
+        let (tx, mut rx) = channel();
+        let received = Arc::new(AtomicUsize::new(0));
+        let consumer = {
+            let received = Arc::clone(&received);
+            std::thread::spawn(move || {
+                while rx.recv().is_some() {
+                    std::thread::sleep(Duration::from_millis(1));
+                    received.fetch_add(1, Ordering::SeqCst);
+                }
+            })
+        };
+        let producers = (0..3)
+            .map(|_| {
+                let tx = tx.clone();
+                std::thread::spawn(move || {
+                    for i in 0..10 {
+                        tx.send(i).unwrap();
+                    }
+                })
+            })
+            .collect::<Vec<_>>();
+        for producer in producers {
+            producer.join().unwrap();
+        }
+        tx.barrier().unwrap();
+        assert_eq!(received.load(Ordering::SeqCst), 30);
+        drop(tx);
+        consumer.join().unwrap();
+        assert_eq!(received.load(Ordering::SeqCst), 30);
+    }
+
+    #[test]
+    fn test_barrier_receiver_dropped() {
+        let (tx, rx) = channel::<i32>();
+        tx.send(1).unwrap();
+        let jh = std::thread::spawn(move || tx.barrier());
+        std::thread::sleep(Duration::from_millis(20));
+        drop(rx);
+        assert!(jh.join().unwrap().is_ok());
+    }
+
+    #[test]
+    fn test_recv_interruptible() {
+        let (tx, mut rx) = channel::<i32>();
+        let interrupt = Arc::new(AtomicBool::new(false));
+        tx.send(1).unwrap();
+        assert_eq!(rx.recv_interruptible(&interrupt), Ok(Some(1)));
+
+        let flag = Arc::clone(&interrupt);
+        let jh = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            flag.store(true, Ordering::Release);
+            Instant::now()
+        });
+        assert_eq!(rx.recv_interruptible(&interrupt), Err(Interrupted));
+        let interrupted_at = jh.join().unwrap();
+        assert!(interrupted_at.elapsed() < INTERRUPT_POLL * 10);
+        drop(tx);
+    }
+
+    #[test]
+    fn test_bounded_blocks_when_full() {
+        let (tx, mut rx) = bounded(2);
+        tx.send(1).unwrap();
+        tx.send(2).unwrap();
+        let jh = std::thread::spawn(move || {
+            tx.send(3).unwrap();
+            tx.send_all(vec![4, 5, 6]).unwrap();
+        });
+        std::thread::sleep(Duration::from_millis(20));
+        assert_eq!(rx.shared.inner.lock().queue.len(), 2);
+        assert_eq!(
+            std::iter::from_fn(|| rx.recv()).collect::<Vec<_>>(),
+            vec![1, 2, 3, 4, 5, 6]
+        );
+        jh.join().unwrap();
+    }
+
+    #[test]
+    fn test_blocked_send_fails_on_receiver_drop() {
+        let (tx, rx) = bounded(1);
+        tx.send(1).unwrap();
+        let jh = std::thread::spawn(move || tx.send(2));
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(!jh.is_finished());
+        drop(rx);
+        assert_eq!(jh.join().unwrap(), Err(SendError::Disconnected(2)));
+    }
+
+    #[test]
+    fn test_send_if_space() {
+        let (tx, mut rx) = bounded(1);
+        assert_eq!(tx.send_if_space(1), Ok(None));
+        assert_eq!(tx.send_if_space(2), Ok(Some(2)));
+        assert_eq!(rx.recv(), Some(1));
+        assert_eq!(tx.send_if_space(3), Ok(None));
+        drop(rx);
+        assert_eq!(tx.send_if_space(4), Err(SendError::Disconnected(4)));
+    }
+
+    #[test]
+    fn test_recv_latest() {
+        let (tx, mut rx) = channel();
+        for i in 1..=3 {
+            tx.send(i).unwrap();
+        }
+        assert_eq!(rx.recv_latest(), Some(3));
+        assert!(rx.buffer.is_empty());
+        assert!(rx.shared.inner.lock().queue.is_empty());
+        tx.send(4).unwrap();
+        assert_eq!(rx.recv_latest(), Some(4));
+        drop(tx);
+        assert_eq!(rx.recv_latest(), None);
+    }
+
+    #[test]
+    fn test_reserve() {
+        let (tx, mut rx) = bounded(5);
+        let other = tx.clone();
+        let mut reservation = tx.reserve(3).unwrap();
+        assert!(tx.reserve(6).is_none());
+
+        let blocked = std::thread::spawn(move || {
+            let mut reservation = other.reserve(3).unwrap();
+            reservation.send(10).unwrap();
+        });
+        std::thread::sleep(Duration::from_millis(20));
+        assert!(!blocked.is_finished());
+
+        {
+            let mut two = tx.reserve(2).unwrap();
+            assert_eq!(tx.send_if_space(0), Ok(Some(0)));
+            two.send(4).unwrap();
+        }
+        reservation.send(1).unwrap();
+        reservation.send(2).unwrap();
+        reservation.send(3).unwrap();
+        assert_eq!(reservation.send(9), Err(SendError::Overflow(9)));
+        drop(reservation);
+
+        assert_eq!(rx.recv(), Some(4));
+        assert_eq!(rx.recv(), Some(1));
+        blocked.join().unwrap();
+        drop(tx);
+        assert_eq!(
+            std::iter::from_fn(|| rx.recv()).collect::<Vec<_>>(),
+            vec![2, 3, 10]
+        );
+    }
+
+    #[test]
+    fn test_enumerate_recv() {
+        let (tx, mut rx) = channel();
+        tx.send('a').unwrap();
+        tx.send_cancellable('b').unwrap().cancel();
+        tx.send_with_ttl('c', Duration::ZERO).unwrap();
+        tx.send_all(vec!['d', 'e']).unwrap();
+        drop(tx);
+        assert_eq!(
+            rx.enumerate_recv().collect::<Vec<_>>(),
+            vec![(0, 'a'), (3, 'd'), (4, 'e')]
+        );
+    }
+
+    #[test]
+    fn test_is_disconnected() {
+        let (tx, mut rx) = channel();
+        tx.send(1).unwrap();
+        tx.send(2).unwrap();
+        tx.send_cancellable(3).unwrap().cancel();
+        assert!(!rx.is_disconnected());
+        drop(tx);
+        assert!(!rx.is_disconnected());
+        assert_eq!(rx.recv(), Some(1));
+        assert!(!rx.is_disconnected());
+        assert_eq!(rx.recv(), Some(2));
+        assert!(rx.is_disconnected());
+        assert_eq!(rx.recv(), None);
+    }
+
+    #[test]
+    fn test_send_last() {
+        let (tx, mut rx) = channel();
+        let other = tx.clone();
+        tx.send(1).unwrap();
+        other.send(2).unwrap();
+        tx.send_last(3).unwrap();
+        assert_eq!(other.send(4), Err(SendError::Disconnected(4)));
+        assert_eq!(rx.recv(), Some(1));
+        assert_eq!(rx.recv(), Some(2));
+        assert_eq!(rx.recv(), Some(3));
+        assert_eq!(rx.recv(), None);
+    }
+
+    #[test]
+    fn test_yield_every() {
+        let (tx, mut rx) = ChannelBuilder::new().yield_every(10).build();
+        tx.send_all((0..100).collect()).unwrap();
+        assert_eq!(rx.recv(), Some(0));
+        assert_eq!(rx.buffer.len(), 99);
+        for i in 1..100 {
+            assert_eq!(rx.recv(), Some(i));
+        }
+        // Once per ten served from the buffer; the first came off the queue.
+        assert_eq!(rx.yields, 9);
+
+        let (tx, mut rx) = channel();
+        tx.send_all((0..100).collect()).unwrap();
+        drop(tx);
+        while rx.recv().is_some() {}
+        assert_eq!(rx.yields, 0);
+    }
+
+    #[test]
+    fn test_recv_until_err() {
+        let (tx, mut rx) = channel::<Result<i32, &str>>();
+        tx.send(Ok(1)).unwrap();
+        tx.send(Ok(2)).unwrap();
+        tx.send(Err("boom")).unwrap();
+        let mut got = Vec::new();
+        let err = loop {
+            match rx.recv_until_err() {
+                Ok(Some(v)) => got.push(v),
+                Ok(None) => unreachable!(),
+                Err(e) => break e,
+            }
+        };
+        assert_eq!(got, vec![1, 2]);
+        assert_eq!(err, "boom");
+        assert!(tx.send(Ok(3)).is_err());
+        assert_eq!(rx.recv_until_err(), Ok(None));
+    }
+
+    #[test]
+    fn test_skip_notify_stress() {
+        // Every send races the receiver parking; a lost wakeup hangs here.
+        for _ in 0..200 {
+            let (tx, mut rx) = channel();
+            let jh = std::thread::spawn(move || {
+                for i in 0..50 {
+                    tx.send(i).unwrap();
+                    if i % 7 == 0 {
+                        std::thread::yield_now();
+                    }
+                }
+            });
+            let mut next = 0;
+            while let Some(v) = rx.recv() {
+                assert_eq!(v, next);
+                next += 1;
+            }
+            assert_eq!(next, 50);
+            jh.join().unwrap();
+        }
+    }
+
+    #[test]
+    fn test_shared_receiver() {
+        let (tx, rx) = channel();
+        let rx = SharedReceiver::new(rx);
+        let consumers: Vec<_> = (0..2)
+            .map(|_| {
+                let rx = rx.clone();
+                std::thread::spawn(move || std::iter::from_fn(|| rx.recv()).collect::<Vec<_>>())
+            })
+            .collect();
+        for i in 0..1000 {
+            tx.send(i).unwrap();
+        }
+        drop(tx);
+        let mut got: Vec<i32> = consumers
+            .into_iter()
+            .flat_map(|jh| jh.join().unwrap())
+            .collect();
+        got.sort();
+        assert_eq!(got, (0..1000).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn test_name_in_debug() {
+        let (tx, rx) = ChannelBuilder::new().name("jobs").build::<i32>();
+        assert!(format!("{:?}", tx).contains("\"jobs\""));
+        assert!(format!("{:?}", rx).contains("\"jobs\""));
+        assert_eq!(rx.name(), Some("jobs"));
+        let (tx, _rx) = channel::<i32>();
+        assert!(format!("{:?}", tx).contains("None"));
+    }
+
+    #[test]
+    fn test_name_in_metrics() {
+        let (tx, mut rx) = ChannelBuilder::new().name("jobs").build::<i32>();
+        tx.send_all(vec![1, 2, 3]).unwrap();
+        assert_eq!(rx.recv(), Some(1));
+        let report = rx.metrics();
+        assert_eq!(report.name.as_deref(), Some("jobs"));
+        assert_eq!(
+            report.to_string(),
+            "jobs: sent 3, received 1, dropped 0, lag 2 (peak 3)"
+        );
+        let (_tx, rx) = channel::<i32>();
+        assert!(rx.metrics().to_string().starts_with("unnamed channel: "));
+    }
+
+    #[test]
+    fn test_select_op_picks_ready_recv() {
+        let (full_tx, mut full_rx) = bounded::<i32>(1);
+        full_tx.send(1).unwrap();
+        let (tx, mut rx) = channel::<i32>();
+        tx.send(7).unwrap();
+        let mut ops = [Op::send(&full_tx, 2), Op::recv(&mut rx)];
+        assert_eq!(select_op(&mut ops), Selected::Recv(1, Some(7)));
+        assert!(matches!(ops[0], Op::Send(_, Some(2))));
+
+        // Once the bounded channel has room the send goes through.
+        assert_eq!(full_rx.recv(), Some(1));
+        assert_eq!(select_op(&mut ops), Selected::Send(0, Ok(())));
+        assert_eq!(full_rx.recv(), Some(2));
+    }
+
+    #[test]
+    fn test_select_op_parks_until_woken() {
+        let (full_tx, full_rx) = bounded::<i32>(1);
+        full_tx.send(1).unwrap();
+        let (tx, mut rx) = channel::<i32>();
+
+        // Room made from another thread wakes a parked send.
+        let taker = std::thread::spawn(move || {
+            let mut full_rx = full_rx;
+            std::thread::sleep(Duration::from_millis(20));
+            assert_eq!(full_rx.recv(), Some(1));
+            full_rx
+        });
+        let mut ops = [Op::send(&full_tx, 2), Op::recv(&mut rx)];
+        assert_eq!(select_op(&mut ops), Selected::Send(0, Ok(())));
+        let mut full_rx = taker.join().unwrap();
+
+        // And a message from another thread wakes a parked receive.
+        let sender = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(20));
+            tx.send(3).unwrap();
+        });
+        let mut ops = [Op::send(&full_tx, 4), Op::recv(&mut rx)];
+        assert_eq!(select_op(&mut ops), Selected::Recv(1, Some(3)));
+        sender.join().unwrap();
+        assert_eq!(full_rx.recv(), Some(2));
+    }
+
+    #[test]
+    fn test_select_op_forgets_its_wakers() {
+        let (full_tx, _full_rx) = bounded::<i32>(1);
+        full_tx.send(0).unwrap();
+        let (tx, mut rx) = channel::<i32>();
+        for i in 0..100 {
+            tx.send(i).unwrap();
+            let mut ops = [Op::send(&full_tx, i), Op::recv(&mut rx)];
+            assert_eq!(select_op(&mut ops), Selected::Recv(1, Some(i)));
+            assert!(full_tx.shared.select_wakers.lock().len() <= 1);
+        }
+        assert!(full_tx.shared.select_wakers.lock().is_empty());
+
+        // A receive that didn't win leaves nothing behind either.
+        let (room_tx, _room_rx) = channel::<i32>();
+        let mut ops = [Op::recv(&mut rx), Op::send(&room_tx, 1)];
+        assert_eq!(select_op(&mut ops), Selected::Send(1, Ok(())));
+        assert!(rx.shared.inner.lock().wakers.is_empty());
+    }
+
+    #[test]
+    fn test_overflow_policy() {
+        let run = |policy| {
+            let (tx, mut rx) = ChannelBuilder::new()
+                .capacity(2)
+                .overflow(policy)
+                .build::<i32>();
+            for i in 1..=3 {
+                tx.send(i).unwrap();
+            }
+            drop(tx);
+            std::iter::from_fn(|| rx.recv()).collect::<Vec<_>>()
+        };
+        assert_eq!(run(OverflowPolicy::DropNewest), vec![1, 2]);
+        assert_eq!(run(OverflowPolicy::DropOldest), vec![2, 3]);
+
+        let (tx, mut rx) = bounded(2);
+        let jh = std::thread::spawn(move || {
+            for i in 1..=3 {
+                tx.send(i).unwrap();
+            }
+        });
+        assert_eq!(
+            std::iter::from_fn(|| rx.recv()).collect::<Vec<_>>(),
+            vec![1, 2, 3]
+        );
+        jh.join().unwrap();
+    }
+
+    #[test]
+    fn test_forward_to_writer() {
+        let (tx, rx) = channel();
+        for chunk in [&b"foo"[..], b"", b"barbaz"] {
+            tx.send(chunk.to_vec()).unwrap();
+        }
+        drop(tx);
+        let mut out = Vec::new();
+        assert_eq!(forward_to_writer(rx, &mut out).unwrap(), 9);
+        assert_eq!(out, b"foobarbaz");
+    }
+
+    #[test]
+    fn test_delivery_redelivered_unless_acked() {
+        let (tx, mut rx) = channel();
+        tx.send(1).unwrap();
+        tx.send(2).unwrap();
+        let delivery = rx.recv_delivery().unwrap();
+        assert_eq!(*delivery, 1);
+        assert_eq!(rx.in_flight(), 1);
+        drop(delivery);
+        assert_eq!(rx.in_flight(), 0);
+
+        let delivery = rx.recv_delivery().unwrap();
+        assert_eq!(*delivery, 1);
+        let worker = std::thread::spawn(move || {
+            let _delivery = delivery;
+            panic!("consumer crashed");
+        });
+        assert!(worker.join().is_err());
+
+        assert_eq!(rx.recv_delivery().unwrap().ack(), 1);
+        assert_eq!(rx.recv_delivery().unwrap().ack(), 2);
+        assert_eq!(rx.in_flight(), 0);
+    }
+
+    #[test]
+    fn test_buffer_capacity_stabilizes() {
+        let (tx, mut rx) = channel();
+        let mut capacities = Vec::new();
+        for round in 0..50 {
+            for i in 0..100 {
+                tx.send(round * 100 + i).unwrap();
+            }
+            for i in 0..100 {
+                assert_eq!(rx.recv(), Some(round * 100 + i));
+            }
+            capacities.push((
+                rx.buffer.capacity(),
+                rx.shared.inner.lock().queue.capacity(),
+            ));
+        }
+        // After warming up neither side reallocates or hands its buffer over.
+        assert!(capacities[2..].iter().all(|&c| c == capacities[2]));
+        assert!(capacities[2].0 >= 99);
+    }
+
+    #[test]
+    fn test_max_queue() {
+        let (tx, mut rx) = ChannelBuilder::new().max_queue(Some(2)).build();
+        tx.send(1).unwrap();
+        tx.send(2).unwrap();
+        assert_eq!(tx.send(3), Err(SendError::Overflow(3)));
+        assert_eq!(tx.send_if_space(3), Ok(Some(3)));
+        // Receiving moves everything queued into the receiver's buffer,
+        // which doesn't count towards the limit.
+        assert_eq!(rx.recv(), Some(1));
+        assert_eq!(
+            tx.send_all(vec![3, 4, 5]),
+            Err(SendError::Overflow(vec![5]))
+        );
+        drop(tx);
+        assert_eq!(
+            std::iter::from_fn(|| rx.recv()).collect::<Vec<_>>(),
+            vec![2, 3, 4]
+        );
+    }
+
+    #[test]
+    fn test_close_with_reason() {
+        #[derive(Clone, Debug, PartialEq)]
+        enum Reason {
+            Shutdown,
+            Failed(String),
+        }
+
+        impl From<Shutdown> for Reason {
+            fn from(_: Shutdown) -> Self {
+                Reason::Shutdown
+            }
+        }
+
+        let (tx, mut rx) = ChannelBuilder::new().build_with_reason();
+        tx.send(1).unwrap();
+        rx.close_with_reason(Reason::Failed("disk full".into()));
+        rx.close_with_reason(Reason::Shutdown);
+        tx.begin_shutdown();
+        match tx.send(2) {
+            Err(SendError::Closed(2, Reason::Failed(why))) => assert_eq!(why, "disk full"),
+            other => panic!("send should fail with the first close reason: {other:?}"),
+        }
+        assert_eq!(rx.recv(), Some(1));
+        assert_eq!(rx.recv(), None);
+    }
+
+    #[test]
+    fn test_recv_with_token() {
+        let (tx, mut rx) = channel::<i32>();
+        let token = CancellationToken::new();
+        tx.send(1).unwrap();
+        assert_eq!(rx.recv_with_token(&token), Some(1));
+
+        let canceller = token.clone();
+        let jh = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(20));
+            canceller.cancel();
+            Instant::now()
+        });
+        assert_eq!(rx.recv_with_token(&token), None);
+        let returned = Instant::now();
+        // Nothing polls the token, so only the wake can end the wait; the
+        // bound is loose because the two threads may share one busy core.
+        assert!(returned - jh.join().unwrap() < Duration::from_millis(50));
+        assert!(token.is_cancelled());
+        assert!(token.inner.wakes.lock().1.is_empty());
+        drop(tx);
+    }
+
+    #[test]
+    fn test_send_all_progress() {
+        let (tx, mut rx) = channel();
+        let mut reports = Vec::new();
+        tx.send_all_progress((0..100).collect(), 10, |sent| reports.push(sent))
+            .unwrap();
+        assert_eq!(reports, (1..=10).map(|i| i * 10).collect::<Vec<_>>());
+        drop(tx);
+        assert_eq!(
+            std::iter::from_fn(|| rx.recv()).collect::<Vec<_>>(),
+            (0..100).collect::<Vec<_>>()
+        );
+    }
+
+    #[test]
+    fn test_call() {
+        let (tx, mut rx) = channel::<(i32, Responder<i32>)>();
+        let worker = std::thread::spawn(move || {
+            while let Some((request, responder)) = rx.recv() {
+                if request >= 0 {
+                    responder.respond(request * 2);
+                }
+            }
+        });
+        assert_eq!(tx.call(21), Some(42));
+        assert_eq!(tx.call(-1), None);
+        drop(tx);
+        worker.join().unwrap();
+    }
+
+    #[test]
+    fn test_consumer_lag() {
+        let (tx, mut rx) = channel();
+        for i in 0..10 {
+            tx.send(i).unwrap();
+        }
+        for _ in 0..4 {
+            rx.recv().unwrap();
+        }
+        assert_eq!(rx.consumer_lag(), 6);
+        drop(rx.recv_delivery());
+        assert_eq!(rx.consumer_lag(), 6);
+        rx.recv_delivery().unwrap().ack();
+        assert_eq!(rx.consumer_lag(), 5);
+    }
+
+    #[test]
+    fn test_peak_lag() {
+        let (tx, mut rx) = channel();
+        for i in 0..5 {
+            tx.send(i).unwrap();
+        }
+        while rx.consumer_lag() > 0 {
+            rx.recv().unwrap();
+        }
+        assert_eq!(rx.peak_lag(), 5);
+        tx.send(5).unwrap();
+        assert_eq!(rx.peak_lag(), 5);
+        rx.recv().unwrap();
+        rx.reset_peak_lag();
+        assert_eq!(rx.peak_lag(), 0);
+    }
+
+    #[test]
+    #[cfg(feature = "mpmc")]
+    fn test_transfer_buffered() {
+        let (tx, mut rx1) = channel();
+        let mut rx2 = rx1.try_clone().unwrap();
+        for i in 0..11 {
+            tx.send(i).unwrap();
+        }
+        // The first recv bulk-buffers the other ten.
+        assert_eq!(rx1.recv(), Some(0));
+        assert_eq!(rx1.buffer.len(), 10);
+        assert_eq!(rx1.transfer_buffered(&mut rx2, 5), 5);
+        assert_eq!(rx1.transfer_buffered(&mut rx2, 0), 0);
+        drop(tx);
+        let first: Vec<_> = std::iter::from_fn(|| rx1.recv()).collect();
+        let second: Vec<_> = std::iter::from_fn(|| rx2.recv()).collect();
+        assert_eq!(first, vec![1, 2, 3, 4, 5]);
+        assert_eq!(second, vec![6, 7, 8, 9, 10]);
+    }
+
+    #[test]
+    fn test_drop_policy() {
+        use std::sync::atomic::AtomicUsize;
+
+        static DROPPED: AtomicUsize = AtomicUsize::new(0);
+        #[derive(Debug)]
+        struct Counted(u32);
+        impl Drop for Counted {
+            fn drop(&mut self) {
+                DROPPED.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+        // Five queued messages, one of them already in the receiver's buffer.
+        let fill = |tx: &Sender<Counted>, rx: &mut Receiver<Counted>| {
+            tx.send(Counted(0)).unwrap();
+            tx.send(Counted(1)).unwrap();
+            drop(rx.recv());
+            for i in 2..6 {
+                tx.send(Counted(i)).unwrap();
+            }
+            DROPPED.store(0, Ordering::SeqCst);
+        };
+
+        let (tx, mut rx) = channel();
+        fill(&tx, &mut rx);
+        drop(rx);
+        assert_eq!(DROPPED.load(Ordering::SeqCst), 5);
+
+        let (tx, mut rx) = ChannelBuilder::new()
+            .drop_policy(DropPolicy::Discard)
+            .build();
+        fill(&tx, &mut rx);
+        drop(rx);
+        assert_eq!(DROPPED.load(Ordering::SeqCst), 0);
+
+        let handed = Arc::new(Mutex::new(Vec::new()));
+        let sink = Arc::clone(&handed);
+        let (tx, mut rx) = ChannelBuilder::new()
+            .drop_policy(DropPolicy::Callback(Box::new(move |v: Counted| {
+                sink.lock().push(v.0);
+                std::mem::forget(v);
+            })))
+            .build();
+        fill(&tx, &mut rx);
+        drop(rx);
+        assert_eq!(DROPPED.load(Ordering::SeqCst), 0);
+        assert_eq!(*handed.lock(), vec![1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn test_recv_into_slice() {
+        let (tx, mut rx) = channel();
+        for i in 0..5 {
+            tx.send(i).unwrap();
+        }
+        let mut out = [MaybeUninit::<i32>::uninit(); 8];
+        let n = rx.recv_into_slice(&mut out);
+        assert_eq!(n, 5);
+        let got: Vec<i32> = out[..n]
+            .iter()
+            .map(|slot| unsafe { slot.assume_init() })
+            .collect();
+        assert_eq!(got, vec![0, 1, 2, 3, 4]);
+        assert_eq!(rx.recv_into_slice(&mut []), 0);
+        drop(tx);
+        assert_eq!(rx.recv_into_slice(&mut out), 0);
+    }
+
+    #[test]
+    fn test_send_len() {
+        let (tx, mut rx) = channel();
+        assert_eq!(tx.send_len(1), Ok(1));
+        assert_eq!(tx.send_len(2), Ok(2));
+        assert_eq!(tx.send_len(3), Ok(3));
+        // The receiver takes the whole queue into its buffer.
+        assert_eq!(rx.recv(), Some(1));
+        assert_eq!(tx.send_len(4), Ok(1));
+        drop(rx);
+        assert_eq!(tx.send_len(5), Err(SendError::Disconnected(5)));
+    }
+
+    #[test]
+    fn test_receiver_panic_disconnects() {
+        let (tx, mut rx) = bounded(1);
+        tx.send(1).unwrap();
+        let blocked = {
+            let tx = tx.clone();
+            std::thread::spawn(move || tx.send(2))
+        };
+        let consumer = std::thread::spawn(move || {
+            // Panicking under the drain guard poisons the channel's lock.
+            let _drain = rx.lock_and_drain();
+            panic!("consumer failed mid-drain");
+        });
+        assert!(consumer.join().is_err());
+        assert_eq!(tx.send(3), Err(SendError::Disconnected(3)));
+        assert!(blocked.join().unwrap().is_err());
+    }
+
+    #[test]
+    #[cfg(feature = "mpmc")]
+    fn test_strict_fifo() {
+        let run = |builder: ChannelBuilder| {
+            let (tx, mut rx1) = builder.build();
+            let mut rx2 = rx1.try_clone().unwrap();
+            tx.send(1).unwrap();
+            tx.send(2).unwrap();
+            let first = rx1.recv().unwrap();
+            tx.send(3).unwrap();
+            (first, rx2.recv().unwrap())
+        };
+        // Buffered, rx1 holds 2 while rx2 takes the newer 3.
+        assert_eq!(run(ChannelBuilder::new()), (1, 3));
+        assert_eq!(run(ChannelBuilder::new().strict_fifo()), (1, 2));
+
+        // Deliveries handed back out of order.
+        let redeliver = |builder: ChannelBuilder| {
+            let (tx, mut rx1) = builder.build();
+            let mut rx2 = rx1.try_clone().unwrap();
+            for i in 1..=3 {
+                tx.send(i).unwrap();
+            }
+            let first = rx1.recv_delivery().unwrap();
+            let second = rx2.recv_delivery().unwrap();
+            drop(second);
+            drop(first);
+            drop(tx);
+            std::iter::from_fn(|| rx1.recv()).collect::<Vec<_>>()
+        };
+        // Otherwise each goes behind the redeliveries before it, so 1 ends
+        // up after 2.
+        assert_eq!(redeliver(ChannelBuilder::new().no_bulk_buffer()), [2, 1, 3]);
+        assert_eq!(redeliver(ChannelBuilder::new().strict_fifo()), [1, 2, 3]);
+    }
+
+    #[test]
+    fn test_recv_origin() {
+        let (tx, mut rx) = channel();
+        for i in 0..3 {
+            tx.send(i).unwrap();
+        }
+        assert_eq!(rx.recv_origin(), Some((0, Origin::Queue)));
+        assert_eq!(rx.recv_origin(), Some((1, Origin::Buffer)));
+        assert_eq!(rx.recv_origin(), Some((2, Origin::Buffer)));
+        let jh = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(20));
+            tx.send(3).unwrap();
+        });
+        assert_eq!(rx.recv_origin(), Some((3, Origin::AfterWait)));
+        jh.join().unwrap();
+    }
+
+    #[test]
+    fn test_recv_rate() {
+        let (tx, mut rx) = channel();
+        assert_eq!(rx.recv_rate(Duration::from_secs(1)), 0.0);
+        let jh = std::thread::spawn(move || {
+            for i in 0..30 {
+                tx.send(i).unwrap();
+                std::thread::sleep(Duration::from_millis(10));
+            }
+        });
+        while rx.recv().is_some() {}
+        jh.join().unwrap();
+        // Roughly 100/s; wide margins for a busy machine's sleeps.
+        let rate = rx.recv_rate(Duration::from_millis(200));
+        assert!((40.0..=150.0).contains(&rate), "rate {rate}");
+
+        let (tx, mut rx) = channel();
+        for i in 0..RATE_SAMPLES * 2 {
+            tx.send(i).unwrap();
+        }
+        while rx.recv_with_depth().is_some_and(|(_, depth)| depth > 0) {}
+        assert_eq!(rx.recent.len(), RATE_SAMPLES);
+        assert!(rx.recv_rate(Duration::from_secs(1)) > RATE_SAMPLES as f64);
+    }
+
+    #[test]
+    fn test_try_reserve() {
+        let (tx, mut rx) = bounded(4);
+        let mut two = tx.try_reserve(2).unwrap();
+        assert!(tx.try_reserve(3).is_none());
+        assert!(tx.try_reserve(5).is_none());
+        two.send(1).unwrap();
+        let mut rest = tx.try_reserve(2).unwrap();
+        assert!(tx.try_reserve(1).is_none());
+        rest.send(2).unwrap();
+        drop((two, rest));
+        assert_eq!(rx.recv(), Some(1));
+        assert!(tx.try_reserve(3).is_some());
+    }
+
+    #[test]
+    fn test_auto_flush() {
+        let (tx, mut rx) = channel();
+        let interval = Duration::from_millis(20);
+        let batched = tx.with_auto_flush(interval);
+        let consumer = std::thread::spawn(move || {
+            for i in 0..10_000 {
+                assert_eq!(rx.recv(), Some(i));
+            }
+        });
+        let start = Instant::now();
+        for i in 0..10_000 {
+            batched.send(i).unwrap();
+        }
+        // With both senders still alive, the timer delivers the burst's tail.
+        consumer.join().unwrap();
+        let elapsed = start.elapsed();
+        let flushes = batched.state.flushes.load(Ordering::Relaxed);
+        assert!(flushes as u128 <= elapsed.as_millis() / interval.as_millis() + 2);
+        drop(tx);
+    }
+
+    #[test]
+    fn test_auto_flush_on_drop() {
+        let (tx, mut rx) = channel();
+        let probe = rx.blocked_probe();
+        let consumer =
+            std::thread::spawn(move || (0..3).map(|_| rx.recv().unwrap()).collect::<Vec<_>>());
+        while !probe.is_blocked_waiting() {
+            std::thread::yield_now();
+        }
+        // Too long an interval for the timer to ever flush.
+        let batched = tx.with_auto_flush(Duration::from_secs(3600));
+        for i in 0..3 {
+            batched.send(i).unwrap();
+        }
+        drop(batched);
+        assert_eq!(consumer.join().unwrap(), [0, 1, 2]);
+        drop(tx);
+    }
+
+    #[test]
+    fn test_poll_recv() {
+        use std::task::Wake;
+
+        #[derive(Default)]
+        struct CountingWaker(AtomicUsize);
+        impl Wake for CountingWaker {
+            fn wake(self: Arc<Self>) {
+                self.0.fetch_add(1, Ordering::SeqCst);
+            }
+        }
+
+        let (tx, mut rx) = channel();
+        tx.send(1).unwrap();
+        let mut noop = Context::from_waker(Waker::noop());
+        assert_eq!(rx.poll_recv(&mut noop), Poll::Ready(Some(1)));
+
+        let counter = Arc::new(CountingWaker::default());
+        let waker = Waker::from(Arc::clone(&counter));
+        let mut cx = Context::from_waker(&waker);
+        assert_eq!(rx.poll_recv(&mut cx), Poll::Pending);
+        assert_eq!(rx.poll_recv(&mut cx), Poll::Pending);
+        tx.send(2).unwrap();
+        tx.send(3).unwrap();
+        // Registered once despite two polls, and woken once.
+        assert_eq!(counter.0.load(Ordering::SeqCst), 1);
+        assert_eq!(rx.poll_recv(&mut cx), Poll::Ready(Some(2)));
+        assert_eq!(rx.poll_recv(&mut cx), Poll::Ready(Some(3)));
+
+        assert_eq!(rx.poll_recv(&mut cx), Poll::Pending);
+        drop(tx);
+        assert_eq!(counter.0.load(Ordering::SeqCst), 2);
+        assert_eq!(rx.poll_recv(&mut cx), Poll::Ready(None));
+    }
+
+    #[test]
+    fn test_pause() {
+        let (tx, mut rx) = channel();
+        tx.send(1).unwrap();
+        tx.send(2).unwrap();
+        assert_eq!(rx.recv(), Some(1));
+        // 2 now sits in the receiver's buffer; 3 goes to the queue.
+        let guard = rx.pause();
+        tx.send(3).unwrap();
+        assert_eq!(rx.recv_ready(), None);
+        let delivered = Arc::new(AtomicBool::new(false));
+        let jh = {
+            let delivered = Arc::clone(&delivered);
+            std::thread::spawn(move || {
+                let v = rx.recv();
+                delivered.store(true, Ordering::SeqCst);
+                (v, rx.recv())
+            })
+        };
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(!delivered.load(Ordering::SeqCst));
+        drop(guard);
+        assert_eq!(jh.join().unwrap(), (Some(2), Some(3)));
+    }
+
+    #[test]
+    fn test_is_blocked_waiting() {
+        let (tx, mut rx) = channel();
+        assert!(!rx.is_blocked_waiting());
+        let probe = rx.blocked_probe();
+        let jh = std::thread::spawn(move || {
+            let v = rx.recv();
+            (v, rx.is_blocked_waiting())
+        });
+        while !probe.is_blocked_waiting() {
+            std::thread::yield_now();
+        }
+        tx.send(1).unwrap();
+        assert_eq!(jh.join().unwrap(), (Some(1), false));
+        assert!(!probe.is_blocked_waiting());
+    }
+
+    #[test]
+    fn test_leak_and_reclaim() {
+        let (tx, mut rx) = channel();
+        tx.send(1).unwrap();
+        let ptr = tx.leak().into_raw();
+        assert_eq!(rx.recv(), Some(1));
+        assert!(!rx.is_disconnected());
+        let leaked = unsafe { LeakedSender::<i32>::from_raw(ptr) };
+        let tx = leaked.reclaim();
+        tx.send(2).unwrap();
+        assert_eq!(rx.recv(), Some(2));
+        assert!(!rx.is_disconnected());
+        drop(tx);
+        assert!(rx.is_disconnected());
+    }
+
+    #[test]
+    fn test_recv_watchdog() {
+        let (tx, mut rx) = channel();
+        let stalls = AtomicUsize::new(0);
+        let on_stall = || {
+            stalls.fetch_add(1, Ordering::SeqCst);
+        };
+        tx.send(1).unwrap();
+        assert_eq!(
+            rx.recv_watchdog(Duration::from_millis(20), on_stall),
+            Some(1)
+        );
+        assert_eq!(stalls.load(Ordering::SeqCst), 0);
+        let jh = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(100));
+            tx.send(2).unwrap();
+        });
+        assert_eq!(
+            rx.recv_watchdog(Duration::from_millis(20), on_stall),
+            Some(2)
+        );
+        assert!(stalls.load(Ordering::SeqCst) >= 2);
+        jh.join().unwrap();
+    }
+
+    #[test]
+    fn test_close_counts_pending() {
+        let (tx, mut rx) = channel();
+        for i in 0..5 {
+            tx.send(i).unwrap();
+        }
+        rx.recv().unwrap();
+        rx.recv().unwrap();
+        assert_eq!(rx.close(), 3);
+        assert_eq!(tx.send(5), Err(SendError::Disconnected(5)));
+        assert_eq!(rx.recv(), Some(2));
+        assert_eq!(rx.close(), 2);
+    }
+
+    #[test]
+    #[cfg(feature = "tracing")]
+    fn test_send_traced() {
+        use std::sync::atomic::AtomicU64;
+        use tracing::{span, subscriber::Subscriber, Event, Metadata};
+
+        // Just enough of a subscriber for spans to get ids and a current one.
+        #[derive(Default)]
+        struct Stack {
+            next: AtomicU64,
+            metadata: std::sync::Mutex<Vec<&'static Metadata<'static>>>,
+            entered: std::sync::Mutex<Vec<span::Id>>,
+        }
+        impl Subscriber for Stack {
+            fn enabled(&self, _: &Metadata<'_>) -> bool {
+                true
+            }
+            fn new_span(&self, attrs: &span::Attributes<'_>) -> span::Id {
+                self.metadata.lock().unwrap().push(attrs.metadata());
+                span::Id::from_u64(self.next.fetch_add(1, Ordering::SeqCst) + 1)
+            }
+            fn record(&self, _: &span::Id, _: &span::Record<'_>) {}
+            fn record_follows_from(&self, _: &span::Id, _: &span::Id) {}
+            fn event(&self, _: &Event<'_>) {}
+            fn enter(&self, id: &span::Id) {
+                self.entered.lock().unwrap().push(id.clone());
+            }
+            fn exit(&self, _: &span::Id) {
+                self.entered.lock().unwrap().pop();
+            }
+            fn current_span(&self) -> tracing_core::span::Current {
+                match self.entered.lock().unwrap().last() {
+                    Some(id) => {
+                        let metadata = self.metadata.lock().unwrap()[id.into_u64() as usize - 1];
+                        tracing_core::span::Current::new(id.clone(), metadata)
+                    }
+                    None => tracing_core::span::Current::none(),
+                }
+            }
+        }
+
+        tracing::subscriber::with_default(Stack::default(), || {
+            let (tx, mut rx) = channel();
+            let sent_in = tracing::info_span!("producer");
+            sent_in.in_scope(|| tx.send_traced(1).unwrap());
+            tx.send(2).unwrap();
+            assert_eq!(tracing::Span::current().id(), None);
+            let (value, entered) = rx.recv_traced().unwrap();
+            assert_eq!(value, 1);
+            assert_eq!(tracing::Span::current().id(), sent_in.id());
+            drop(entered);
+            assert_eq!(tracing::Span::current().id(), None);
+            let (value, _entered) = rx.recv_traced().unwrap();
+            assert_eq!(value, 2);
+            assert_eq!(tracing::Span::current().id(), None);
+        });
+    }
+
+    #[test]
+    fn test_recv_filter_map() {
+        let (tx, mut rx) = channel::<i32>();
+        for i in 1..=5 {
+            tx.send(i).unwrap();
+        }
+        drop(tx);
+        let halve_evens = |v: i32| (v % 2 == 0).then_some(v / 2);
+        assert_eq!(rx.recv_filter_map(halve_evens), Some(1));
+        assert_eq!(rx.recv_filter_map(halve_evens), Some(2));
+        assert_eq!(rx.recv_filter_map(halve_evens), None);
+    }
+
+    #[test]
+    fn test_take_matching() {
+        let (tx, mut rx) = channel();
+        for i in 1..=6 {
+            tx.send(i).unwrap();
+        }
+        drop(tx);
+        let mut out = Vec::new();
+        assert_eq!(rx.take_matching(|v| v % 2 == 0, &mut out), 3);
+        assert_eq!(out, [2, 4, 6]);
+        assert_eq!(
+            std::iter::from_fn(|| rx.recv()).collect::<Vec<_>>(),
+            [1, 3, 5]
+        );
+
+        // What isn't taken still counts against the capacity.
+        let (tx, mut rx) = bounded(2);
+        tx.send(1).unwrap();
+        tx.send(3).unwrap();
+        out.clear();
+        assert_eq!(rx.take_matching(|v| v % 2 == 0, &mut out), 0);
+        assert_eq!(tx.send_if_space(5), Ok(Some(5)));
+        let (tx, mut rx) = bounded(2);
+        tx.send(2).unwrap();
+        tx.send(3).unwrap();
+        assert_eq!(rx.take_matching(|v| v % 2 == 0, &mut out), 1);
+        assert_eq!(tx.send_if_space(4), Ok(None));
+        assert_eq!(tx.send_if_space(6), Ok(Some(6)));
+    }
+
+    #[test]
+    fn test_fair_balances_producers() {
+        let (tx, rx) = ChannelBuilder::new().fair().build();
+        let stop = Arc::new(AtomicBool::new(false));
+        let start = Arc::new(std::sync::Barrier::new(5));
+        let producers: Vec<_> = (0..4)
+            .map(|_| {
+                let tx = tx.clone();
+                let stop = Arc::clone(&stop);
+                let start = Arc::clone(&start);
+                std::thread::spawn(move || {
+                    start.wait();
+                    let mut landed = 0usize;
+                    while !stop.load(Ordering::Relaxed) {
+                        tx.send(()).unwrap();
+                        landed += 1;
+                    }
+                    landed
+                })
+            })
+            .collect();
+        start.wait();
+        std::thread::sleep(Duration::from_millis(100));
+        stop.store(true, Ordering::Relaxed);
+        let landed: Vec<_> = producers.into_iter().map(|p| p.join().unwrap()).collect();
+        drop(rx);
+        let (min, max) = (landed.iter().min().unwrap(), landed.iter().max().unwrap());
+        assert!(min * 3 >= *max, "skewed: {landed:?}");
+    }
+
+    #[test]
+    fn test_recv_events() {
+        let (tx, mut rx) = channel();
+        tx.send(1).unwrap();
+        let mut events = rx.recv_events();
+        assert_eq!(events.next(), Some(Event::Message(1)));
+        assert_eq!(events.next(), Some(Event::Idle));
+        drop(tx);
+        assert_eq!(events.next(), Some(Event::Closed));
+        assert_eq!(events.next(), None);
+    }
+
+    #[cfg(feature = "snapshot")]
+    #[test]
+    fn test_snapshot_restore() {
+        let (tx, mut rx) = channel();
+        for i in 0..7 {
+            tx.send(i).unwrap();
+        }
+        assert_eq!(rx.recv(), Some(0));
+        assert_eq!(rx.recv(), Some(1));
+        let snapshot = rx.snapshot();
+        assert_eq!(snapshot.items, [2, 3, 4, 5, 6]);
+
+        let (tx, mut rx) = ChannelBuilder::new().restore(snapshot);
+        assert_eq!(rx.consumer_lag(), 5);
+        drop(tx);
+        let received: Vec<_> = rx.enumerate_recv().collect();
+        assert_eq!(received, [(2, 2), (3, 3), (4, 4), (5, 5), (6, 6)]);
+    }
+
+    #[test]
+    fn test_send_batch_partial() {
+        let (tx, mut rx) = bounded(3);
+        assert_eq!(tx.send_batch_partial(vec![1, 2, 3, 4, 5]), Ok(vec![4, 5]));
+        assert_eq!(tx.send_batch_partial(vec![6]), Ok(vec![6]));
+        assert_eq!(rx.recv(), Some(1));
+        assert_eq!(tx.send_batch_partial(vec![4, 5]), Ok(vec![5]));
+        drop(rx);
+        assert_eq!(
+            tx.send_batch_partial(vec![5]),
+            Err(SendError::Disconnected(vec![5]))
+        );
+    }
+
+    #[test]
+    fn test_par_for_each() {
+        let (tx, rx) = channel();
+        let producer = std::thread::spawn(move || {
+            for i in 1..=1000 {
+                tx.send(i).unwrap();
+            }
+        });
+        let total = AtomicUsize::new(0);
+        rx.par_for_each(|v| {
+            total.fetch_add(v, Ordering::Relaxed);
+        });
+        producer.join().unwrap();
+        assert_eq!(total.into_inner(), 500500);
+    }
+
+    #[test]
+    fn test_max_buffer() {
+        let (tx, mut rx) = ChannelBuilder::new().max_buffer(4).build();
+        for i in 0..10 {
+            tx.send(i).unwrap();
+        }
+        assert_eq!(rx.recv(), Some(0));
+        assert_eq!(rx.buffer.len(), 4);
+        assert_eq!(rx.shared.inner.lock().queue.len(), 5);
+        for i in 1..10 {
+            assert_eq!(rx.recv(), Some(i));
+            assert!(rx.buffer.len() <= 4);
+        }
+    }
+
+    #[cfg(feature = "diagnostics")]
+    #[test]
+    fn test_detect_stalls() {
+        let (tx, mut rx) = channel::<i32>();
+        let (stalled_tx, stalled_rx) = std::sync::mpsc::channel();
+        rx.detect_stalls(Duration::from_millis(20), move |parked| {
+            let _ = stalled_tx.send(parked);
+        });
+        let receiver = std::thread::spawn(move || rx.recv());
+        let parked = stalled_rx.recv_timeout(Duration::from_secs(5)).unwrap();
+        assert!(parked >= Duration::from_millis(20));
+        tx.send(1).unwrap();
+        assert_eq!(receiver.join().unwrap(), Some(1));
+    }
+
+    #[test]
+    fn test_on_close_runs_once_both_sides_drop() {
+        let (tx, rx) = channel::<i32>();
+        let closed = Arc::new(AtomicUsize::new(0));
+        let counter = Arc::clone(&closed);
+        tx.on_close(move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+        let other = tx.clone();
+        drop(tx);
+        drop(rx);
+        assert_eq!(closed.load(Ordering::SeqCst), 0);
+        drop(other);
+        assert_eq!(closed.load(Ordering::SeqCst), 1);
+
+        // Only the callback needs to be `Send`, not the messages.
+        let (tx, rx) = channel::<std::rc::Rc<i32>>();
+        let counter = Arc::clone(&closed);
+        tx.on_close(move || {
+            counter.fetch_add(1, Ordering::SeqCst);
+        });
+        drop((tx, rx));
+        assert_eq!(closed.load(Ordering::SeqCst), 2);
+    }
+
+    fn check_no_lost_wakeups(builder: ChannelBuilder) {
+        const PRODUCERS: usize = 4;
+        const BURSTS: usize = 200;
+        const PER_BURST: usize = 50;
+        let (tx, mut rx) = builder.build();
+        for producer in 0..PRODUCERS {
+            let tx = tx.clone();
+            std::thread::spawn(move || {
+                for burst in 0..BURSTS {
+                    for i in 0..PER_BURST {
+                        tx.send((producer, burst * PER_BURST + i)).unwrap();
+                    }
+                    // Let the receiver drain and park between bursts.
+                    std::thread::sleep(Duration::from_micros(50));
+                }
+            });
+        }
+        drop(tx);
+        let mut next = [0; PRODUCERS];
+        while let Some((producer, seq)) = rx.recv() {
+            assert_eq!(seq, next[producer]);
+            next[producer] += 1;
+        }
+        assert_eq!(next, [BURSTS * PER_BURST; PRODUCERS]);
+    }
+
+    #[test]
+    fn test_no_lost_wakeups() {
+        check_no_lost_wakeups(ChannelBuilder::new());
+        check_no_lost_wakeups(ChannelBuilder::new().no_bulk_buffer());
+        check_no_lost_wakeups(ChannelBuilder::new().capacity(8));
+    }
+
+    #[test]
+    fn test_collect_until_quiet() {
+        let (tx, mut rx) = channel();
+        for i in 1..=3 {
+            tx.send(i).unwrap();
+        }
+        let jh = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(200));
+            tx.send(4).unwrap();
+            tx
+        });
+        assert_eq!(
+            rx.collect_until_quiet(Duration::from_millis(50), None),
+            [1, 2, 3]
+        );
+        let tx = jh.join().unwrap();
+        tx.send(5).unwrap();
+        assert_eq!(
+            rx.collect_until_quiet(Duration::from_millis(50), Some(1)),
+            [4]
+        );
+        drop(tx);
+        assert_eq!(rx.collect_until_quiet(Duration::from_secs(5), None), [5]);
+    }
+
+    #[test]
+    fn test_steal_half() {
+        let (tx, mut rx) = channel();
+        for i in 0..10 {
+            tx.send(i).unwrap();
+        }
+        drop(tx);
+        assert_eq!(rx.steal_half(), [5, 6, 7, 8, 9]);
+        assert_eq!(
+            std::iter::from_fn(|| rx.recv()).collect::<Vec<_>>(),
+            [0, 1, 2, 3, 4]
+        );
+    }
+
+    #[test]
+    #[cfg(feature = "mpmc")]
+    fn test_steal_half_for_idle_receiver() {
+        let (tx, mut busy) = channel();
+        let mut idle = busy.try_clone().unwrap();
+        for i in 0..10 {
+            tx.send(i).unwrap();
+        }
+        drop(tx);
+        let stolen = idle.steal_half();
+        assert_eq!(stolen, [5, 6, 7, 8, 9]);
+        let rest: Vec<_> = std::iter::from_fn(|| busy.recv()).collect();
+        assert_eq!(rest, [0, 1, 2, 3, 4]);
+        assert_eq!(idle.recv(), None);
+    }
+
+    #[test]
+    fn test_two_phase_shutdown() {
+        let (tx, mut rx) = channel();
+        let other = tx.clone();
+        for i in 0..3 {
+            tx.send(i).unwrap();
+        }
+        tx.begin_shutdown();
+        let Err(SendError::Closed(3, Shutdown)) = other.send(3) else {
+            panic!("send after begin_shutdown should fail");
+        };
+        assert_eq!(rx.drain_remaining(), [0, 1, 2]);
+    }
+
+    #[test]
+    fn test_recv_tagged() {
+        let (a, mut rx) = channel();
+        let b = a.clone();
+        assert_ne!(a.id(), b.id());
+        a.send("a1").unwrap();
+        b.send("b1").unwrap();
+        a.send("a2").unwrap();
+        let (a_id, b_id) = (a.id(), b.id());
+        drop((a, b));
+        assert_eq!(
+            std::iter::from_fn(|| rx.recv_tagged()).collect::<Vec<_>>(),
+            [(a_id, "a1"), (b_id, "b1"), (a_id, "a2")]
+        );
+    }
+
+    #[cfg(feature = "lock-metrics")]
+    #[test]
+    fn test_lock_wait_nanos() {
+        let (tx, mut rx) = channel();
+        let producers: Vec<_> = (0..4)
+            .map(|_| {
+                let tx = tx.clone();
+                std::thread::spawn(move || {
+                    for i in 0..10_000 {
+                        tx.send(i).unwrap();
+                    }
+                })
+            })
+            .collect();
+        drop(tx);
+        while rx.recv().is_some() {}
+        for producer in producers {
+            producer.join().unwrap();
+        }
+        assert!(rx.lock_wait_nanos() > 0);
+    }
+
+    #[cfg(feature = "lock-metrics")]
+    #[test]
+    fn test_condvar_notifies() {
+        // With nobody parked, sends skip the condvar.
+        let (tx, mut rx) = channel();
+        for i in 0..100 {
+            tx.send(i).unwrap();
+        }
+        assert_eq!(rx.condvar_notifies(), 0);
+        let jh = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            tx.send(100).unwrap();
+            tx
+        });
+        for i in 0..=100 {
+            assert_eq!(rx.recv(), Some(i));
+        }
+        let _tx = jh.join().unwrap();
+        assert_eq!(rx.condvar_notifies(), 1);
+    }
+
+    #[test]
+    fn test_recv_chunk() {
+        let (tx, mut rx) = channel();
+        for i in 0..10 {
+            tx.send(i).unwrap();
+        }
+        drop(tx);
+        assert_eq!(rx.recv_chunk(4).as_deref(), Some(&[0, 1, 2, 3][..]));
+        assert_eq!(rx.recv_chunk(4).as_deref(), Some(&[4, 5, 6, 7][..]));
+        assert_eq!(rx.recv_chunk(4).as_deref(), Some(&[8, 9][..]));
+        assert_eq!(rx.recv_chunk(4), None);
+    }
+
+    #[test]
+    fn test_send_conflate_key() {
+        let (tx, mut rx) = channel();
+        tx.send_conflate_key("a", 1).unwrap();
+        tx.send_conflate_key("a", 2).unwrap();
+        tx.send_conflate_key("b", 3).unwrap();
+        tx.send_conflate_key("a", 4).unwrap();
+        let other = tx.clone();
+        other.send_conflate_key("a", 5).unwrap();
+        let (tx_id, other_id) = (tx.id(), other.id());
+        drop((tx, other));
+        assert_eq!(rx.recv_tagged(), Some((tx_id, ("a", 2))));
+        assert_eq!(rx.recv_tagged(), Some((tx_id, ("b", 3))));
+        // The replaced tail is attributed to the sender that replaced it.
+        assert_eq!(rx.recv_tagged(), Some((other_id, ("a", 5))));
+        assert_eq!(rx.recv(), None);
+    }
+
+    #[test]
+    fn test_wait_for_sender() {
+        let (tx, mut rx) = channel();
+        let connector = rx.connector();
+        drop(tx);
+        let producer = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            connector.connect().unwrap().send(1).unwrap();
+            connector
+        });
+        assert!(rx.wait_for_sender());
+        assert_eq!(rx.recv(), Some(1));
+        let connector = producer.join().unwrap();
+        assert_eq!(rx.recv(), None);
+        // Once the receiver has seen the disconnect, it stays disconnected.
+        assert!(connector.connect().is_none());
+        rx.close();
+        assert!(!rx.wait_for_sender());
+    }
+
+    #[test]
+    fn test_dropped_total() {
+        let (tx, mut rx) = ChannelBuilder::new()
+            .capacity(2)
+            .overflow(OverflowPolicy::DropOldest)
+            .build();
+        for i in 0..5 {
+            tx.send(i).unwrap();
+        }
+        assert_eq!(rx.dropped_total(), 3);
+        assert_eq!(rx.recv(), Some(3));
+        assert_eq!(rx.recv(), Some(4));
+    }
+
+    #[test]
+    fn test_recv_map_ctx() {
+        let (tx, mut rx) = channel();
+        tx.send(2).unwrap();
+        assert_eq!(rx.recv_map_ctx(40, |base, value| base + value), Some(42));
+        drop(tx);
+        assert_eq!(rx.recv_map_ctx(40, |base, value| base + value), None);
+    }
+
+    #[test]
+    fn test_event_listener() {
+        let (tx, mut rx) = channel();
+        let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let seen = Arc::clone(&events);
+        rx.set_event_listener(Box::new(move |event| seen.lock().unwrap().push(event)));
+        tx.send(1).unwrap();
+        assert_eq!(rx.recv(), Some(1));
+        drop(tx.clone());
+        drop(tx);
+        drop(rx);
+        use ChannelEvent::*;
+        assert_eq!(
+            *events.lock().unwrap(),
+            [
+                MessageSent,
+                MessageReceived,
+                SenderCloned,
+                SenderDropped,
+                SenderDropped,
+                Disconnected,
+                ReceiverDropped,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_event_listener_sees_change() {
+        // Each event comes after the change it reports, with the channel
+        // unlocked: the listener can probe it and sees the change made.
+        let (tx, mut rx) = channel::<i32>();
+        let probe = std::sync::Mutex::new(Some(tx.clone()));
+        let events = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let seen = Arc::clone(&events);
+        rx.set_event_listener(Box::new(move |event| {
+            let mut probe = probe.lock().unwrap();
+            let Some(tx) = probe.as_ref() else {
+                return;
+            };
+            let state = (event, tx.consumer_lag(), tx.ping().is_ok());
+            seen.lock().unwrap().push(state);
+            // The listener would otherwise keep the channel alive.
+            if event == ChannelEvent::Disconnected {
+                let tx = probe.take();
+                drop(probe);
+                drop(tx);
+            }
+        }));
+        tx.send(1).unwrap();
+        tx.send_all(vec![2, 3]).unwrap();
+        assert_eq!(rx.recv(), Some(1));
+        drop(rx);
+        use ChannelEvent::*;
+        assert_eq!(
+            *events.lock().unwrap(),
+            [
+                (MessageSent, 1, true),
+                (MessageSent, 3, true),
+                (MessageSent, 3, true),
+                (MessageReceived, 2, true),
+                (ReceiverDropped, 2, false),
+                (Disconnected, 2, false),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_priority_headroom() {
+        let (tx, mut rx) = bounded_with_reserve(3, 1);
+        tx.send(1).unwrap();
+        tx.send(2).unwrap();
+        assert_eq!(tx.send_if_space(3).unwrap(), Some(3));
+        tx.send_priority(9).unwrap();
+        assert_eq!(tx.send_if_space(3).unwrap(), Some(3));
+        drop(tx);
+        assert_eq!(
+            std::iter::from_fn(|| rx.recv()).collect::<Vec<_>>(),
+            [1, 2, 9]
+        );
+    }
+
+    #[test]
+    fn test_recv_expect() {
+        let (tx, mut rx) = channel();
+        for i in [1, 2, 3, 5] {
+            tx.send(i).unwrap();
+        }
+        drop(tx);
+        assert_eq!(rx.recv_expect(&[1, 2]), Ok(()));
+        let err = rx.recv_expect(&[3, 4]).unwrap_err();
+        assert_eq!(
+            err,
+            SequenceError::Mismatch {
+                index: 1,
+                got: 5,
+                wanted: 4
+            }
+        );
+        assert_eq!(err.to_string(), "message 1 was 5, expected 4");
+        assert_eq!(
+            rx.recv_expect(&[6]),
+            Err(SequenceError::Disconnected { index: 0 })
+        );
+    }
+
+    #[test]
+    fn test_splice_from() {
+        let (tx_a, mut rx_a) = channel();
+        let (tx_b, mut rx_b) = channel();
+        tx_a.send(1).unwrap();
+        tx_a.send(2).unwrap();
+        for i in 10..13 {
+            tx_b.send(i).unwrap();
+        }
+        // 10 to 12 are in `rx_b`'s buffer after this.
+        assert_eq!(rx_b.recv(), Some(10));
+        tx_b.send(13).unwrap();
+        rx_a.splice_from(&mut rx_b);
+        tx_a.send(3).unwrap();
+        drop((tx_a, tx_b));
+        assert_eq!(rx_b.recv(), None);
+        assert_eq!(
+            std::iter::from_fn(|| rx_a.recv()).collect::<Vec<_>>(),
+            [1, 2, 11, 12, 13, 3]
+        );
+        assert_eq!(rx_b.consumer_lag(), 0);
+    }
+
+    #[test]
+    #[cfg(feature = "mpmc")]
+    fn test_hand_back_keeps_redeliveries_first() {
+        let run = |splice: bool| {
+            let (tx, mut rx1) = channel();
+            let mut rx2 = rx1.try_clone().unwrap();
+            tx.send_all(vec![1, 2, 3]).unwrap();
+            // 2 and 3 are in `rx1`'s buffer after this.
+            assert_eq!(rx1.recv(), Some(1));
+            tx.send_all(vec![4, 5]).unwrap();
+            // 4 is redelivered ahead of every buffer, 5 sits in `rx2`'s.
+            drop(rx2.recv_delivery());
+            if splice {
+                rx1.splice_from(&mut rx2);
+            }
+            drop((tx, rx2));
+            std::iter::from_fn(|| rx1.recv()).collect::<Vec<_>>()
+        };
+        assert_eq!(run(true), [4, 2, 3, 5]);
+        assert_eq!(run(false), [4, 2, 3, 5]);
+    }
+
+    #[test]
+    fn test_send_until_buffered() {
+        let (tx, mut rx) = channel();
+        let done = Arc::new(AtomicBool::new(false));
+        let jh = std::thread::spawn({
+            let done = Arc::clone(&done);
+            move || {
+                tx.send(0).unwrap();
+                tx.send_until_buffered(1).unwrap();
+                done.store(true, Ordering::SeqCst);
+            }
+        });
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(!done.load(Ordering::SeqCst));
+        // Taking 0 swaps 1 into the buffer too, which releases the sender
+        // while 1 is still unreceived.
+        assert_eq!(rx.recv(), Some(0));
+        jh.join().unwrap();
+        assert!(done.load(Ordering::SeqCst));
+        assert_eq!(rx.recv(), Some(1));
+
+        // Evicted by a `DropOldest` send, the message is gone for good,
+        // which releases its sender too.
+        let (tx, mut rx) = ChannelBuilder::new()
+            .capacity(1)
+            .overflow(OverflowPolicy::DropOldest)
+            .build();
+        let waiter = std::thread::spawn({
+            let tx = tx.clone();
+            move || tx.send_until_buffered(1).unwrap()
+        });
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(!waiter.is_finished());
+        tx.send(2).unwrap();
+        let deadline = Instant::now() + Duration::from_secs(5);
+        while !waiter.is_finished() {
+            assert!(Instant::now() < deadline, "sender never released");
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        waiter.join().unwrap();
+        assert_eq!(rx.recv(), Some(2));
+    }
+
+    #[test]
+    fn test_set_capacity() {
+        let (tx, mut rx) = bounded(1);
+        tx.send(1).unwrap();
+        let jh = std::thread::spawn(move || {
+            tx.send(2).unwrap();
+            tx.send(3).unwrap();
+        });
+        std::thread::sleep(Duration::from_millis(50));
+        assert!(!jh.is_finished());
+        rx.set_capacity(None);
+        jh.join().unwrap();
+        assert_eq!(
+            std::iter::from_fn(|| rx.recv()).collect::<Vec<_>>(),
+            [1, 2, 3]
+        );
+    }
+
+    #[test]
+    fn test_fold() {
+        let (tx, rx) = channel();
+        for i in 1..=10 {
+            tx.send(i).unwrap();
+        }
+        drop(tx);
+        assert_eq!(rx.fold(0, |sum, i| sum + i), 55);
+    }
+
+    #[test]
+    fn test_notify_space_every() {
+        let (tx, mut rx) = ChannelBuilder::new()
+            .capacity(6)
+            .notify_space_every(4)
+            .build();
+        for i in 0..6 {
+            tx.send(i).unwrap();
+        }
+        let jh = std::thread::spawn(move || tx.send(6).unwrap());
+        std::thread::sleep(Duration::from_millis(50));
+        for i in 0..3 {
+            assert_eq!(rx.recv(), Some(i));
+        }
+        std::thread::sleep(Duration::from_millis(50));
+        // Room for 3 more, but nobody has told the sender.
+        assert!(!jh.is_finished());
+        assert_eq!(rx.recv(), Some(3));
+        jh.join().unwrap();
+        assert_eq!(
+            std::iter::from_fn(|| rx.recv()).collect::<Vec<_>>(),
+            [4, 5, 6]
+        );
+    }
+
+    #[test]
+    fn test_try_recv_many() {
+        let (tx, mut rx) = channel();
+        for i in 0..3 {
+            tx.send(i).unwrap();
+        }
+        let mut out = Vec::new();
+        assert_eq!(rx.try_recv_many(10, &mut out), 3);
+        assert_eq!(rx.try_recv_many(10, &mut out), 0);
+        assert_eq!(out, [0, 1, 2]);
+        tx.send(3).unwrap();
+        tx.send(4).unwrap();
+        assert_eq!(rx.try_recv_many(1, &mut out), 1);
+        assert_eq!(out, [0, 1, 2, 3]);
+    }
+
+    #[cfg(feature = "unstable")]
+    #[test]
+    fn test_receiver_from_shared_ptr() {
+        let (tx, mut rx) = channel();
+        for i in 0..3 {
+            tx.send(i).unwrap();
+        }
+        assert_eq!(rx.recv(), Some(0));
+        let ptr = tx.shared_ptr();
+        assert!(ptr.ptr_eq(&rx.shared_ptr()));
+        let mut second = ptr.new_receiver().unwrap();
+        // `rx` hands what it buffered to `second` as it goes.
+        drop(rx);
+        ptr.new_sender().unwrap().send(3).unwrap();
+        drop(tx);
+        assert_eq!(
+            std::iter::from_fn(|| second.recv()).collect::<Vec<_>>(),
+            [1, 2, 3]
+        );
+        drop(second);
+        assert!(ptr.new_receiver().is_none());
+    }
+
+    #[test]
+    fn test_discard_oldest() {
+        let (tx, mut rx) = channel();
+        for i in 1..=5 {
+            tx.send(i).unwrap();
+        }
+        assert_eq!(rx.discard_oldest(2), 2);
+        assert_eq!(rx.recv(), Some(3));
+        assert_eq!(rx.discard_oldest(5), 2);
+        assert_eq!(rx.consumer_lag(), 0);
+    }
+
+    #[cfg(feature = "stream")]
+    #[test]
+    fn test_stream_under_smol() {
+        use smol::stream::StreamExt;
+
+        let (tx, rx) = channel();
+        let jh = std::thread::spawn(move || {
+            for i in 0..5 {
+                std::thread::sleep(Duration::from_millis(5));
+                tx.send(i).unwrap();
+            }
+        });
+        let items: Vec<_> = smol::block_on(rx.collect());
+        jh.join().unwrap();
+        assert_eq!(items, [0, 1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_recv_or() {
+        let (tx, mut rx) = channel();
+        tx.send(1).unwrap();
+        drop(tx);
+        assert_eq!(rx.recv_or(99), 1);
+        assert_eq!(rx.recv_or(99), 99);
+        assert_eq!(rx.recv_or_else(|| 98), 98);
+    }
+
+    #[test]
+    fn test_max_total_bytes() {
+        let (tx, mut rx) = ChannelBuilder::new().max_total_bytes(10).build();
+        tx.send_sized(vec![1u8; 4]).unwrap();
+        tx.send_sized(vec![2u8; 4]).unwrap();
+        assert_eq!(
+            tx.send_sized(vec![3u8; 4]),
+            Err(SendError::SizeLimit(vec![3u8; 4]))
+        );
+        // Unsized sends don't count.
+        tx.send(vec![0u8; 100]).unwrap();
+        assert_eq!(rx.recv(), Some(vec![1u8; 4]));
+        tx.send_sized(vec![3u8; 4]).unwrap();
+        assert!(tx.send_sized(vec![4u8; 3]).is_err());
+
+        // What a `DropOldest` eviction frees counts toward the new message.
+        let (tx, mut rx) = ChannelBuilder::new()
+            .capacity(2)
+            .overflow(OverflowPolicy::DropOldest)
+            .max_total_bytes(10)
+            .build();
+        tx.send_sized(vec![1u8; 4]).unwrap();
+        tx.send_sized(vec![2u8; 4]).unwrap();
+        tx.send_sized(vec![3u8; 6]).unwrap();
+        assert_eq!(
+            tx.send_sized(vec![4u8; 8]),
+            Err(SendError::SizeLimit(vec![4u8; 8]))
+        );
+        drop(tx);
+        assert_eq!(rx.recv(), Some(vec![2u8; 4]));
+        assert_eq!(rx.recv(), Some(vec![3u8; 6]));
+        assert_eq!(rx.recv(), None);
+    }
+
+    #[test]
+    fn test_recv_with_senders() {
+        let (tx, mut rx) = channel();
+        let others = [tx.clone(), tx.clone()];
+        tx.send(1).unwrap();
+        assert_eq!(rx.recv_with_senders(), Some((1, 3)));
+        drop(others);
+        tx.send(2).unwrap();
+        assert_eq!(rx.recv_with_senders(), Some((2, 1)));
+        drop(tx);
+        assert_eq!(rx.recv_with_senders(), None);
+
+        // A sender dropped between the take and the report is still counted.
+        let (tx, mut rx) = bounded(4);
+        tx.send(0).unwrap();
+        let racer = std::sync::Mutex::new(Some(tx.clone()));
+        rx.set_event_listener(Box::new(move |event| {
+            if event == ChannelEvent::MessageReceived {
+                racer.lock().unwrap().take();
+            }
+        }));
+        assert_eq!(rx.recv_with_senders(), Some((0, 2)));
+        drop(tx);
+    }
+
+    #[test]
+    fn test_backlog_priority_boost() {
+        let (tx, mut rx) = ChannelBuilder::new().backlog_priority_boost(4).build();
+        let before = thread_priority::current();
+        for i in 0..10 {
+            tx.send(i).unwrap();
+        }
+        assert_eq!(rx.recv(), Some(0));
+        // Needs Linux and the privilege to raise priority; otherwise the
+        // boost quietly never happens.
+        if rx.priority_boosted() {
+            assert!(thread_priority::current() < before);
+        } else {
+            assert_eq!(thread_priority::current(), before);
+        }
+        for i in 1..10 {
+            assert_eq!(rx.recv(), Some(i));
+        }
+        assert!(!rx.priority_boosted());
+        assert_eq!(thread_priority::current(), before);
+    }
+
+    #[test]
+    fn test_send_cloned() {
+        use std::sync::atomic::AtomicUsize;
+
+        struct Counted(Arc<AtomicUsize>);
+
+        impl Clone for Counted {
+            fn clone(&self) -> Self {
+                self.0.fetch_add(1, Ordering::SeqCst);
+                Counted(Arc::clone(&self.0))
+            }
+        }
+
+        let clones = Arc::new(AtomicUsize::new(0));
+        let canonical = Counted(Arc::clone(&clones));
+        let (tx, mut rx) = channel();
+        tx.send_cloned(&canonical).unwrap();
+        assert_eq!(clones.load(Ordering::SeqCst), 1);
+        assert!(rx.recv().is_some());
+        drop(rx);
+        assert_eq!(tx.send_cloned(&canonical), Err(SendError::Disconnected(())));
+        assert_eq!(clones.load(Ordering::SeqCst), 1);
+    }
+
+    #[test]
+    fn test_wait_ready() {
+        let (tx, mut rx) = channel();
+        // Skipped over rather than reported ready.
+        assert!(tx.send_cancellable(0).unwrap().cancel());
+        let jh = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(20));
+            tx.send(1).unwrap();
+        });
+        assert!(rx.wait_ready());
+        assert!(rx.wait_ready());
+        assert_eq!(rx.recv(), Some(1));
+        jh.join().unwrap();
+        assert!(!rx.wait_ready());
+    }
+
+    #[test]
+    fn test_max_block() {
+        let (tx, mut rx) = ChannelBuilder::new()
+            .max_block(Duration::from_millis(20))
+            .build();
+        let start = Instant::now();
+        assert_eq!(rx.recv_checked(), Err(RecvError::Timeout));
+        assert!(start.elapsed() >= Duration::from_millis(20));
+        // Plain `recv` ignores `max_block` and waits for the late message.
+        let jh = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(60));
+            tx.send(1).unwrap();
+            tx
+        });
+        assert_eq!(rx.recv(), Some(1));
+        let tx = jh.join().unwrap();
+        tx.send(2).unwrap();
+        assert_eq!(rx.recv_checked(), Ok(2));
+        drop(tx);
+        assert_eq!(rx.recv_checked(), Err(RecvError::Disconnected));
+    }
+
+    #[test]
+    fn test_replace_channel_generation() {
+        let (tx, mut rx) = channel();
+        assert_eq!(rx.generation(), 0);
+        tx.send(1).unwrap();
+        let (new_tx, new_rx) = channel();
+        new_tx.send(2).unwrap();
+        let mut old = rx.replace_channel(new_rx);
+        assert_eq!(rx.generation(), 1);
+        assert_eq!(rx.recv(), Some(2));
+        assert_eq!(old.recv(), Some(1));
+        drop((tx, new_tx));
+        assert_eq!(rx.recv(), None);
+    }
+
+    #[test]
+    fn test_send_gather_waits_for_acks() {
+        let (tx, mut rx) = channel();
+        let handle = tx.send_gather(vec![1, 2, 3]);
+        let group = handle.id();
+        let acked = Arc::new(AtomicUsize::new(0));
+        let jh = std::thread::spawn({
+            let acked = Arc::clone(&acked);
+            move || {
+                while let Some(delivery) = rx.recv_delivery() {
+                    assert_eq!(delivery.group(), Some(group));
+                    std::thread::sleep(Duration::from_millis(10));
+                    acked.fetch_add(1, Ordering::SeqCst);
+                    delivery.ack();
+                }
+            }
+        });
+        assert!(handle.wait_all());
+        assert_eq!(acked.load(Ordering::SeqCst), 3);
+        drop(tx);
+        jh.join().unwrap();
+
+        // Left unreceived when the receiver goes, the group is incomplete.
+        let (tx, rx) = channel();
+        let handle = tx.send_gather(vec![1, 2]);
+        drop(rx);
+        assert!(!handle.wait_all());
+    }
+
+    #[test]
+    fn test_recv_burst() {
+        let (tx, mut rx) = channel();
+        for i in 0..5 {
+            tx.send(i).unwrap();
+        }
+        assert_eq!(rx.recv_burst(), Some(vec![0, 1, 2, 3, 4]));
+        let jh = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(20));
+            tx.send(5).unwrap();
+        });
+        assert_eq!(rx.recv_burst(), Some(vec![5]));
+        jh.join().unwrap();
+        assert_eq!(rx.recv_burst(), None);
+    }
+
+    #[test]
+    fn test_ping() {
+        let (tx, rx) = bounded(1);
+        tx.send(1).unwrap();
+        assert!(tx.ping().is_ok());
+        assert_eq!(tx.consumer_lag(), 1);
+        drop(rx);
+        assert!(matches!(tx.ping(), Err(SendError::Disconnected(()))));
+    }
+
+    #[test]
+    fn test_send_panic_caught() {
+        // Panics comparing keys, under the channel's lock.
+        #[derive(Debug)]
+        struct Key(bool);
+        impl PartialEq for Key {
+            fn eq(&self, other: &Key) -> bool {
+                assert!(!self.0 && !other.0, "bad key");
+                true
+            }
+        }
+
+        let (tx, mut rx) = channel();
+        let other = tx.clone();
+        tx.send((Key(false), 1)).unwrap();
+        let caught = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            tx.send_conflate_key(Key(true), 2)
+        }));
+        assert!(caught.is_err());
+        other.send((Key(false), 3)).unwrap();
+        tx.send((Key(false), 4)).unwrap();
+        drop((tx, other));
+        let values: Vec<_> = std::iter::from_fn(|| rx.recv()).map(|(_, v)| v).collect();
+        assert_eq!(values, [1, 3, 4]);
+    }
+
+    #[test]
+    fn test_weak_receiver() {
+        let (tx, rx) = channel::<i32>();
+        let weak = rx.downgrade();
+        if cfg!(feature = "mpmc") {
+            drop(weak.upgrade().unwrap());
+        } else {
+            assert!(weak.upgrade().is_none());
+        }
+        assert!(tx.ping().is_ok());
+        tx.send(1).unwrap();
+        assert_eq!(weak.consumer_lag(), 1);
+        assert!(!weak.is_disconnected());
+        drop(rx);
+        assert!(weak.is_disconnected());
+        assert_eq!(tx.send(1), Err(SendError::Disconnected(1)));
+        assert!(weak.upgrade().is_none());
+        drop(tx);
+        assert!(weak.upgrade().is_none());
+        assert_eq!(weak.consumer_lag(), 0);
+    }
+
+    #[test]
+    #[should_panic(expected = "room for at least one message")]
+    fn test_bounded_zero() {
+        let _ = bounded::<i32>(0);
+    }
+
+    #[test]
+    fn test_recv_async_single_thread() {
+        // No thread ever blocks, as on wasm: the receiver parks its task and
+        // the producer's sends wake it.
+        let (tx, mut rx) = channel();
+        let producer = async move {
+            for i in 0..3 {
+                smol::future::yield_now().await;
+                tx.send(i).unwrap();
+            }
+        };
+        let consumer = async {
+            let mut got = Vec::new();
+            while let Some(value) = rx.recv_async().await {
+                got.push(value);
+            }
+            got
+        };
+        let ((), got) = smol::block_on(smol::future::zip(producer, consumer));
+        assert_eq!(got, [0, 1, 2]);
+    }
 }
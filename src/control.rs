@@ -0,0 +1,107 @@
+//! A data channel with an out-of-band control lane: control messages jump
+//! ahead of any data waiting, so commands like "stop" aren't stuck behind a
+//! backlog.
+
+use crate::channel_v2::{self, Receiver, SendError, Sender};
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum Message<T, C> {
+    Data(T),
+    Control(C),
+}
+
+/// An ordinary `channel_v2` receiver, so every receive method works on it.
+pub type ControlReceiver<T, C> = Receiver<Message<T, C>>;
+
+pub struct DataSender<T, C>(Sender<Message<T, C>>);
+
+impl<T, C> DataSender<T, C> {
+    pub fn send(&self, value: T) -> Result<(), SendError<T>> {
+        self.0.send(Message::Data(value)).map_err(|err| {
+            err.map(|message| match message {
+                Message::Data(value) => value,
+                Message::Control(_) => unreachable!("only data is sent here"),
+            })
+        })
+    }
+}
+
+impl<T, C> Clone for DataSender<T, C> {
+    fn clone(&self) -> Self {
+        DataSender(self.0.clone())
+    }
+}
+
+pub struct ControlSender<T, C>(Sender<Message<T, C>>);
+
+impl<T, C> ControlSender<T, C> {
+    /// Send `command` to be received before any data still waiting, but
+    /// after the control messages sent before it.
+    pub fn send(&self, command: C) -> Result<(), SendError<C>> {
+        self.0.send_ahead(Message::Control(command)).map_err(|err| {
+            err.map(|message| match message {
+                Message::Control(command) => command,
+                Message::Data(_) => unreachable!("only control is sent here"),
+            })
+        })
+    }
+}
+
+impl<T, C> Clone for ControlSender<T, C> {
+    fn clone(&self) -> Self {
+        ControlSender(self.0.clone())
+    }
+}
+
+/// The receiver disconnects once every data and control sender is gone.
+pub fn channel_with_control<T, C>() -> (DataSender<T, C>, ControlSender<T, C>, ControlReceiver<T, C>)
+{
+    let (tx, rx) = channel_v2::channel();
+    (DataSender(tx.clone()), ControlSender(tx), rx)
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use super::*;
+
+    #[test]
+    fn test_control_jumps_data() {
+        let (data, control, mut rx) = channel_with_control();
+        data.send(1).unwrap();
+        data.send(2).unwrap();
+        data.send(3).unwrap();
+        assert_eq!(rx.recv(), Some(Message::Data(1)));
+        // 2 and 3 are in the receiver's buffer by now.
+        control.send("pause").unwrap();
+        control.send("stop").unwrap();
+        drop((data, control));
+        assert_eq!(
+            std::iter::from_fn(|| rx.recv()).collect::<Vec<_>>(),
+            [
+                Message::Control("pause"),
+                Message::Control("stop"),
+                Message::Data(2),
+                Message::Data(3),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_control_wakes_parked_receiver() {
+        let (data, control, mut rx) = channel_with_control::<i32, &str>();
+        let jh = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(50));
+            control.send("stop").unwrap();
+            std::thread::sleep(Duration::from_millis(50));
+            data.send(1).unwrap();
+        });
+        let start = Instant::now();
+        assert_eq!(rx.recv(), Some(Message::Control("stop")));
+        assert!(start.elapsed() < Duration::from_secs(1));
+        assert_eq!(rx.recv(), Some(Message::Data(1)));
+        jh.join().unwrap();
+        assert_eq!(rx.recv(), None);
+    }
+}
@@ -0,0 +1,162 @@
+//! A C API over a `u64` channel. Needs the `ffi` feature; build a linkable
+//! library with `cargo rustc --release --features ffi --crate-type cdylib`
+//! (or `staticlib`). `include/pamada.h` is generated from this file with
+//! `cbindgen --config cbindgen.toml --output include/pamada.h`.
+//!
+//! Every handle from `channel_create` or `channel_sender_clone` is owned by
+//! the caller and must be freed exactly once with the matching `_free`
+//! function, after which it must not be used. Null handles are accepted
+//! everywhere and treated as already disconnected.
+
+use crate::channel_v2::{self, Receiver, Sender};
+
+/// Opaque sending half; clone with `channel_sender_clone`.
+pub struct ChannelSender(Sender<u64>);
+
+/// Opaque receiving half; there is only ever one per channel.
+pub struct ChannelReceiver(Receiver<u64>);
+
+#[repr(C)]
+#[derive(Debug, PartialEq, Eq)]
+pub enum ChannelStatus {
+    Ok,
+    /// Nothing was ready to receive yet.
+    Empty,
+    /// The other side is gone or the channel was closed.
+    Disconnected,
+}
+
+/// Create a channel, writing its two handles to `sender` and `receiver`.
+///
+/// # Safety
+///
+/// Both pointers must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn channel_create(
+    sender: *mut *mut ChannelSender,
+    receiver: *mut *mut ChannelReceiver,
+) {
+    let (tx, rx) = channel_v2::channel();
+    sender.write(Box::into_raw(Box::new(ChannelSender(tx))));
+    receiver.write(Box::into_raw(Box::new(ChannelReceiver(rx))));
+}
+
+/// # Safety
+///
+/// `sender` must be null or a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn channel_send(sender: *const ChannelSender, value: u64) -> ChannelStatus {
+    let Some(sender) = sender.as_ref() else {
+        return ChannelStatus::Disconnected;
+    };
+    match sender.0.send(value) {
+        Ok(()) => ChannelStatus::Ok,
+        Err(_) => ChannelStatus::Disconnected,
+    }
+}
+
+/// Receive without blocking, writing the value to `out` on `Ok`.
+///
+/// # Safety
+///
+/// `receiver` must be null or a live handle, not used from another thread
+/// at the same time, and `out` must be valid for writes.
+#[no_mangle]
+pub unsafe extern "C" fn channel_try_recv(
+    receiver: *mut ChannelReceiver,
+    out: *mut u64,
+) -> ChannelStatus {
+    let Some(receiver) = receiver.as_mut() else {
+        return ChannelStatus::Disconnected;
+    };
+    match receiver.0.recv_ready() {
+        Some(Some(value)) => {
+            out.write(value);
+            ChannelStatus::Ok
+        }
+        Some(None) => ChannelStatus::Disconnected,
+        None => ChannelStatus::Empty,
+    }
+}
+
+/// Make further sends fail; what's queued can still be received.
+///
+/// # Safety
+///
+/// `receiver` must be null or a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn channel_close(receiver: *const ChannelReceiver) {
+    if let Some(receiver) = receiver.as_ref() {
+        receiver.0.close();
+    }
+}
+
+/// A new sender handle for the same channel, or null for a null `sender`.
+///
+/// # Safety
+///
+/// `sender` must be null or a live handle.
+#[no_mangle]
+pub unsafe extern "C" fn channel_sender_clone(sender: *const ChannelSender) -> *mut ChannelSender {
+    match sender.as_ref() {
+        Some(sender) => Box::into_raw(Box::new(ChannelSender(sender.0.clone()))),
+        None => std::ptr::null_mut(),
+    }
+}
+
+/// # Safety
+///
+/// `sender` must be null or a live handle, which is dead afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn channel_sender_free(sender: *mut ChannelSender) {
+    if !sender.is_null() {
+        drop(Box::from_raw(sender));
+    }
+}
+
+/// # Safety
+///
+/// `receiver` must be null or a live handle, which is dead afterwards.
+#[no_mangle]
+pub unsafe extern "C" fn channel_receiver_free(receiver: *mut ChannelReceiver) {
+    if !receiver.is_null() {
+        drop(Box::from_raw(receiver));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_ffi_round_trip() {
+        unsafe {
+            let mut tx = std::ptr::null_mut();
+            let mut rx = std::ptr::null_mut();
+            channel_create(&mut tx, &mut rx);
+            let mut out = 0;
+            assert_eq!(channel_try_recv(rx, &mut out), ChannelStatus::Empty);
+
+            let tx2 = channel_sender_clone(tx);
+            assert_eq!(channel_send(tx, 1), ChannelStatus::Ok);
+            assert_eq!(channel_send(tx2, 2), ChannelStatus::Ok);
+            channel_sender_free(tx);
+            assert_eq!(channel_try_recv(rx, &mut out), ChannelStatus::Ok);
+            assert_eq!(out, 1);
+
+            channel_close(rx);
+            assert_eq!(channel_send(tx2, 3), ChannelStatus::Disconnected);
+            assert_eq!(channel_try_recv(rx, &mut out), ChannelStatus::Ok);
+            assert_eq!(out, 2);
+            assert_eq!(channel_try_recv(rx, &mut out), ChannelStatus::Disconnected);
+            channel_sender_free(tx2);
+            channel_receiver_free(rx);
+
+            assert_eq!(
+                channel_send(std::ptr::null(), 4),
+                ChannelStatus::Disconnected
+            );
+            assert!(channel_sender_clone(std::ptr::null()).is_null());
+        }
+    }
+}
@@ -1,2 +1,26 @@
-mod channel_v1;
-mod channel_v2;
+#![cfg_attr(feature = "allocator-api", feature(allocator_api))]
+
+pub mod backend;
+pub mod breaker;
+#[cfg(feature = "async-bridge")]
+pub mod bridge;
+pub mod broadcast;
+pub mod channel;
+pub mod channel_v1;
+pub mod channel_v2;
+pub mod control;
+#[cfg(feature = "ffi")]
+pub mod ffi;
+pub mod local;
+pub mod metrics;
+pub mod pipeline;
+pub mod priority;
+#[cfg(feature = "record")]
+pub mod record;
+pub mod ring;
+pub mod router;
+pub mod signal;
+#[cfg(feature = "spill")]
+pub mod spill;
+mod sync;
+mod thread_priority;
@@ -0,0 +1,106 @@
+//! A channel for one thread, e.g. a single-threaded event loop: no locks or
+//! atomics, and payloads needn't be `Send`. Neither end is `Send` either.
+//! With nobody else to wait for, `recv` never blocks.
+
+use std::{
+    cell::{Cell, RefCell},
+    collections::VecDeque,
+    rc::Rc,
+};
+
+use crate::channel_v2::SendError;
+
+pub struct LocalSender<T> {
+    shared: Rc<Shared<T>>,
+}
+
+impl<T> LocalSender<T> {
+    pub fn send(&self, value: T) -> Result<(), SendError<T>> {
+        if !self.shared.rx_alive.get() {
+            return Err(SendError::Disconnected(value));
+        }
+        self.shared.queue.borrow_mut().push_back(value);
+        Ok(())
+    }
+}
+
+impl<T> Clone for LocalSender<T> {
+    fn clone(&self) -> Self {
+        self.shared.tx_count.set(self.shared.tx_count.get() + 1);
+        LocalSender {
+            shared: Rc::clone(&self.shared),
+        }
+    }
+}
+
+impl<T> Drop for LocalSender<T> {
+    fn drop(&mut self) {
+        self.shared.tx_count.set(self.shared.tx_count.get() - 1);
+    }
+}
+
+pub struct LocalReceiver<T> {
+    shared: Rc<Shared<T>>,
+}
+
+impl<T> LocalReceiver<T> {
+    /// The oldest queued message, or `None` if there is none right now.
+    pub fn recv(&mut self) -> Option<T> {
+        self.shared.queue.borrow_mut().pop_front()
+    }
+
+    /// Whether every sender is gone and nothing is left to receive.
+    pub fn is_disconnected(&self) -> bool {
+        self.shared.tx_count.get() == 0 && self.shared.queue.borrow().is_empty()
+    }
+}
+
+impl<T> Drop for LocalReceiver<T> {
+    fn drop(&mut self) {
+        self.shared.rx_alive.set(false);
+        // Nobody can receive these any more.
+        let pending = std::mem::take(&mut *self.shared.queue.borrow_mut());
+        drop(pending);
+    }
+}
+
+struct Shared<T> {
+    queue: RefCell<VecDeque<T>>,
+    tx_count: Cell<usize>,
+    rx_alive: Cell<bool>,
+}
+
+pub fn channel<T>() -> (LocalSender<T>, LocalReceiver<T>) {
+    let shared = Rc::new(Shared {
+        queue: RefCell::default(),
+        tx_count: Cell::new(1),
+        rx_alive: Cell::new(true),
+    });
+    (
+        LocalSender {
+            shared: Rc::clone(&shared),
+        },
+        LocalReceiver { shared },
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_rc_payload() {
+        let (tx, mut rx) = channel();
+        let value = Rc::new(1);
+        tx.send(Rc::clone(&value)).unwrap();
+        tx.clone().send(Rc::new(2)).unwrap();
+        assert_eq!(Rc::strong_count(&value), 2);
+        assert_eq!(rx.recv().as_deref(), Some(&1));
+        assert_eq!(rx.recv().as_deref(), Some(&2));
+        assert_eq!(rx.recv(), None);
+        assert!(!rx.is_disconnected());
+        drop(tx);
+        assert!(rx.is_disconnected());
+        assert_eq!(Rc::strong_count(&value), 1);
+    }
+}
@@ -0,0 +1,151 @@
+use std::{
+    fmt::Display,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+/// Number of log-scale buckets in an arrival histogram.
+pub const ARRIVAL_BUCKETS: usize = 24;
+
+/// Bucket for a gap between two arrivals: bucket 0 holds gaps under 1µs,
+/// bucket `i` holds gaps in `[2^(i-1), 2^i)` µs and the last bucket holds
+/// everything longer.
+pub fn arrival_bucket(gap: Duration) -> usize {
+    let micros = gap.as_micros();
+    if micros == 0 {
+        return 0;
+    }
+    let bucket = (u128::BITS - micros.leading_zeros()) as usize;
+    bucket.min(ARRIVAL_BUCKETS - 1)
+}
+
+/// A channel's counters read at one point, labelled with the channel's
+/// name so a log of several channels can tell them apart. Its `Display`
+/// is one line, e.g. `jobs: sent 10, received 8, dropped 0, lag 2 (peak 5)`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MetricsReport {
+    /// From `ChannelBuilder::name`.
+    pub name: Option<String>,
+    pub sent: u64,
+    pub received: u64,
+    pub dropped: u64,
+    pub lag: u64,
+    pub peak_lag: u64,
+}
+
+impl Display for MetricsReport {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}: sent {}, received {}, dropped {}, lag {} (peak {})",
+            self.name.as_deref().unwrap_or("unnamed channel"),
+            self.sent,
+            self.received,
+            self.dropped,
+            self.lag,
+            self.peak_lag
+        )
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct Metrics {
+    arrivals: [AtomicU64; ARRIVAL_BUCKETS],
+    sent: AtomicU64,
+    received: AtomicU64,
+    peak_lag: AtomicU64,
+    dropped: AtomicU64,
+}
+
+impl Metrics {
+    pub(crate) fn record_arrival(&self, gap: Duration) {
+        self.arrivals[arrival_bucket(gap)].fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn arrival_histogram(&self) -> [u64; ARRIVAL_BUCKETS] {
+        std::array::from_fn(|i| self.arrivals[i].load(Ordering::Relaxed))
+    }
+
+    pub(crate) fn record_sent(&self) {
+        let sent = self.sent.fetch_add(1, Ordering::Relaxed) + 1;
+        let lag = sent.saturating_sub(self.received_total());
+        self.peak_lag.fetch_max(lag, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_received(&self) {
+        self.received.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn unrecord_received(&self) {
+        self.received.fetch_sub(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_dropped(&self) {
+        self.dropped.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn dropped_total(&self) -> u64 {
+        self.dropped.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn sent_total(&self) -> u64 {
+        self.sent.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn received_total(&self) -> u64 {
+        self.received.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn peak_lag(&self) -> u64 {
+        self.peak_lag.load(Ordering::Relaxed)
+    }
+
+    pub(crate) fn reset_peak_lag(&self) {
+        self.peak_lag.store(0, Ordering::Relaxed);
+    }
+
+    // Start the totals from a restored snapshot.
+    #[cfg(feature = "snapshot")]
+    pub(crate) fn set_totals(&self, sent: u64, received: u64) {
+        self.sent.store(sent, Ordering::Relaxed);
+        self.received.store(received, Ordering::Relaxed);
+    }
+
+    pub(crate) fn report(&self, name: Option<&str>) -> MetricsReport {
+        let received = self.received_total();
+        let sent = self.sent_total();
+        MetricsReport {
+            name: name.map(str::to_owned),
+            sent,
+            received,
+            dropped: self.dropped_total(),
+            lag: sent.saturating_sub(received),
+            peak_lag: self.peak_lag(),
+        }
+    }
+
+    // Read received first: a send and receive racing between the two loads
+    // can then only make the lag look larger, never wrap below zero.
+    pub(crate) fn lag(&self) -> u64 {
+        let received = self.received_total();
+        self.sent_total().saturating_sub(received)
+    }
+}
+
+#[cfg(test)]
+mod test {
+
+    use super::*;
+
+    #[test]
+    fn test_arrival_bucket() {
+        assert_eq!(arrival_bucket(Duration::from_nanos(500)), 0);
+        assert_eq!(arrival_bucket(Duration::from_micros(1)), 1);
+        assert_eq!(arrival_bucket(Duration::from_micros(3)), 2);
+        assert_eq!(arrival_bucket(Duration::from_micros(4)), 3);
+        assert_eq!(
+            arrival_bucket(Duration::from_secs(3600)),
+            ARRIVAL_BUCKETS - 1
+        );
+    }
+}
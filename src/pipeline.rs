@@ -0,0 +1,88 @@
+use crate::channel_v2::{self, Receiver};
+
+/// Chains transform stages onto a receiver, each stage running on its own
+/// thread and feeding the next through a fresh channel:
+/// `Pipeline::from(rx).map(f).filter(g).build()`. When the source
+/// disconnects every stage drains and stops in turn, so the final receiver
+/// ends too; dropping the final receiver likewise stops the stages.
+pub struct Pipeline<T> {
+    rx: Receiver<T>,
+}
+
+impl<T: Send + 'static> Pipeline<T> {
+    pub fn map<U, F>(self, mut f: F) -> Pipeline<U>
+    where
+        U: Send + 'static,
+        F: FnMut(T) -> U + Send + 'static,
+    {
+        self.stage(move |value| Some(f(value)))
+    }
+
+    pub fn filter<F>(self, mut keep: F) -> Pipeline<T>
+    where
+        F: FnMut(&T) -> bool + Send + 'static,
+    {
+        self.stage(move |value| keep(&value).then_some(value))
+    }
+
+    pub fn build(self) -> Receiver<T> {
+        self.rx
+    }
+
+    fn stage<U, F>(self, mut f: F) -> Pipeline<U>
+    where
+        U: Send + 'static,
+        F: FnMut(T) -> Option<U> + Send + 'static,
+    {
+        let (tx, rx) = channel_v2::channel();
+        let mut source = self.rx;
+        std::thread::spawn(move || {
+            while let Some(value) = source.recv() {
+                if let Some(out) = f(value) {
+                    if tx.send(out).is_err() {
+                        return;
+                    }
+                }
+            }
+        });
+        Pipeline { rx }
+    }
+}
+
+impl<T> From<Receiver<T>> for Pipeline<T> {
+    fn from(rx: Receiver<T>) -> Self {
+        Pipeline { rx }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_map_then_filter() {
+        let (tx, rx) = channel_v2::channel();
+        let mut out = Pipeline::from(rx)
+            .map(|x: u32| x * 3)
+            .filter(|x| x % 2 == 0)
+            .build();
+        for i in 0..10 {
+            tx.send(i).unwrap();
+        }
+        drop(tx);
+        let got: Vec<_> = std::iter::from_fn(|| out.recv()).collect();
+        assert_eq!(got, vec![0, 6, 12, 18, 24]);
+    }
+
+    #[test]
+    fn test_drop_output_stops_stages() {
+        let (tx, rx) = channel_v2::channel();
+        let out = Pipeline::from(rx).map(|x: u32| x + 1).build();
+        drop(out);
+        tx.send(1).unwrap();
+        // The stage fails to forward, exits and drops the source receiver.
+        while tx.send(2).is_ok() {
+            std::thread::yield_now();
+        }
+    }
+}
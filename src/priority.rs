@@ -0,0 +1,177 @@
+//! An unbounded channel delivering higher priorities first, and messages of
+//! equal priority in the order they were sent. One made with `with_aging`
+//! also promotes messages as they wait, so a flood of high priorities can't
+//! hold a low one back forever.
+
+use std::{
+    cmp::Reverse,
+    collections::{BTreeMap, VecDeque},
+    time::{Duration, Instant},
+};
+
+use crate::{
+    backend::{self, QueueBackend},
+    channel_v2::SendError,
+};
+
+pub struct Sender<T> {
+    inner: backend::Sender<(i8, T), Lanes<T>>,
+}
+
+impl<T> Sender<T> {
+    /// Send at the default priority, `0`.
+    pub fn send(&self, value: T) -> Result<(), SendError<T>> {
+        self.send_prio(value, 0)
+    }
+
+    /// Send to be received before every queued message with a lower `prio`
+    /// and after those with the same or a higher one.
+    pub fn send_prio(&self, value: T, prio: i8) -> Result<(), SendError<T>> {
+        self.inner
+            .send((prio, value))
+            .map_err(|err| err.map(|(_, value)| value))
+    }
+}
+
+impl<T> Clone for Sender<T> {
+    fn clone(&self) -> Self {
+        Sender {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+pub struct Receiver<T> {
+    inner: backend::Receiver<(i8, T), Lanes<T>>,
+}
+
+impl<T> Receiver<T> {
+    pub fn recv(&mut self) -> Option<T> {
+        self.inner.recv().map(|(_, value)| value)
+    }
+}
+
+// A FIFO lane per priority, so each lane's front is both its next message
+// and the one that has aged the most.
+struct Lanes<T> {
+    lanes: BTreeMap<i8, VecDeque<Entry<T>>>,
+    len: usize,
+    // Send order, the tie-breaker that makes equal priorities FIFO.
+    next_seq: u64,
+    // How long a message waits for each level it's promoted.
+    aging: Option<Duration>,
+}
+
+struct Entry<T> {
+    seq: u64,
+    // When it was sent, kept only with aging on.
+    sent: Option<Instant>,
+    value: T,
+}
+
+impl<T> Lanes<T> {
+    fn new(aging: Option<Duration>) -> Self {
+        Lanes {
+            lanes: BTreeMap::new(),
+            len: 0,
+            next_seq: 0,
+            aging,
+        }
+    }
+
+    // `prio` raised a level for every `aging` that `entry` has waited, up to
+    // the highest, where the oldest message wins the tie.
+    fn effective(&self, prio: i8, entry: &Entry<T>, now: Option<Instant>) -> i8 {
+        let (Some(aging), Some(sent), Some(now)) = (self.aging, entry.sent, now) else {
+            return prio;
+        };
+        let levels = now.saturating_duration_since(sent).as_nanos() / aging.as_nanos().max(1);
+        prio.saturating_add(i8::try_from(levels).unwrap_or(i8::MAX))
+    }
+}
+
+impl<T> QueueBackend<(i8, T)> for Lanes<T> {
+    fn push(&mut self, (prio, value): (i8, T)) {
+        let seq = self.next_seq;
+        self.next_seq += 1;
+        let sent = self.aging.map(|_| Instant::now());
+        self.lanes
+            .entry(prio)
+            .or_default()
+            .push_back(Entry { seq, sent, value });
+        self.len += 1;
+    }
+
+    fn pop(&mut self) -> Option<(i8, T)> {
+        let now = self.aging.map(|_| Instant::now());
+        let (&prio, _) = self.lanes.iter().max_by_key(|&(&prio, lane)| {
+            let front = &lane[0];
+            (self.effective(prio, front, now), Reverse(front.seq))
+        })?;
+        let lane = self.lanes.get_mut(&prio).unwrap();
+        let entry = lane.pop_front().unwrap();
+        if lane.is_empty() {
+            self.lanes.remove(&prio);
+        }
+        self.len -= 1;
+        Some((prio, entry.value))
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+}
+
+pub fn channel<T>() -> (Sender<T>, Receiver<T>) {
+    let (tx, rx) = backend::channel_with(Lanes::new(None));
+    (Sender { inner: tx }, Receiver { inner: rx })
+}
+
+/// Like `channel`, but a queued message is received as though its priority
+/// were one higher for every `after` it has waited, so it eventually goes
+/// ahead of anything sent later.
+pub fn with_aging<T>(after: Duration) -> (Sender<T>, Receiver<T>) {
+    let (tx, rx) = backend::channel_with(Lanes::new(Some(after)));
+    (Sender { inner: tx }, Receiver { inner: rx })
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_equal_priorities_stay_fifo() {
+        let (tx, mut rx) = channel();
+        tx.send_prio("first", 1).unwrap();
+        tx.send_prio("urgent", 5).unwrap();
+        tx.send_prio("second", 1).unwrap();
+        tx.send_prio("third", 1).unwrap();
+        tx.send_prio("later", -3).unwrap();
+        drop(tx);
+        assert_eq!(
+            std::iter::from_fn(|| rx.recv()).collect::<Vec<_>>(),
+            ["urgent", "first", "second", "third", "later"]
+        );
+    }
+
+    #[test]
+    fn test_aging_delivers_starved_message() {
+        let (tx, mut rx) = with_aging(Duration::from_millis(5));
+        let sent = Instant::now();
+        tx.send_prio("low", -5).unwrap();
+        tx.send_prio("high", 5).unwrap();
+        assert_eq!(rx.recv(), Some("high"));
+        // A steady flood of fresh high priorities, one queued at a time.
+        loop {
+            tx.send_prio("high", 5).unwrap();
+            if rx.recv() == Some("low") {
+                break;
+            }
+            assert!(sent.elapsed() < Duration::from_secs(5), "still starved");
+            std::thread::sleep(Duration::from_millis(1));
+        }
+        // Ten levels behind, it needed ten agings to draw level.
+        assert!(sent.elapsed() >= Duration::from_millis(50));
+        assert_eq!(rx.recv(), Some("high"));
+    }
+}
@@ -0,0 +1,125 @@
+//! Capture a channel's traffic to a file and replay it later, one JSON
+//! value per line. Needs the `record` feature.
+
+use std::{
+    error::Error,
+    fmt::{Debug, Display},
+    fs::File,
+    io::{self, BufRead, BufReader, BufWriter, Write},
+    path::Path,
+    sync::Mutex,
+};
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::channel_v2::{self, Receiver, SendError, Sender};
+
+/// Wraps a sender so every value it successfully sends is also logged.
+pub struct RecordingSender<T, W: Write = BufWriter<File>> {
+    tx: Sender<T>,
+    log: Mutex<W>,
+}
+
+impl<T: Serialize> RecordingSender<T> {
+    /// Record into a new file at `path`, replacing any existing one.
+    pub fn create(tx: Sender<T>, path: impl AsRef<Path>) -> io::Result<Self> {
+        Ok(RecordingSender::new(
+            tx,
+            BufWriter::new(File::create(path)?),
+        ))
+    }
+}
+
+impl<T: Serialize, W: Write> RecordingSender<T, W> {
+    pub fn new(tx: Sender<T>, log: W) -> Self {
+        RecordingSender {
+            tx,
+            log: Mutex::new(log),
+        }
+    }
+
+    /// Send `value`, logging it only once it's in the channel. Sends are
+    /// logged in the order they were made.
+    pub fn send(&self, value: T) -> Result<(), RecordError<T>> {
+        let line = match serde_json::to_string(&value) {
+            Ok(line) => line,
+            Err(err) => return Err(RecordError::Io(err.into())),
+        };
+        // Held across the send so the log order matches the channel's.
+        let mut log = self.log.lock().unwrap();
+        self.tx.send(value).map_err(RecordError::Send)?;
+        writeln!(log, "{line}").map_err(RecordError::Io)
+    }
+
+    /// Flush buffered log lines to the writer.
+    pub fn flush(&self) -> io::Result<()> {
+        self.log.lock().unwrap().flush()
+    }
+
+    /// Stop recording, returning the sender and the flushed log writer.
+    pub fn into_inner(self) -> io::Result<(Sender<T>, W)> {
+        let mut log = self.log.into_inner().unwrap();
+        log.flush()?;
+        Ok((self.tx, log))
+    }
+}
+
+/// Replay a log written by `RecordingSender`: the receiver yields every
+/// recorded value in order, then disconnects.
+pub fn replay<T: DeserializeOwned>(path: impl AsRef<Path>) -> io::Result<Receiver<T>> {
+    let (tx, rx) = channel_v2::channel();
+    for line in BufReader::new(File::open(path)?).lines() {
+        let value = serde_json::from_str(&line?)?;
+        // `rx` is still held here, so the send can't fail.
+        let _ = tx.send(value);
+    }
+    Ok(rx)
+}
+
+#[derive(Debug)]
+pub enum RecordError<T> {
+    /// The send itself failed; nothing was logged.
+    Send(SendError<T>),
+    /// The value couldn't be serialized or written to the log. If it
+    /// failed writing, the value was still sent.
+    Io(io::Error),
+}
+
+impl<T> Display for RecordError<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            RecordError::Send(err) => Display::fmt(err, f),
+            RecordError::Io(err) => write!(f, "recording a sent value: {err}"),
+        }
+    }
+}
+
+impl<T: Debug> Error for RecordError<T> {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_record_then_replay() {
+        let path = std::env::temp_dir().join(format!("pamada-record-{}.jsonl", std::process::id()));
+        let (tx, mut rx) = channel_v2::channel();
+        let recorder = RecordingSender::create(tx, &path).unwrap();
+        let sent = vec![
+            (1, "one".to_string()),
+            (2, "two".into()),
+            (3, "three".into()),
+        ];
+        for value in sent.clone() {
+            recorder.send(value).unwrap();
+        }
+        drop(recorder);
+        let live: Vec<_> = std::iter::from_fn(|| rx.recv()).collect();
+        assert_eq!(live, sent);
+
+        let mut replayed = replay::<(i32, String)>(&path).unwrap();
+        let replayed: Vec<_> = std::iter::from_fn(|| replayed.recv()).collect();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(replayed, sent);
+    }
+}
@@ -0,0 +1,128 @@
+use std::mem::MaybeUninit;
+
+use crate::{
+    backend::{self, QueueBackend},
+    channel_v2::SendError,
+};
+
+pub struct Sender<T, const N: usize> {
+    inner: backend::Sender<T, Ring<T, N>>,
+}
+
+impl<T, const N: usize> Sender<T, N> {
+    /// Block while all `N` slots are taken.
+    pub fn send(&self, value: T) -> Result<(), SendError<T>> {
+        self.inner.send(value)
+    }
+}
+
+impl<T, const N: usize> Clone for Sender<T, N> {
+    fn clone(&self) -> Self {
+        Sender {
+            inner: self.inner.clone(),
+        }
+    }
+}
+
+pub struct Receiver<T, const N: usize> {
+    inner: backend::Receiver<T, Ring<T, N>>,
+}
+
+impl<T, const N: usize> Receiver<T, N> {
+    pub fn recv(&mut self) -> Option<T> {
+        self.inner.recv()
+    }
+}
+
+/// Fixed-capacity FIFO stored inline; slots `head..head + len` (mod `N`)
+/// are initialized.
+struct Ring<T, const N: usize> {
+    slots: [MaybeUninit<T>; N],
+    head: usize,
+    len: usize,
+}
+
+impl<T, const N: usize> Ring<T, N> {
+    fn new() -> Self {
+        Ring {
+            slots: [const { MaybeUninit::uninit() }; N],
+            head: 0,
+            len: 0,
+        }
+    }
+}
+
+impl<T, const N: usize> QueueBackend<T> for Ring<T, N> {
+    fn push(&mut self, value: T) {
+        debug_assert!(self.len < N);
+        self.slots[(self.head + self.len) % N].write(value);
+        self.len += 1;
+    }
+
+    fn pop(&mut self) -> Option<T> {
+        if self.len == 0 {
+            return None;
+        }
+        // SAFETY: `head` is within the initialized range and is moved past
+        // before the slot can be read again.
+        let value = unsafe { self.slots[self.head].assume_init_read() };
+        self.head = (self.head + 1) % N;
+        self.len -= 1;
+        Some(value)
+    }
+
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn is_full(&self) -> bool {
+        self.len == N
+    }
+}
+
+impl<T, const N: usize> Drop for Ring<T, N> {
+    fn drop(&mut self) {
+        while self.pop().is_some() {}
+    }
+}
+
+/// A channel holding at most `N` messages in storage allocated together
+/// with the channel itself, so sending never allocates.
+pub fn bounded_const<T, const N: usize>() -> (Sender<T, N>, Receiver<T, N>) {
+    assert!(N > 0, "bounded_const needs at least one slot");
+    let (tx, rx) = backend::channel_with(Ring::new());
+    (Sender { inner: tx }, Receiver { inner: rx })
+}
+
+#[cfg(test)]
+mod test {
+
+    use std::rc::Rc;
+
+    use super::*;
+
+    #[test]
+    fn test_blocks_when_full() {
+        let (tx, mut rx) = bounded_const::<i32, 1>();
+        tx.send(1).unwrap();
+        let jh = std::thread::spawn(move || tx.send(2));
+        std::thread::sleep(std::time::Duration::from_millis(20));
+        assert!(!jh.is_finished());
+        assert_eq!(rx.recv(), Some(1));
+        jh.join().unwrap().unwrap();
+        assert_eq!(rx.recv(), Some(2));
+    }
+
+    #[test]
+    fn test_drops_live_slots() {
+        let item = Rc::new(());
+        {
+            let (tx, _rx) = bounded_const::<Rc<()>, 4>();
+            for _ in 0..3 {
+                let _ = tx.send(Rc::clone(&item));
+            }
+            assert_eq!(Rc::strong_count(&item), 4);
+        }
+        assert_eq!(Rc::strong_count(&item), 1);
+    }
+}
@@ -0,0 +1,91 @@
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    sync::{Arc, Mutex},
+};
+
+use crate::channel_v2::{self, Receiver, Sender};
+
+type Routes<K, T> = Arc<Mutex<HashMap<K, Sender<T>>>>;
+
+/// Fans keyed messages out to per-key channels. Everything sent goes through
+/// one input channel to a dispatcher thread, which forwards each message to
+/// whoever subscribed to its key and drops it if nobody did.
+pub struct Router<K, T> {
+    tx: Sender<(K, T)>,
+    routes: Routes<K, T>,
+}
+
+impl<K, T> Router<K, T>
+where
+    K: Eq + Hash + Send + 'static,
+    T: Send + 'static,
+{
+    pub fn new() -> Self {
+        let (tx, mut rx) = channel_v2::channel::<(K, T)>();
+        let routes: Routes<K, T> = Arc::default();
+        let dispatch = Arc::clone(&routes);
+        std::thread::spawn(move || {
+            while let Some((key, value)) = rx.recv() {
+                let mut routes = dispatch.lock().unwrap();
+                if let Some(route) = routes.get(&key) {
+                    if route.send(value).is_err() {
+                        routes.remove(&key);
+                    }
+                }
+            }
+        });
+        Router { tx, routes }
+    }
+
+    /// A sender tagging each message with the key it's routed by. The
+    /// dispatcher, and so every subscription, lives until the router and
+    /// all of these are dropped.
+    pub fn sender(&self) -> Sender<(K, T)> {
+        self.tx.clone()
+    }
+
+    /// Receive every message sent from now on under `key`. A key has one
+    /// subscriber at a time; subscribing again disconnects the previous one.
+    pub fn subscribe(&self, key: K) -> Receiver<T> {
+        let (tx, rx) = channel_v2::channel();
+        self.routes.lock().unwrap().insert(key, tx);
+        rx
+    }
+}
+
+impl<K, T> Default for Router<K, T>
+where
+    K: Eq + Hash + Send + 'static,
+    T: Send + 'static,
+{
+    fn default() -> Self {
+        Router::new()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_route_by_key() {
+        let router = Router::new();
+        let mut a = router.subscribe("a");
+        let mut b = router.subscribe("b");
+        let tx = router.sender();
+        let jh = std::thread::spawn(move || {
+            for i in 0..10 {
+                let key = if i % 2 == 0 { "a" } else { "b" };
+                tx.send((key, i)).unwrap();
+            }
+            tx.send(("c", 100)).unwrap();
+        });
+        jh.join().unwrap();
+        drop(router);
+        let a: Vec<_> = std::iter::from_fn(|| a.recv()).collect();
+        let b: Vec<_> = std::iter::from_fn(|| b.recv()).collect();
+        assert_eq!(a, vec![0, 2, 4, 6, 8]);
+        assert_eq!(b, vec![1, 3, 5, 7, 9]);
+    }
+}
@@ -0,0 +1,136 @@
+//! A data-less wakeup: notifications made before a `wait` coalesce into one,
+//! so there is no queue, just a flag.
+
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use crate::sync::{Condvar, Mutex};
+
+pub struct Notifier {
+    shared: Arc<Shared>,
+}
+
+impl Notifier {
+    /// Wake the waiter, or let its next `wait` return at once. Does nothing
+    /// if a notification is already pending.
+    pub fn notify(&self) {
+        if self.shared.notified.swap(true, Ordering::AcqRel) {
+            return;
+        }
+        // Taken so the notify can't land between the waiter's check of the
+        // flag and its park.
+        let _notifiers = self.shared.notifiers.lock();
+        self.shared.wake.notify_one();
+    }
+}
+
+impl Clone for Notifier {
+    fn clone(&self) -> Self {
+        *self.shared.notifiers.lock() += 1;
+        Notifier {
+            shared: Arc::clone(&self.shared),
+        }
+    }
+}
+
+impl Drop for Notifier {
+    fn drop(&mut self) {
+        let mut notifiers = self.shared.notifiers.lock();
+        *notifiers -= 1;
+        if *notifiers == 0 {
+            self.shared.wake.notify_one();
+        }
+    }
+}
+
+pub struct Waiter {
+    shared: Arc<Shared>,
+}
+
+impl Waiter {
+    /// Block until notified, consuming the notification. `false` once every
+    /// notifier is gone with nothing pending.
+    pub fn wait(&self) -> bool {
+        if self.shared.notified.swap(false, Ordering::AcqRel) {
+            return true;
+        }
+        let mut notifiers = self.shared.notifiers.lock();
+        loop {
+            if self.shared.notified.swap(false, Ordering::AcqRel) {
+                return true;
+            }
+            if *notifiers == 0 {
+                return false;
+            }
+            notifiers = self.shared.wake.wait(notifiers);
+        }
+    }
+
+    /// Like `wait`, but also give up with `false` after `timeout`.
+    pub fn wait_timeout(&self, timeout: Duration) -> bool {
+        if self.shared.notified.swap(false, Ordering::AcqRel) {
+            return true;
+        }
+        let deadline = Instant::now() + timeout;
+        let mut notifiers = self.shared.notifiers.lock();
+        loop {
+            if self.shared.notified.swap(false, Ordering::AcqRel) {
+                return true;
+            }
+            let now = Instant::now();
+            if *notifiers == 0 || now >= deadline {
+                return false;
+            }
+            (notifiers, _) = self.shared.wake.wait_timeout(notifiers, deadline - now);
+        }
+    }
+}
+
+struct Shared {
+    notified: AtomicBool,
+    // Live `Notifier`s, and the lock the waiter parks under.
+    notifiers: Mutex<usize>,
+    wake: Condvar,
+}
+
+pub fn signal() -> (Notifier, Waiter) {
+    let shared = Arc::new(Shared {
+        notified: AtomicBool::new(false),
+        notifiers: Mutex::new(1),
+        wake: Condvar::default(),
+    });
+    (
+        Notifier {
+            shared: Arc::clone(&shared),
+        },
+        Waiter { shared },
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_notifies_coalesce() {
+        let (notifier, waiter) = signal();
+        for _ in 0..5 {
+            notifier.notify();
+        }
+        assert!(waiter.wait_timeout(Duration::ZERO));
+        assert!(!waiter.wait_timeout(Duration::from_millis(20)));
+
+        let jh = std::thread::spawn(move || {
+            std::thread::sleep(Duration::from_millis(20));
+            notifier.notify();
+        });
+        assert!(waiter.wait());
+        jh.join().unwrap();
+        assert!(!waiter.wait());
+    }
+}
@@ -0,0 +1,210 @@
+//! A channel that spills to disk: once more than a set number of messages
+//! are held in memory, further sends are serialized to a temp file, one JSON
+//! value per line, and read back once the in-memory ones are taken. Needs
+//! the `spill` feature.
+
+use std::{
+    error::Error,
+    fmt::{Debug, Display},
+    fs::{File, OpenOptions},
+    io::{self, BufRead, BufReader, BufWriter, Seek, SeekFrom, Write},
+    path::PathBuf,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+};
+
+use serde::{de::DeserializeOwned, Serialize};
+
+use crate::channel_v2::{self, Receiver, SendError, Sender};
+
+/// A channel keeping at most `threshold` messages in memory (at least one)
+/// and spilling the rest to a temp file, removed once both ends are gone.
+pub fn channel<T>(threshold: usize) -> io::Result<(SpillSender<T>, SpillReceiver<T>)> {
+    static NEXT: AtomicUsize = AtomicUsize::new(0);
+    let path = std::env::temp_dir().join(format!(
+        "pamada-spill-{}-{}.jsonl",
+        std::process::id(),
+        NEXT.fetch_add(1, Ordering::Relaxed)
+    ));
+    let writer = OpenOptions::new()
+        .create_new(true)
+        .append(true)
+        .open(&path)?;
+    let reader = File::open(&path)?;
+    let state = Arc::new(Mutex::new(Spill {
+        threshold: threshold.max(1),
+        in_memory: 0,
+        spilled: 0,
+        closed: false,
+        writer: BufWriter::new(writer),
+        reader: BufReader::new(reader),
+        path,
+    }));
+    let (tx, rx) = channel_v2::channel();
+    Ok((
+        SpillSender {
+            tx,
+            state: Arc::clone(&state),
+        },
+        SpillReceiver { rx, state },
+    ))
+}
+
+struct Spill {
+    threshold: usize,
+    // Sent to the channel and not yet received.
+    in_memory: usize,
+    // Lines in the file not yet read back.
+    spilled: usize,
+    // The receiver is gone.
+    closed: bool,
+    writer: BufWriter<File>,
+    reader: BufReader<File>,
+    path: PathBuf,
+}
+
+impl Spill {
+    fn read<T: DeserializeOwned>(&mut self) -> io::Result<T> {
+        self.writer.flush()?;
+        let mut line = String::new();
+        self.reader.read_line(&mut line)?;
+        self.spilled -= 1;
+        if self.spilled == 0 {
+            // Everything written has been read, so start the file over.
+            self.writer.get_ref().set_len(0)?;
+            self.reader.seek(SeekFrom::Start(0))?;
+        }
+        Ok(serde_json::from_str(&line)?)
+    }
+}
+
+impl Drop for Spill {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_file(&self.path);
+    }
+}
+
+pub struct SpillSender<T> {
+    tx: Sender<T>,
+    state: Arc<Mutex<Spill>>,
+}
+
+impl<T: Serialize> SpillSender<T> {
+    /// Send `value`, to memory if there's room and nothing is spilled ahead
+    /// of it, otherwise to disk.
+    pub fn send(&self, value: T) -> Result<(), SpillError<T>> {
+        // Held across the send so a value can't land in memory behind one
+        // still on disk.
+        let mut state = self.state.lock().unwrap();
+        if state.closed {
+            return Err(SpillError::Send(SendError::Disconnected(value)));
+        }
+        if state.spilled == 0 && state.in_memory < state.threshold {
+            self.tx.send(value).map_err(SpillError::Send)?;
+            state.in_memory += 1;
+            return Ok(());
+        }
+        let line = serde_json::to_string(&value).map_err(|err| SpillError::Io(err.into()))?;
+        writeln!(state.writer, "{line}").map_err(SpillError::Io)?;
+        state.spilled += 1;
+        Ok(())
+    }
+}
+
+impl<T> Clone for SpillSender<T> {
+    fn clone(&self) -> Self {
+        SpillSender {
+            tx: self.tx.clone(),
+            state: Arc::clone(&self.state),
+        }
+    }
+}
+
+pub struct SpillReceiver<T> {
+    rx: Receiver<T>,
+    state: Arc<Mutex<Spill>>,
+}
+
+impl<T: DeserializeOwned> SpillReceiver<T> {
+    /// Receive in send order, across memory and disk alike. `None` once
+    /// every sender is gone and nothing is left.
+    pub fn recv(&mut self) -> io::Result<Option<T>> {
+        {
+            let mut state = self.state.lock().unwrap();
+            if let Some(Some(value)) = self.rx.recv_ready() {
+                state.in_memory -= 1;
+                return Ok(Some(value));
+            }
+            if state.spilled > 0 {
+                return state.read().map(Some);
+            }
+        }
+        // Memory and disk are both empty, so the next send goes to memory.
+        let value = self.rx.recv();
+        if value.is_some() {
+            self.state.lock().unwrap().in_memory -= 1;
+        }
+        Ok(value)
+    }
+}
+
+impl<T> SpillReceiver<T> {
+    /// Messages currently waiting on disk.
+    pub fn spilled(&self) -> usize {
+        self.state.lock().unwrap().spilled
+    }
+}
+
+impl<T> Drop for SpillReceiver<T> {
+    fn drop(&mut self) {
+        self.state.lock().unwrap().closed = true;
+    }
+}
+
+#[derive(Debug)]
+pub enum SpillError<T> {
+    /// The send itself failed.
+    Send(SendError<T>),
+    /// The value couldn't be serialized or written to the spill file.
+    Io(io::Error),
+}
+
+impl<T> Display for SpillError<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SpillError::Send(err) => Display::fmt(err, f),
+            SpillError::Io(err) => write!(f, "spilling a value to disk: {err}"),
+        }
+    }
+}
+
+impl<T: Debug> Error for SpillError<T> {}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_spill_keeps_fifo() {
+        let (tx, mut rx) = channel::<u32>(3).unwrap();
+        for i in 0..10 {
+            tx.send(i).unwrap();
+        }
+        assert_eq!(rx.spilled(), 7);
+        let mut received = Vec::new();
+        for _ in 0..5 {
+            received.push(rx.recv().unwrap().unwrap());
+        }
+        // Still spilling while older values wait on disk.
+        tx.send(10).unwrap();
+        assert_eq!(rx.spilled(), 6);
+        drop(tx);
+        while let Some(v) = rx.recv().unwrap() {
+            received.push(v);
+        }
+        assert_eq!(received, (0..=10).collect::<Vec<_>>());
+        assert_eq!(rx.spilled(), 0);
+    }
+}
@@ -0,0 +1,259 @@
+//! The mutex and condvar behind `channel_v2`: `std::sync` by default, or
+//! `parking_lot` with the `parking_lot` feature. Both backends expose the
+//! same small API.
+//!
+//! A std lock poisoned by a panic is used anyway, as parking_lot's would be:
+//! the channel never panics halfway through updating its state, so the only
+//! way to poison it is a user panicking while holding it, e.g. under a
+//! `DrainGuard` or in the key comparison of `send_conflate_key`. Giving up
+//! there would turn the receiver's drop, which has to lock to tell senders
+//! it's gone, into a double panic.
+//!
+//! Neither backend promises an order among threads waiting for the lock, so
+//! a producer that just unlocked can win it straight back and starve the
+//! rest. A mutex made with `Mutex::fair` puts a ticket gate in front: `lock`
+//! callers get in strictly in arrival order, parked on the gate's condvar
+//! until their turn. Every handoff then goes through a waiting thread
+//! instead of whichever one is already running, so expect noticeably less
+//! throughput under contention. Threads woken by a `Condvar` relock without
+//! a ticket.
+//!
+//! Single-threaded wasm has no threads to notify a `Condvar` wait, so
+//! waiting there panics: receivers use `Receiver::recv_async`, which parks
+//! the task on its `Waker` instead.
+//!
+//! With the `lock-metrics` feature every `lock` call is timed and the total
+//! kept in `wait_nanos`, to tell whether the single lock is what a loaded
+//! channel is waiting on. Relocking after a `Condvar` wait isn't counted.
+//! Every `Condvar` notify is counted too, in `notifies`: with std's condvar
+//! on Linux each one is a `futex` syscall, whether anyone waits or not.
+
+#[cfg(feature = "lock-metrics")]
+use std::sync::atomic::Ordering;
+#[cfg(not(feature = "parking_lot"))]
+use std::sync::PoisonError;
+use std::time::Duration;
+#[cfg(feature = "lock-metrics")]
+use std::{sync::atomic::AtomicU64, time::Instant};
+
+#[cfg(not(feature = "parking_lot"))]
+pub(crate) type MutexGuard<'a, T> = std::sync::MutexGuard<'a, T>;
+#[cfg(feature = "parking_lot")]
+pub(crate) type MutexGuard<'a, T> = parking_lot::MutexGuard<'a, T>;
+
+#[derive(Default)]
+pub(crate) struct Mutex<T> {
+    #[cfg(not(feature = "parking_lot"))]
+    inner: std::sync::Mutex<T>,
+    #[cfg(feature = "parking_lot")]
+    inner: parking_lot::Mutex<T>,
+    tickets: Option<Tickets>,
+    #[cfg(feature = "lock-metrics")]
+    wait_nanos: AtomicU64,
+}
+
+#[derive(Default)]
+struct Tickets {
+    gate: std::sync::Mutex<Gate>,
+    // Notified each time `serving` moves on.
+    turn: std::sync::Condvar,
+}
+
+#[derive(Default)]
+struct Gate {
+    next: usize,
+    serving: usize,
+}
+
+impl<T> Mutex<T> {
+    pub(crate) fn new(value: T) -> Self {
+        Mutex {
+            inner: value.into(),
+            tickets: None,
+            #[cfg(feature = "lock-metrics")]
+            wait_nanos: AtomicU64::new(0),
+        }
+    }
+
+    /// A mutex handing the lock out in the order `lock` was called.
+    pub(crate) fn fair(value: T) -> Self {
+        Mutex {
+            inner: value.into(),
+            tickets: Some(Tickets::default()),
+            #[cfg(feature = "lock-metrics")]
+            wait_nanos: AtomicU64::new(0),
+        }
+    }
+
+    #[cfg(not(feature = "lock-metrics"))]
+    pub(crate) fn lock(&self) -> MutexGuard<'_, T> {
+        self.lock_untimed()
+    }
+
+    #[cfg(feature = "lock-metrics")]
+    pub(crate) fn lock(&self) -> MutexGuard<'_, T> {
+        let start = Instant::now();
+        let guard = self.lock_untimed();
+        let waited = start.elapsed().as_nanos() as u64;
+        self.wait_nanos.fetch_add(waited, Ordering::Relaxed);
+        guard
+    }
+
+    /// Total time `lock` calls have spent acquiring the lock.
+    #[cfg(feature = "lock-metrics")]
+    pub(crate) fn wait_nanos(&self) -> u64 {
+        self.wait_nanos.load(Ordering::Relaxed)
+    }
+
+    fn lock_untimed(&self) -> MutexGuard<'_, T> {
+        let Some(tickets) = &self.tickets else {
+            return self.lock_inner();
+        };
+        // Nothing panics holding the gate, so it's never poisoned.
+        let mut gate = tickets.gate.lock().unwrap();
+        let ticket = gate.next;
+        gate.next += 1;
+        while gate.serving != ticket {
+            gate = tickets.turn.wait(gate).unwrap();
+        }
+        drop(gate);
+        let guard = self.lock_inner();
+        tickets.gate.lock().unwrap().serving += 1;
+        tickets.turn.notify_all();
+        guard
+    }
+
+    #[cfg(not(feature = "parking_lot"))]
+    pub(crate) fn get_mut(&mut self) -> &mut T {
+        self.inner.get_mut().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    #[cfg(feature = "parking_lot")]
+    pub(crate) fn get_mut(&mut self) -> &mut T {
+        self.inner.get_mut()
+    }
+
+    #[cfg(not(feature = "parking_lot"))]
+    fn lock_inner(&self) -> MutexGuard<'_, T> {
+        self.inner.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    #[cfg(feature = "parking_lot")]
+    fn lock_inner(&self) -> MutexGuard<'_, T> {
+        self.inner.lock()
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct Condvar {
+    #[cfg(not(feature = "parking_lot"))]
+    inner: std::sync::Condvar,
+    #[cfg(feature = "parking_lot")]
+    inner: parking_lot::Condvar,
+    #[cfg(feature = "lock-metrics")]
+    notifies: AtomicU64,
+}
+
+impl Condvar {
+    #[cfg(not(feature = "parking_lot"))]
+    pub(crate) fn wait<'a, T>(&self, guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        Self::check_can_block();
+        self.inner
+            .wait(guard)
+            .unwrap_or_else(PoisonError::into_inner)
+    }
+
+    #[cfg(feature = "parking_lot")]
+    pub(crate) fn wait<'a, T>(&self, mut guard: MutexGuard<'a, T>) -> MutexGuard<'a, T> {
+        Self::check_can_block();
+        self.inner.wait(&mut guard);
+        guard
+    }
+
+    /// Like `wait`, giving up after `timeout`; also reports whether it did.
+    #[cfg(not(feature = "parking_lot"))]
+    pub(crate) fn wait_timeout<'a, T>(
+        &self,
+        guard: MutexGuard<'a, T>,
+        timeout: Duration,
+    ) -> (MutexGuard<'a, T>, bool) {
+        Self::check_can_block();
+        let (guard, result) = self
+            .inner
+            .wait_timeout(guard, timeout)
+            .unwrap_or_else(PoisonError::into_inner);
+        (guard, result.timed_out())
+    }
+
+    #[cfg(feature = "parking_lot")]
+    pub(crate) fn wait_timeout<'a, T>(
+        &self,
+        mut guard: MutexGuard<'a, T>,
+        timeout: Duration,
+    ) -> (MutexGuard<'a, T>, bool) {
+        Self::check_can_block();
+        let timed_out = self.inner.wait_for(&mut guard, timeout).timed_out();
+        (guard, timed_out)
+    }
+
+    #[track_caller]
+    fn check_can_block() {
+        if cfg!(all(target_family = "wasm", not(target_feature = "atomics"))) {
+            panic!("can't block on single-threaded wasm; receive with `recv_async`");
+        }
+    }
+
+    pub(crate) fn notify_one(&self) {
+        #[cfg(feature = "lock-metrics")]
+        self.notifies.fetch_add(1, Ordering::Relaxed);
+        self.inner.notify_one();
+    }
+
+    pub(crate) fn notify_all(&self) {
+        #[cfg(feature = "lock-metrics")]
+        self.notifies.fetch_add(1, Ordering::Relaxed);
+        self.inner.notify_all();
+    }
+
+    /// `notify_one` and `notify_all` calls so far.
+    #[cfg(feature = "lock-metrics")]
+    pub(crate) fn notifies(&self) -> u64 {
+        self.notifies.load(Ordering::Relaxed)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::{sync::Arc, time::Duration};
+
+    use super::*;
+
+    // How many times the holder relocks ahead of a thread already waiting.
+    fn relocks_ahead_of_waiter(mutex: Mutex<Vec<&'static str>>) -> usize {
+        let mutex = Arc::new(mutex);
+        let guard = mutex.lock();
+        let waiter = {
+            let mutex = Arc::clone(&mutex);
+            std::thread::spawn(move || mutex.lock().push("waiter"))
+        };
+        // Let the waiter queue up behind the held lock.
+        std::thread::sleep(Duration::from_millis(50));
+        drop(guard);
+        for _ in 0..1000 {
+            mutex.lock().push("holder");
+        }
+        waiter.join().unwrap();
+        let order = mutex.lock();
+        order.iter().position(|&who| who == "waiter").unwrap()
+    }
+
+    #[test]
+    fn test_fair_stops_barging() {
+        assert_eq!(relocks_ahead_of_waiter(Mutex::fair(Vec::new())), 0);
+        // std's mutex lets a running thread take the lock straight back,
+        // starving the waiter; parking_lot's eventually hands it over.
+        if cfg!(not(feature = "parking_lot")) {
+            assert!(relocks_ahead_of_waiter(Mutex::new(Vec::new())) > 1);
+        }
+    }
+}
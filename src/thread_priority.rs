@@ -0,0 +1,34 @@
+//! Nudging the calling thread's scheduling priority, for
+//! `ChannelBuilder::backlog_priority_boost`. Only Linux, where a nice value
+//! can be set per thread, is supported; elsewhere nothing ever changes.
+//! Raising priority usually takes privileges (`CAP_SYS_NICE`), so callers
+//! must expect it to fail and carry on as they were.
+
+/// The calling thread's nice value: lower runs sooner.
+#[cfg(target_os = "linux")]
+pub(crate) fn current() -> Option<i32> {
+    // `-1` is a valid nice value, so only errno tells a failure apart.
+    // SAFETY: plain syscalls on the calling thread; errno is thread-local.
+    unsafe {
+        *libc::__errno_location() = 0;
+        let nice = libc::getpriority(libc::PRIO_PROCESS, libc::gettid() as libc::id_t);
+        (*libc::__errno_location() == 0).then_some(nice)
+    }
+}
+
+/// Set the calling thread's nice value, returning whether that worked.
+#[cfg(target_os = "linux")]
+pub(crate) fn set(nice: i32) -> bool {
+    // SAFETY: a plain syscall on the calling thread.
+    unsafe { libc::setpriority(libc::PRIO_PROCESS, libc::gettid() as libc::id_t, nice) == 0 }
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn current() -> Option<i32> {
+    None
+}
+
+#[cfg(not(target_os = "linux"))]
+pub(crate) fn set(_nice: i32) -> bool {
+    false
+}
@@ -0,0 +1,52 @@
+//! Counts allocations with a global allocator, which would count for every
+//! test in a binary; hence a binary of its own.
+
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    cell::Cell,
+};
+
+use pamada::ring::bounded_const;
+
+struct CountingAlloc;
+
+thread_local! {
+    static ALLOCATIONS: Cell<usize> = const { Cell::new(0) };
+}
+
+unsafe impl GlobalAlloc for CountingAlloc {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.with(|n| n.set(n.get() + 1));
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAlloc = CountingAlloc;
+
+#[test]
+fn test_fill_wrap_drain() {
+    let (tx, mut rx) = bounded_const::<u64, 4>();
+    let before = ALLOCATIONS.with(Cell::get);
+    for i in 0..4 {
+        tx.send(i).unwrap();
+    }
+    assert_eq!(rx.recv(), Some(0));
+    assert_eq!(rx.recv(), Some(1));
+    for i in 4..6 {
+        tx.send(i).unwrap();
+    }
+    let mut got = Vec::with_capacity(4);
+    for _ in 0..4 {
+        got.push(rx.recv().unwrap());
+    }
+    let after = ALLOCATIONS.with(Cell::get);
+    assert_eq!(got, vec![2, 3, 4, 5]);
+    assert_eq!(after - before, 1, "only the result Vec may allocate");
+    drop(tx);
+    assert_eq!(rx.recv(), None);
+}